@@ -3,7 +3,7 @@ use std::sync::{
     Arc,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use axum::{
     extract::{Query, State},
     http::{header, StatusCode},
@@ -11,19 +11,42 @@ use axum::{
     routing::{get, post},
     Form, Json, Router,
 };
+use chrono_tz::Tz;
 use opentelemetry::metrics::{Counter, MeterProvider};
+use opentelemetry::KeyValue;
 use opentelemetry_prometheus::exporter;
 use opentelemetry_sdk::metrics::SdkMeterProvider;
 use prometheus::{Encoder, Registry, TextEncoder};
 use serde::Deserialize;
 use std::net::SocketAddr;
-use tokio::sync::{oneshot, Mutex};
-use tokio::task::JoinHandle;
-use weewx_sinks::FsSink;
-use weex_core::{Sink, WeatherPacket};
-use weex_ingest::{InterceptorUdpDriver, StationDriver};
+use tokio::sync::{mpsc, Mutex};
+use weewx_config::AppConfig;
+use weewx_sinks::{FsSink, SinkFanout, SinkStats};
+use weex_archive::{
+    rebuild_daily_summaries, ArchiveSink, ArchiveStats, FsyncPolicy, IntervalAggregator,
+    LatePacket, Wal,
+};
+use weex_core::{
+    aggregate_packets_with, register_obs_type, AggregateType, ChannelSource, ObsType, Pipeline,
+    PipelineBuilder, QcCheck, QcLimits, QcOptions, QcStats, QualityControl, RainCounter,
+    RainCounterOptions, RollupOptions, Sink, StageStats, StdWxCalculate, ValidRange, WeatherPacket,
+    WxCalculateOptions,
+};
+use weex_db::{ConflictPolicy, DbClient};
+use weex_ingest::{DriverSource, InterceptorUdpDriver, StationDriver};
 
 const HISTORY_CAP: usize = 1000;
+const INGEST_QUEUE_CAP: usize = 1024;
+/// The archive's own queue, deep enough to ride out a slow database without
+/// holding up ingest
+const ARCHIVE_QUEUE_CAP: usize = 8192;
+/// The live view only needs the latest packets; it drops rather than waits
+const LIVE_QUEUE_CAP: usize = 64;
+
+type SinkStatsList = Arc<std::sync::RwLock<Vec<(String, Arc<SinkStats>)>>>;
+type StageStatsList = Arc<std::sync::RwLock<Vec<Arc<StageStats>>>>;
+type QcStatsSlot = Arc<std::sync::RwLock<Option<Arc<QcStats>>>>;
+type ArchiveStatsSlot = Arc<std::sync::RwLock<Option<Arc<ArchiveStats>>>>;
 
 pub struct AppState {
     ready: AtomicBool,
@@ -31,10 +54,48 @@ pub struct AppState {
     #[allow(dead_code)]
     provider: SdkMeterProvider,
     requests_total: Counter<u64>,
+    live: Arc<LiveData>,
+    ingest_tx: Mutex<Option<mpsc::Sender<WeatherPacket>>>,
+    pipeline: Mutex<Option<Pipeline>>,
+    stage_stats: StageStatsList,
+    sink_stats: SinkStatsList,
+    qc_stats: QcStatsSlot,
+    archive_stats: ArchiveStatsSlot,
+}
+
+/// Latest packet and recent history served by the API
+#[derive(Default)]
+struct LiveData {
     latest: Mutex<Option<WeatherPacket>>,
     history: Mutex<Vec<WeatherPacket>>,
 }
 
+impl LiveData {
+    async fn update(&self, packet: WeatherPacket) {
+        {
+            let mut latest = self.latest.lock().await;
+            *latest = Some(packet.clone());
+        }
+        let mut hist = self.history.lock().await;
+        hist.push(packet);
+        if hist.len() > HISTORY_CAP {
+            let overflow = hist.len() - HISTORY_CAP;
+            hist.drain(0..overflow);
+        }
+    }
+}
+
+/// Pipeline sink feeding `/api/v1/current` and `/api/v1/history`
+struct LiveSink(Arc<LiveData>);
+
+#[async_trait::async_trait]
+impl Sink for LiveSink {
+    async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+        self.0.update(packet.clone()).await;
+        Ok(())
+    }
+}
+
 pub fn build_app() -> (Router, Arc<AppState>) {
     // Prometheus exporter via OpenTelemetry
     let registry = Registry::new();
@@ -50,13 +111,138 @@ pub fn build_app() -> (Router, Arc<AppState>) {
         .with_description("Total HTTP requests served")
         .init();
 
+    // Per-sink counters, observed at scrape time
+    let sink_stats: SinkStatsList = Arc::default();
+    let observe = |read: fn(&SinkStats) -> u64| {
+        let stats = Arc::clone(&sink_stats);
+        move |observer: &dyn opentelemetry::metrics::AsyncInstrument<u64>| {
+            if let Ok(list) = stats.read() {
+                for (name, s) in list.iter() {
+                    observer.observe(read(s), &[KeyValue::new("sink", name.clone())]);
+                }
+            }
+        }
+    };
+    meter
+        .u64_observable_counter("weewx_sink_written_total")
+        .with_description("Packets written per sink")
+        .with_callback(observe(SinkStats::written))
+        .init();
+    meter
+        .u64_observable_counter("weewx_sink_errors_total")
+        .with_description("Sink emit errors per sink")
+        .with_callback(observe(SinkStats::errors))
+        .init();
+    meter
+        .u64_observable_counter("weewx_sink_dropped_total")
+        .with_description("Packets dropped per sink because its queue was full")
+        .with_callback(observe(SinkStats::dropped))
+        .init();
+    meter
+        .u64_observable_gauge("weewx_sink_backlog")
+        .with_description("Packets queued per sink")
+        .with_callback(observe(SinkStats::backlog))
+        .init();
+    meter
+        .u64_observable_gauge("weewx_sink_outbox_depth")
+        .with_description("Packets held in each sink's durable outbox")
+        .with_callback(observe(SinkStats::outbox_depth))
+        .init();
+
+    // Per-stage pipeline counters, observed at scrape time
+    let stage_stats: StageStatsList = Arc::default();
+    let observe_stage = |read: fn(&StageStats) -> u64| {
+        let stats = Arc::clone(&stage_stats);
+        move |observer: &dyn opentelemetry::metrics::AsyncInstrument<u64>| {
+            if let Ok(list) = stats.read() {
+                for s in list.iter() {
+                    let labels = [
+                        KeyValue::new("stage", s.name().to_string()),
+                        KeyValue::new("kind", s.kind().as_str()),
+                    ];
+                    observer.observe(read(s), &labels);
+                }
+            }
+        }
+    };
+    meter
+        .u64_observable_counter("weewx_pipeline_received_total")
+        .with_description("Packets received per pipeline stage")
+        .with_callback(observe_stage(StageStats::received))
+        .init();
+    meter
+        .u64_observable_counter("weewx_pipeline_emitted_total")
+        .with_description("Packets produced, passed on or written per pipeline stage")
+        .with_callback(observe_stage(StageStats::emitted))
+        .init();
+    meter
+        .u64_observable_counter("weewx_pipeline_errors_total")
+        .with_description("Errors per pipeline stage")
+        .with_callback(observe_stage(StageStats::errors))
+        .init();
+    meter
+        .u64_observable_counter("weewx_pipeline_dropped_total")
+        .with_description("Packets dropped by lossy pipeline sinks with a full queue")
+        .with_callback(observe_stage(StageStats::dropped))
+        .init();
+    meter
+        .u64_observable_gauge("weewx_pipeline_backlog")
+        .with_description("Packets queued in front of each pipeline stage")
+        .with_callback(observe_stage(StageStats::backlog))
+        .init();
+
+    // QC failures per check, observed at scrape time
+    let qc_stats: QcStatsSlot = Arc::default();
+    let qc_slot = Arc::clone(&qc_stats);
+    meter
+        .u64_observable_counter("weewx_qc_failures_total")
+        .with_description("Observation values rejected by quality control, per check")
+        .with_callback(move |observer| {
+            if let Ok(slot) = qc_slot.read() {
+                if let Some(stats) = slot.as_ref() {
+                    for check in QcCheck::ALL {
+                        observer.observe(
+                            stats.failures(check),
+                            &[KeyValue::new("check", check.as_str())],
+                        );
+                    }
+                }
+            }
+        })
+        .init();
+
+    // Late archive packets per outcome, observed at scrape time
+    let archive_stats: ArchiveStatsSlot = Arc::default();
+    let archive_slot = Arc::clone(&archive_stats);
+    meter
+        .u64_observable_counter("weewx_archive_late_packets_total")
+        .with_description("Packets for already archived intervals, per outcome")
+        .with_callback(move |observer| {
+            if let Ok(slot) = archive_slot.read() {
+                if let Some(stats) = slot.as_ref() {
+                    for outcome in LatePacket::ALL {
+                        observer.observe(
+                            stats.late(outcome),
+                            &[KeyValue::new("outcome", outcome.as_str())],
+                        );
+                    }
+                }
+            }
+        })
+        .init();
+
     let state = Arc::new(AppState {
         ready: AtomicBool::new(false),
         registry,
         provider,
         requests_total,
-        latest: Mutex::new(None),
-        history: Mutex::new(Vec::with_capacity(256)),
+        live: Arc::default(),
+        ingest_tx: Mutex::new(None),
+        pipeline: Mutex::new(None),
+        stage_stats,
+        sink_stats,
+        qc_stats,
+        archive_stats,
     });
 
     let router = Router::new()
@@ -65,6 +251,10 @@ pub fn build_app() -> (Router, Arc<AppState>) {
         .route("/metrics", get(metrics))
         .route("/api/v1/current", get(current))
         .route("/api/v1/history", get(history))
+        .route("/api/v1/aggregate", get(aggregate))
+        .route("/api/v1/sinks", get(sinks_status))
+        .route("/api/v1/pipeline", get(pipeline_status))
+        .route("/api/v1/qc", get(qc_status))
         .route("/ingest/ecowitt", get(ingest_ecowitt).post(ingest_post))
         .route("/data", post(ingest_post))
         .with_state(Arc::clone(&state));
@@ -72,67 +262,487 @@ pub fn build_app() -> (Router, Arc<AppState>) {
     (router, state)
 }
 
-pub async fn start_interceptor_ingest(
-    state: Arc<AppState>,
-    bind: SocketAddr,
-    fs_dir: Option<String>,
-) -> Result<(SocketAddr, JoinHandle<()>)> {
-    let (tx, rx) = oneshot::channel();
-    let handle = tokio::spawn(async move {
-        let mut driver = InterceptorUdpDriver::new(bind);
-        if let Err(e) = driver.start().await {
-            tracing::error!(error=?e, "failed to start interceptor driver");
-            let _ = tx.send(Err(e.into()));
-            return;
+/// Instantiate every sink configured in `cfg`, each with its own bounded queue.
+///
+/// Sinks whose feature is not compiled in, or that fail to initialize, are
+/// logged and skipped so one bad sink never prevents the server from starting.
+pub async fn build_sinks(cfg: &AppConfig) -> SinkFanout {
+    let mut fanout = SinkFanout::new();
+
+    if let Some(dir) = cfg.fs_dir() {
+        match FsSink::new(&dir) {
+            Ok(s) => fanout.add("fs", s, weewx_sinks::fanout::DEFAULT_QUEUE_CAP),
+            Err(e) => tracing::warn!(error=?e, "fs sink disabled"),
         }
-        // Report bound address
-        let _ = tx.send(Ok(bind));
-
-        let mut fs_sink = match fs_dir {
-            Some(dir) => match FsSink::new(dir) {
-                Ok(s) => Some(s),
-                Err(e) => {
-                    tracing::warn!(error=?e, "fs sink disabled");
-                    None
-                }
-            },
-            None => None,
-        };
+    }
 
-        loop {
-            match driver.get_packet().await {
-                Ok(pkt) => {
-                    inject_packet(&state, pkt.clone()).await;
-                    if let Some(sink) = fs_sink.as_mut() {
-                        let _ = sink.emit(&pkt).await;
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!(error=?e, "ingest error");
+    if let Some(path) = cfg.sqlite_path() {
+        #[cfg(feature = "sqlite")]
+        {
+            let opts = weewx_sinks::sqlite::SqliteSinkOptions {
+                commit_interval: std::time::Duration::from_millis(cfg.sqlite_commit_interval_ms()),
+                ..Default::default()
+            };
+            match weewx_sinks::sqlite::SqliteSink::with_options(&path, opts) {
+                Ok(s) => add_sink(&mut fanout, cfg, "sqlite", s),
+                Err(e) => tracing::warn!(error=?e, "sqlite sink disabled"),
+            }
+        }
+        #[cfg(not(feature = "sqlite"))]
+        tracing::warn!(%path, "sqlite sink configured but `sqlite` feature is not enabled");
+    }
+
+    if let Some(url) = cfg.postgres_url() {
+        #[cfg(feature = "postgres")]
+        match weewx_sinks::postgres::PostgresSink::new(&url).await {
+            Ok(s) => add_sink(&mut fanout, cfg, "postgres", s),
+            Err(e) => tracing::warn!(error=?e, "postgres sink disabled"),
+        }
+        #[cfg(not(feature = "postgres"))]
+        {
+            let _ = url;
+            tracing::warn!("postgres sink configured but `postgres` feature is not enabled");
+        }
+    }
+
+    if let Some(influx) = cfg.influx_config().filter(|i| i.url.is_some()) {
+        #[cfg(feature = "influx")]
+        match build_influx(influx) {
+            Ok(s) => add_sink(&mut fanout, cfg, "influx", s),
+            Err(e) => tracing::warn!(error=?e, "influx sink disabled"),
+        }
+        #[cfg(not(feature = "influx"))]
+        {
+            let _ = influx;
+            tracing::warn!("influx sink configured but `influx` feature is not enabled");
+        }
+    }
+
+    fanout
+}
+
+/// Register a networked/database sink, behind a durable on-disk outbox
+/// (`<outbox dir>/<name>`) when `[sinks.outbox]` is configured
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "influx"))]
+fn add_sink<S: weex_core::Sink + 'static>(
+    fanout: &mut SinkFanout,
+    cfg: &AppConfig,
+    name: &str,
+    sink: S,
+) {
+    let cap = weewx_sinks::fanout::DEFAULT_QUEUE_CAP;
+    let Some(dir) = cfg.outbox_dir() else {
+        fanout.add(name, sink, cap);
+        return;
+    };
+    let opts = weewx_sinks::OutboxOptions {
+        fsync: cfg.outbox_fsync(),
+        ..Default::default()
+    };
+    let dir = std::path::Path::new(&dir).join(name);
+    if let Err(e) = fanout.add_with_outbox(name, sink, &dir, opts, cap) {
+        tracing::warn!(sink = name, error=?e, "outbox unavailable; sink disabled");
+    }
+}
+
+#[cfg(feature = "influx")]
+fn build_influx(
+    influx: &weewx_config::InfluxSinkConfig,
+) -> Result<weewx_sinks::influx::InfluxSink> {
+    use weewx_sinks::influx::{InfluxSink, InfluxSinkOptions, InfluxTarget};
+
+    let url = influx.url.clone().unwrap_or_default();
+    let target = if influx.version == Some(1) {
+        InfluxTarget::V1 {
+            database: influx.database.clone().unwrap_or_default(),
+            retention_policy: influx.retention_policy.clone(),
+            username: influx.username.clone(),
+            password: influx.password.clone(),
+        }
+    } else {
+        InfluxTarget::V2 {
+            org: influx.org.clone().unwrap_or_default(),
+            bucket: influx.bucket.clone().unwrap_or_default(),
+            token: influx.token.clone().unwrap_or_default(),
+        }
+    };
+    let mut opts = InfluxSinkOptions::default();
+    if let Some(p) = influx.precision.as_deref() {
+        opts.precision = p.parse()?;
+    }
+    if let Some(n) = influx.batch_size {
+        opts.max_batch = n;
+    }
+    if let Some(ms) = influx.linger_ms {
+        opts.linger = std::time::Duration::from_millis(ms);
+    }
+    InfluxSink::with_options(url, target, opts)
+}
+
+/// Add the `[obs_types]` from `cfg` to the obs-type registry, overriding
+/// built-in types field by field
+pub fn register_obs_types(cfg: &AppConfig) -> Result<()> {
+    for (name, tc) in cfg.obs_types.iter().flatten() {
+        let mut obs_type = obs_registry()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| ObsType::new(name.as_str()));
+        if let Some(group) = tc.group.as_deref() {
+            obs_type.group = Some(group.parse()?);
+        }
+        if let Some(aggregate) = tc.aggregate.as_deref() {
+            obs_type.aggregate = aggregate.parse().map_err(anyhow::Error::msg)?;
+        }
+        if tc.min.is_some() || tc.max.is_some() || tc.unit.is_some() {
+            let mut range = obs_type.range.unwrap_or(ValidRange {
+                min: f64::NEG_INFINITY,
+                max: f64::INFINITY,
+                unit: None,
+            });
+            if let Some(unit) = tc.unit.as_deref() {
+                range.unit = Some(unit.parse()?);
+            }
+            range.min = tc.min.unwrap_or(range.min);
+            range.max = tc.max.unwrap_or(range.max);
+            obs_type.range = Some(range);
+        }
+        if let Some(column) = tc.column.as_ref() {
+            obs_type.column = Some(column.clone());
+        }
+        if let Some(aliases) = tc.aliases.as_ref() {
+            obs_type.aliases = aliases.clone();
+        }
+        register_obs_type(obs_type);
+    }
+    Ok(())
+}
+
+/// Quality-control settings from `[qc]`
+pub fn qc_options(cfg: &AppConfig) -> QcOptions {
+    let mut opts = QcOptions {
+        unit_system: cfg.unit_system(),
+        ..Default::default()
+    };
+    if let Some(qc) = cfg.qc.as_ref() {
+        opts.range_checks = qc.range_checks.unwrap_or(true);
+        for (obs, l) in qc.limits.iter().flatten() {
+            let limits = QcLimits {
+                spike: l.spike,
+                max_rate_per_hour: l.max_rate_per_hour,
+                flatline_secs: l.flatline_secs,
+            };
+            opts.limits.insert(obs.clone(), limits);
+        }
+    }
+    opts
+}
+
+/// Run `qc` as a pipeline stage and report its failures on `/api/v1/qc` and
+/// `/metrics`
+pub fn with_qc(
+    state: &Arc<AppState>,
+    pipeline: PipelineBuilder,
+    qc: QualityControl,
+) -> PipelineBuilder {
+    if let Ok(mut slot) = state.qc_stats.write() {
+        *slot = Some(qc.stats());
+    }
+    pipeline.processor("qc", qc)
+}
+
+/// Derived-observation settings from `[station]` and `[calculate]`
+pub fn wx_calculate_options(cfg: &AppConfig) -> Result<WxCalculateOptions> {
+    let mut opts = WxCalculateOptions {
+        altitude_m: cfg.station_altitude_m(),
+        unit_system: cfg.unit_system(),
+        ..Default::default()
+    };
+    if let Some(calc) = cfg.calculate.as_ref() {
+        if let Some(algorithm) = calc.altimeter_algorithm.as_deref() {
+            opts.altimeter_algorithm = algorithm.parse().map_err(anyhow::Error::msg)?;
+        }
+        for (field, policy) in calc.calculations.iter().flatten() {
+            let policy = policy.parse().map_err(anyhow::Error::msg)?;
+            opts.policies.insert(field.clone(), policy);
+        }
+    }
+    Ok(opts)
+}
+
+/// Assemble the pipeline described by `cfg`, stage by stage:
+///
+/// 1. the UDP interceptor source;
+/// 2. rain from counters (`[rain]`);
+/// 3. quality control (`[qc]`);
+/// 4. derived observations (`[calculate]`);
+/// 5. the WeeWX archive, if `[archive]` is set;
+/// 6. the configured output sinks (`[sinks]`).
+///
+/// Like [`build_sinks`], parts that fail to initialize are logged and skipped,
+/// except the archive: a configured archive database that cannot be opened or
+/// fails its schema check is an error, rather than running without archiving.
+pub async fn build_pipeline(state: &Arc<AppState>, cfg: &AppConfig) -> Result<PipelineBuilder> {
+    if let Err(e) = register_obs_types(cfg) {
+        tracing::error!(error=?e, "invalid [obs_types] config");
+    }
+
+    let mut pipeline = Pipeline::builder().capacity(cfg.pipeline_capacity());
+
+    match cfg.interceptor_bind().parse::<SocketAddr>() {
+        Ok(bind) => match interceptor_source(bind).await {
+            Ok((local, source)) => {
+                tracing::info!(%local, "INTERCEPTOR UDP ingest listening");
+                pipeline = pipeline.source("interceptor", source);
+            }
+            Err(e) => tracing::error!(error=?e, "failed to start UDP ingest"),
+        },
+        Err(e) => tracing::error!(error=?e, "invalid UDP bind address"),
+    }
+
+    // The archive database also keeps processor state across restarts
+    let archive_db = match cfg.archive_params() {
+        Some((db_url, _, unit_system)) => Some(
+            connect_archive_db(&db_url, unit_system)
+                .await
+                .context("failed to open archive database")?,
+        ),
+        None => None,
+    };
+
+    if cfg.rain_enabled() {
+        match rain_counter_options(cfg) {
+            Ok(opts) => {
+                let mut counter = RainCounter::new(opts);
+                if let Some(db) = archive_db.as_ref() {
+                    counter = counter.with_store(Arc::new(db.clone()));
                 }
+                pipeline = pipeline.processor("rain", counter);
             }
+            Err(e) => tracing::error!(error=?e, "invalid [rain] config"),
         }
-    });
-    let local = rx
-        .await
-        .unwrap_or_else(|_| Err(anyhow::anyhow!("driver start channel closed")))?;
-    Ok((local, handle))
+    }
+
+    if cfg.qc_enabled() {
+        pipeline = with_qc(state, pipeline, QualityControl::new(qc_options(cfg)));
+    }
+
+    if cfg.calculate_enabled() {
+        match wx_calculate_options(cfg) {
+            Ok(opts) => {
+                pipeline = pipeline.processor("calculate", StdWxCalculate::new(opts));
+            }
+            Err(e) => tracing::error!(error=?e, "invalid [calculate] config"),
+        }
+    }
+
+    if let (Some(db), Some((_, interval, unit_system))) = (archive_db, cfg.archive_params()) {
+        let sink = archive_sink(cfg, db, interval, unit_system)
+            .await
+            .context("invalid [archive] config")?;
+        tracing::info!(interval, unit_system, "archive aggregation enabled");
+        if let Ok(mut slot) = state.archive_stats.write() {
+            *slot = Some(sink.stats());
+        }
+        let capacity = cfg.pipeline_capacity().max(ARCHIVE_QUEUE_CAP);
+        pipeline = pipeline.sink_with_capacity("archive", sink, capacity);
+    }
+
+    let fanout = build_sinks(cfg).await;
+    if !fanout.is_empty() {
+        tracing::info!(sinks = fanout.len(), "sink fan-out started");
+        pipeline = with_output_sinks(state, pipeline, fanout);
+    }
+
+    Ok(pipeline)
+}
+
+/// Route packets to the configured output sinks and report their counters on
+/// `/api/v1/sinks` and `/metrics`
+pub fn with_output_sinks(
+    state: &Arc<AppState>,
+    pipeline: PipelineBuilder,
+    fanout: SinkFanout,
+) -> PipelineBuilder {
+    if let Ok(mut list) = state.sink_stats.write() {
+        *list = fanout.stats();
+    }
+    pipeline.sink("outputs", fanout)
+}
+
+/// Bind the INTERCEPTOR UDP driver; returns the bound address and the source
+pub async fn interceptor_source(bind: SocketAddr) -> Result<(SocketAddr, DriverSource)> {
+    let mut driver = InterceptorUdpDriver::new(bind);
+    driver.start().await?;
+    let local = driver.local_addr().unwrap_or(bind);
+    Ok((local, DriverSource::new(Box::new(driver))))
+}
+
+/// Connect to the WeeWX database and check its schema: the archive table
+/// (whose columns records are written to) must exist and the database must
+/// be in `unit_system`
+pub async fn connect_archive_db(database_url: &str, unit_system: i32) -> Result<DbClient> {
+    let db_client = DbClient::new(database_url).await?;
+    db_client.ping().await?;
+    db_client.check_schema(unit_system).await?;
+    Ok(db_client)
+}
+
+/// Build a sink that aggregates packets into archive records, closing each
+/// interval `archive_delay` seconds after it ends. The open interval is
+/// written when the pipeline shuts down. Archiving resumes after the newest
+/// record in the database; with a WAL configured, packets a crashed run left
+/// behind are restored first.
+pub async fn archive_sink(
+    cfg: &AppConfig,
+    db_client: DbClient,
+    interval: i32,
+    unit_system: i32,
+) -> Result<ArchiveSink> {
+    let rollup = rollup_options(cfg)?;
+    let tz = station_timezone(cfg)?;
+    let db_client = db_client.with_timezone(tz);
+    let mut aggregator = IntervalAggregator::new(interval, unit_system, db_client)
+        .time_weighted(rollup.time_weighted)
+        .aggregates(rollup.aggregates)
+        .timezone(tz)
+        .archive_delay(cfg.archive_delay())
+        .gap_markers(cfg.archive_gap_markers())
+        .late_grace(cfg.archive_late_grace())
+        .late_upsert(cfg.archive_late_upsert());
+    let archive = cfg.archive.as_ref();
+    if let Some(policy) = archive.and_then(|a| a.conflict_policy.as_ref()) {
+        let policy: ConflictPolicy = policy
+            .parse()
+            .map_err(|e| anyhow::anyhow!("[archive] conflict_policy: {}", e))?;
+        aggregator = aggregator.conflict_policy(policy);
+    }
+    // Intervals the archive already has are not written again
+    aggregator.resume().await?;
+    if let Some(path) = archive.and_then(|a| a.wal_path.as_ref()) {
+        let fsync: FsyncPolicy = match archive.and_then(|a| a.wal_fsync.as_ref()) {
+            Some(policy) => policy
+                .parse()
+                .map_err(|e| anyhow::anyhow!("[archive] wal_fsync: {}", e))?,
+            None => FsyncPolicy::default(),
+        };
+        aggregator = aggregator.wal(Wal::open(path, fsync)?);
+        aggregator.replay_wal().await?;
+    }
+    Ok(ArchiveSink::new(aggregator))
+}
+
+/// Recompute every `archive_day_*` summary from the archive table, like
+/// WeeWX's `wee_database --rebuild-daily`
+pub async fn rebuild_daily(cfg: &AppConfig) -> Result<()> {
+    let (db_url, _, unit_system) = cfg
+        .archive_params()
+        .ok_or_else(|| anyhow::anyhow!("no [archive] database configured"))?;
+    let db = connect_archive_db(&db_url, unit_system).await?;
+    let (records, rows) = rebuild_daily_summaries(&db, station_timezone(cfg)?).await?;
+    println!(
+        "Rebuilt daily summaries: {} archive records, {} summary rows",
+        records, rows
+    );
+    Ok(())
+}
+
+/// Create a WeeWX schema (`wview_extended`, with daily summaries) in the
+/// `[archive]` database, if it has no archive table yet. A SQLite database
+/// file is created if missing.
+pub async fn init_database(cfg: &AppConfig) -> Result<()> {
+    let (db_url, _, unit_system) = cfg
+        .archive_params()
+        .ok_or_else(|| anyhow::anyhow!("no [archive] database configured"))?;
+    let db = DbClient::create(&db_url).await?;
+    if db.init_schema(unit_system).await? {
+        println!("Created WeeWX schema (unit system {})", unit_system);
+    } else {
+        db.check_schema(unit_system).await?;
+        println!("Database already has a WeeWX schema; left unchanged");
+    }
+    Ok(())
+}
+
+/// The `[station]` timezone
+pub fn station_timezone(cfg: &AppConfig) -> Result<Tz> {
+    cfg.station_timezone()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("[station] timezone: {}", e))
+}
+
+/// Rain counter settings from `[rain]` and the station timezone
+pub fn rain_counter_options(cfg: &AppConfig) -> Result<RainCounterOptions> {
+    let mut opts = RainCounterOptions {
+        timezone: station_timezone(cfg)?,
+        ..Default::default()
+    };
+    if let Some(rain) = cfg.rain.as_ref() {
+        if let Some(counter) = rain.counter.as_ref() {
+            opts.counter = obs_registry().canonical(counter).to_string();
+        }
+        opts.tip_size = rain.tip_size.unwrap_or(opts.tip_size);
+        opts.wrap = rain.wrap;
+    }
+    Ok(opts)
+}
+
+/// Archive rollup settings from `[archive]` and `[archive.aggregates]`
+pub fn rollup_options(cfg: &AppConfig) -> Result<RollupOptions> {
+    let mut opts = RollupOptions {
+        time_weighted: cfg.archive_time_weighted(),
+        ..Default::default()
+    };
+    let aggregates = cfg.archive.as_ref().and_then(|a| a.aggregates.as_ref());
+    for (obs, aggregate) in aggregates.into_iter().flatten() {
+        let aggregate = aggregate
+            .parse()
+            .map_err(|e| anyhow::anyhow!("[archive.aggregates] {}: {}", obs, e))?;
+        opts.aggregates.insert(obs.clone(), aggregate);
+    }
+    Ok(opts)
+}
+
+/// Add HTTP ingest as a source and the live API view as a sink, then start the
+/// pipeline. From here on every injected packet flows through it.
+pub async fn start_pipeline(state: &Arc<AppState>, pipeline: PipelineBuilder) {
+    let (tx, http) = ChannelSource::new(INGEST_QUEUE_CAP);
+    let pipeline = pipeline
+        .source("http", http)
+        .lossy_sink("live", LiveSink(Arc::clone(&state.live)), LIVE_QUEUE_CAP)
+        .spawn();
+    if let Ok(mut list) = state.stage_stats.write() {
+        *list = pipeline.stats();
+    }
+    *state.pipeline.lock().await = Some(pipeline);
+    *state.ingest_tx.lock().await = Some(tx);
+}
+
+/// Graceful shutdown: stop reporting ready, then stop the pipeline so the
+/// buffered archive interval is written and every sink is flushed and closed
+pub async fn shutdown(state: &Arc<AppState>) {
+    set_ready(state, false);
+    state.ingest_tx.lock().await.take();
+    let pipeline = state.pipeline.lock().await.take();
+    if let Some(pipeline) = pipeline {
+        pipeline.shutdown().await;
+    }
 }
 
 pub fn set_ready(state: &Arc<AppState>, is_ready: bool) {
     state.ready.store(is_ready, Ordering::Relaxed);
 }
 
+/// Hand a packet to the pipeline, waiting for queue space (backpressure).
+/// Before [`start_pipeline`] only the live API view is updated.
 pub async fn inject_packet(state: &Arc<AppState>, packet: WeatherPacket) {
-    {
-        let mut latest = state.latest.lock().await;
-        *latest = Some(packet.clone());
-    }
-    let mut hist = state.history.lock().await;
-    hist.push(packet);
-    if hist.len() > HISTORY_CAP {
-        let overflow = hist.len() - HISTORY_CAP;
-        hist.drain(0..overflow);
+    let tx = state.ingest_tx.lock().await.clone();
+    match tx {
+        Some(tx) => {
+            if tx.send(packet).await.is_err() {
+                tracing::warn!("pipeline stopped; packet dropped");
+            }
+        }
+        None => state.live.update(packet).await,
     }
 }
 
@@ -170,7 +780,7 @@ async fn metrics(
 }
 
 async fn current(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    let latest = state.latest.lock().await;
+    let latest = state.live.latest.lock().await;
     if let Some(pkt) = latest.as_ref() {
         return (StatusCode::OK, Json(pkt)).into_response();
     }
@@ -187,99 +797,116 @@ async fn history(
     Query(q): Query<HistoryQuery>,
 ) -> impl IntoResponse {
     let limit = q.limit.unwrap_or(100).min(HISTORY_CAP);
-    let hist = state.history.lock().await;
+    let hist = state.live.history.lock().await;
     let start = hist.len().saturating_sub(limit);
     let slice = hist[start..].to_vec();
     (StatusCode::OK, Json(slice)).into_response()
 }
 
-use std::collections::HashMap;
-use weex_core::ObservationValue;
+#[derive(Deserialize)]
+struct AggregateQuery {
+    obs: String,
+    #[serde(rename = "type")]
+    aggregate: String,
+    start: Option<i64>,
+    end: Option<i64>,
+}
 
-async fn ingest_ecowitt(
+/// One aggregate (e.g. `type=stddev`, `type=p95`) of an observation over the
+/// recent history, optionally limited to `start..=end`
+async fn aggregate(
     State(state): State<Arc<AppState>>,
-    Query(q): Query<HashMap<String, String>>,
+    Query(q): Query<AggregateQuery>,
 ) -> impl IntoResponse {
-    state.requests_total.add(1, &[]);
-    // dateutc can be "now" or "YYYY-MM-DD HH:M:SS" (UTC)
-    let date_time = match q.get("dateutc").map(|s| s.as_str()) {
-        Some("now") | None => chrono::Utc::now().timestamp(),
-        Some(s) => chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
-            .ok()
-            .map(|naive| {
-                chrono::DateTime::<chrono::Utc>::from_naive_utc_and_offset(naive, chrono::Utc)
-                    .timestamp()
-            })
-            .unwrap_or_else(|| chrono::Utc::now().timestamp()),
+    let aggregate_type: AggregateType = match q.aggregate.parse() {
+        Ok(t) => t,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
     };
-
-    let station = q.get("stationtype").cloned();
-    let mut obs: HashMap<String, ObservationValue> = HashMap::new();
-
-    // Helpers
-    let parse_f64 = |k: &str| q.get(k).and_then(|v| v.parse::<f64>().ok());
-    let parse_i64 = |k: &str| q.get(k).and_then(|v| v.parse::<i64>().ok());
-
-    // Temperature: tempf (F) -> outTemp (C)
-    if let Some(tf) = parse_f64("tempf") {
-        let c = (tf - 32.0) * (5.0 / 9.0);
-        obs.insert("outTemp".into(), ObservationValue::Float(c));
-    }
-    // Humidity (% RH)
-    if let Some(h) = parse_i64("humidity") {
-        obs.insert("humidity".into(), ObservationValue::Integer(h));
-    }
-    // Barometer: baromin (inHg) -> hPa
-    if let Some(inhg) = parse_f64("baromin") {
-        let hpa = inhg * 33.8638866667;
-        obs.insert("barometer".into(), ObservationValue::Float(hpa));
-    }
-    // Wind: mph -> m/s
-    if let Some(mph) = parse_f64("windspeedmph") {
-        let mps = mph * 0.44704;
-        obs.insert("windSpeed".into(), ObservationValue::Float(mps));
-    }
-    if let Some(mph) = parse_f64("windgustmph") {
-        let mps = mph * 0.44704;
-        obs.insert("windGust".into(), ObservationValue::Float(mps));
-    }
-    if let Some(dir) = parse_i64("winddir") {
-        obs.insert("windDir".into(), ObservationValue::Integer(dir));
-    }
-    // Rain: inches -> mm
-    if let Some(rri) = parse_f64("rainin") {
-        obs.insert("rainRate".into(), ObservationValue::Float(rri * 25.4));
-    }
-    if let Some(dri) = parse_f64("dailyrainin") {
-        obs.insert("dailyRain".into(), ObservationValue::Float(dri * 25.4));
-    }
-    // Solar / UV
-    if let Some(sr) = parse_f64("solarradiation") {
-        obs.insert("radiation".into(), ObservationValue::Float(sr));
-    }
-    if let Some(uv) = parse_f64("uv") {
-        obs.insert("uv".into(), ObservationValue::Float(uv));
-    }
-
-    let packet = WeatherPacket {
-        date_time,
-        station,
-        interval: None,
-        observations: obs,
+    let obs = obs_registry().canonical(&q.obs).to_string();
+    let packets: Vec<WeatherPacket> = {
+        let hist = state.live.history.lock().await;
+        hist.iter()
+            .filter(|p| p.date_time >= q.start.unwrap_or(i64::MIN))
+            .filter(|p| p.date_time <= q.end.unwrap_or(i64::MAX))
+            .cloned()
+            .collect()
+    };
+    let opts = RollupOptions {
+        aggregates: HashMap::from([(obs.clone(), aggregate_type)]),
+        ..Default::default()
     };
+    let value = aggregate_packets_with(&packets, &opts)
+        .get(&obs)
+        .and_then(|(_, v)| *v);
+    let body = serde_json::json!({
+        "obs": obs,
+        "type": q.aggregate,
+        "start": q.start,
+        "end": q.end,
+        "value": value,
+    });
+    (StatusCode::OK, Json(body)).into_response()
+}
 
-    inject_packet(&state, packet).await;
+async fn sinks_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status: Vec<_> = match state.sink_stats.read() {
+        Ok(list) => list.iter().map(|(name, s)| s.status(name)).collect(),
+        Err(_) => Vec::new(),
+    };
+    (StatusCode::OK, Json(status)).into_response()
+}
 
-    // TODO: Optionally emit to sinks (Fs/Sqlite/Postgres/Influx) once shared sink wiring is added to AppState
+async fn pipeline_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let status: Vec<_> = match state.stage_stats.read() {
+        Ok(list) => list.iter().map(|s| s.status()).collect(),
+        Err(_) => Vec::new(),
+    };
+    (StatusCode::OK, Json(status)).into_response()
+}
 
-    (StatusCode::OK, Json(serde_json::json!({"status":"ok"}))).into_response()
+async fn qc_status(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let stats = state.qc_stats.read().ok().and_then(|s| s.clone());
+    let Some(stats) = stats else {
+        return StatusCode::NO_CONTENT.into_response();
+    };
+    let failures: serde_json::Map<_, _> = QcCheck::ALL
+        .iter()
+        .map(|c| (c.as_str().to_string(), stats.failures(*c).into()))
+        .collect();
+    let body = serde_json::json!({ "failures": failures, "recent": stats.recent() });
+    (StatusCode::OK, Json(body)).into_response()
 }
 
-async fn ingest_post(
-    State(state): State<Arc<AppState>>,
-    Form(q): Form<HashMap<String, String>>,
-) -> impl IntoResponse {
-    state.requests_total.add(1, &[]);
+use std::collections::HashMap;
+use weex_core::{convert_unit, obs_registry, unit_systems, ObservationValue, Unit};
+
+/// Unit system of packets produced by HTTP ingest
+const INGEST_UNIT_SYSTEM: i32 = unit_systems::METRICWX;
+
+/// Ecowitt / Weather Underground upload parameters: (parameter, observation
+/// type, unit the station sends it in). Names and target units come from the
+/// obs-type registry.
+const UPLOAD_FIELDS: &[(&str, &str, Unit)] = &[
+    ("tempf", "outTemp", Unit::DegreeF),
+    ("humidity", "outHumidity", Unit::Percent),
+    ("baromin", "barometer", Unit::InHg),
+    ("baromrelin", "barometer", Unit::InHg),
+    ("baromabsin", "pressure", Unit::InHg),
+    ("windspeedmph", "windSpeed", Unit::MilePerHour),
+    ("windgustmph", "windGust", Unit::MilePerHour),
+    ("winddir", "windDir", Unit::DegreeCompass),
+    ("rainin", "rainRate", Unit::InchPerHour),
+    ("dailyrainin", "dayRain", Unit::Inch),
+    ("weeklyrainin", "weekRain", Unit::Inch),
+    ("monthlyrainin", "monthRain", Unit::Inch),
+    ("yearlyrainin", "yearRain", Unit::Inch),
+    ("totalrainin", "totalRain", Unit::Inch),
+    ("solarradiation", "radiation", Unit::WattPerMeterSquared),
+    ("uv", "UV", Unit::UvIndex),
+];
+
+/// Build a METRICWX packet from upload parameters; unparseable values are skipped
+fn parse_upload(q: &HashMap<String, String>) -> WeatherPacket {
     // dateutc can be "now" or "YYYY-MM-DD HH:MM:SS" (UTC)
     let date_time = match q.get("dateutc").map(|s| s.as_str()) {
         Some("now") | None => chrono::Utc::now().timestamp(),
@@ -292,71 +919,44 @@ async fn ingest_post(
             .unwrap_or_else(|| chrono::Utc::now().timestamp()),
     };
 
-    let station = q.get("stationtype").cloned();
+    let registry = obs_registry();
     let mut obs: HashMap<String, ObservationValue> = HashMap::new();
-
-    // Helpers
-    let parse_f64 = |k: &str| q.get(k).and_then(|v| v.parse::<f64>().ok());
-    let parse_i64 = |k: &str| q.get(k).and_then(|v| v.parse::<i64>().ok());
-
-    // Temperature: tempf (F) -> outTemp (C)
-    if let Some(tf) = parse_f64("tempf") {
-        let c = (tf - 32.0) * (5.0 / 9.0);
-        obs.insert("outTemp".into(), ObservationValue::Float(c));
-    }
-    // Humidity (% RH)
-    if let Some(h) = parse_i64("humidity") {
-        obs.insert("humidity".into(), ObservationValue::Integer(h));
-    }
-    // Barometer: baromin (inHg) -> hPa
-    if let Some(inhg) = parse_f64("baromin") {
-        let hpa = inhg * 33.8638866667;
-        obs.insert("barometer".into(), ObservationValue::Float(hpa));
-    }
-    // Also handle baromabsin and baromrelin
-    if let Some(inhg) = parse_f64("baromabsin") {
-        let hpa = inhg * 33.8638866667;
-        obs.insert("barometerAbs".into(), ObservationValue::Float(hpa));
-    }
-    if let Some(inhg) = parse_f64("baromrelin") {
-        let hpa = inhg * 33.8638866667;
-        obs.insert("barometer".into(), ObservationValue::Float(hpa));
-    }
-    // Wind: mph -> m/s
-    if let Some(mph) = parse_f64("windspeedmph") {
-        let mps = mph * 0.44704;
-        obs.insert("windSpeed".into(), ObservationValue::Float(mps));
-    }
-    if let Some(mph) = parse_f64("windgustmph") {
-        let mps = mph * 0.44704;
-        obs.insert("windGust".into(), ObservationValue::Float(mps));
-    }
-    if let Some(dir) = parse_i64("winddir") {
-        obs.insert("windDir".into(), ObservationValue::Integer(dir));
-    }
-    // Rain: inches -> mm
-    if let Some(rri) = parse_f64("rainin") {
-        obs.insert("rainRate".into(), ObservationValue::Float(rri * 25.4));
-    }
-    if let Some(dri) = parse_f64("dailyrainin") {
-        obs.insert("dailyRain".into(), ObservationValue::Float(dri * 25.4));
-    }
-    // Solar / UV
-    if let Some(sr) = parse_f64("solarradiation") {
-        obs.insert("radiation".into(), ObservationValue::Float(sr));
-    }
-    if let Some(uv) = parse_f64("uv") {
-        obs.insert("uv".into(), ObservationValue::Float(uv));
+    for &(param, obs_type, unit) in UPLOAD_FIELDS {
+        let Some(value) = q.get(param).and_then(|v| v.parse::<f64>().ok()) else {
+            continue;
+        };
+        let name = registry.canonical(obs_type);
+        let target = registry
+            .unit_group(name)
+            .and_then(|g| g.unit_for(INGEST_UNIT_SYSTEM).ok())
+            .unwrap_or(unit);
+        if let Ok(value) = convert_unit(value, unit, target) {
+            obs.insert(name.to_string(), ObservationValue::Float(value));
+        }
     }
 
-    let packet = WeatherPacket {
+    WeatherPacket {
         date_time,
-        station,
+        station: q.get("stationtype").cloned(),
         interval: None,
         observations: obs,
-    };
+    }
+}
 
-    inject_packet(&state, packet).await;
+async fn ingest_ecowitt(
+    State(state): State<Arc<AppState>>,
+    Query(q): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    state.requests_total.add(1, &[]);
+    inject_packet(&state, parse_upload(&q)).await;
+    (StatusCode::OK, Json(serde_json::json!({"status":"ok"}))).into_response()
+}
 
+async fn ingest_post(
+    State(state): State<Arc<AppState>>,
+    Form(q): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    state.requests_total.add(1, &[]);
+    inject_packet(&state, parse_upload(&q)).await;
     (StatusCode::OK, Json(serde_json::json!({"status":"ok"}))).into_response()
 }
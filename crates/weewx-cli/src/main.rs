@@ -8,19 +8,40 @@ async fn main() {
 
     // Config
     let cfg = weewx_config::AppConfig::load().unwrap_or_default();
+
+    // One-off maintenance commands
+    match std::env::args().nth(1).as_deref() {
+        Some("rebuild-daily") => {
+            if let Err(e) = weewx_cli::rebuild_daily(&cfg).await {
+                eprintln!("rebuild-daily failed: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some("init") => {
+            if let Err(e) = weewx_cli::init_database(&cfg).await {
+                eprintln!("init failed: {:#}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => {}
+    }
+
     let http_bind = cfg.http_bind();
-    let udp_bind = cfg.interceptor_bind();
-    let fs_dir = cfg.fs_dir();
 
     // Build app and state
     let (app, state) = weewx_cli::build_app();
 
-    // Start UDP ingest in background
-    let udp_addr: SocketAddr = udp_bind.parse().expect("Invalid UDP bind address");
-    match weewx_cli::start_interceptor_ingest(state.clone(), udp_addr, fs_dir).await {
-        Ok((local, _handle)) => tracing::info!(%local, "INTERCEPTOR UDP ingest listening"),
-        Err(e) => tracing::error!(error=?e, "failed to start UDP ingest"),
-    }
+    // Sources, processors and sinks from config, connected by bounded queues
+    let pipeline = match weewx_cli::build_pipeline(&state, &cfg).await {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            eprintln!("startup failed: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    weewx_cli::start_pipeline(&state, pipeline).await;
 
     // Start HTTP server
     let addr: SocketAddr = http_bind.parse().expect("Invalid HTTP bind address");
@@ -32,5 +53,39 @@ async fn main() {
     weewx_cli::set_ready(&state, true);
 
     tracing::info!(%addr, "HTTP server listening");
-    axum::serve(listener, app).await.expect("server error");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            shutdown_signal().await;
+            tracing::info!("shutdown requested");
+        })
+        .await
+        .expect("server error");
+
+    // Drain the pipeline: archive the open interval and flush/close sinks
+    weewx_cli::shutdown(&state).await;
+}
+
+/// Resolves on Ctrl-C, or on SIGTERM from a service manager or container
+/// runtime
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
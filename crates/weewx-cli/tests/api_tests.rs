@@ -65,3 +65,49 @@ async fn current_and_history_endpoints() {
     let text = String::from_utf8(body.to_vec()).unwrap();
     assert!(text.starts_with("["));
 }
+
+#[tokio::test]
+async fn aggregate_endpoint() {
+    let (app, state) = weewx_cli::build_app();
+
+    for (i, temp) in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter().enumerate() {
+        let mut obs = std::collections::HashMap::new();
+        obs.insert("outTemp".to_string(), ObservationValue::Float(*temp));
+        let pkt = WeatherPacket {
+            date_time: 1_700_000_000 + i as i64 * 60,
+            station: None,
+            interval: None,
+            observations: obs,
+        };
+        weewx_cli::inject_packet(&state, pkt).await;
+    }
+
+    let get = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let res = app
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            let status = res.status();
+            let body = to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+            (
+                status,
+                serde_json::from_slice::<serde_json::Value>(&body).ok(),
+            )
+        }
+    };
+
+    let (status, body) = get("/api/v1/aggregate?obs=outTemp&type=stddev").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.unwrap()["value"], 2.0);
+
+    let (_, body) = get("/api/v1/aggregate?obs=outTemp&type=median&start=1700000240").await;
+    assert_eq!(body.unwrap()["value"], 6.0);
+
+    let (_, body) = get("/api/v1/aggregate?obs=outTemp&type=maxtime").await;
+    assert_eq!(body.unwrap()["value"], 1_700_000_420.0);
+
+    let (status, _) = get("/api/v1/aggregate?obs=outTemp&type=bogus").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -8,7 +8,7 @@ use tower::ServiceExt;
 async fn ecowitt_upload_populates_api() {
     let (app, _state) = weewx_cli::build_app();
     // Simulate Ecowitt GET upload
-    let uri = "/ingest/ecowitt?PASSKEY=ABC&stationtype=GW1100&dateutc=now&tempf=72.5&baromin=29.92&humidity=55&windspeedmph=5.0&windgustmph=7.0&winddir=180";
+    let uri = "/ingest/ecowitt?PASSKEY=ABC&stationtype=GW1100&dateutc=now&tempf=72.5&baromin=29.92&humidity=55&windspeedmph=5.0&windgustmph=7.0&winddir=180&uv=3";
     let res = app
         .clone()
         .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
@@ -33,4 +33,8 @@ async fn ecowitt_upload_populates_api() {
     assert!(text.contains("outTemp"));
     assert!(text.contains("barometer"));
     assert!(text.contains("windSpeed"));
+    // Upload names are mapped to registry names
+    assert!(text.contains("\"outHumidity\""));
+    assert!(text.contains("\"UV\""));
+    assert!(!text.contains("\"uv\""));
 }
@@ -12,9 +12,9 @@ async fn udp_packet_populates_api() {
     let (app, state) = weewx_cli::build_app();
     // Bind to ephemeral port
     let bind: SocketAddr = "127.0.0.1:0".parse().unwrap();
-    let (local, _handle) = weewx_cli::start_interceptor_ingest(state.clone(), bind, None)
-        .await
-        .unwrap();
+    let (local, source) = weewx_cli::interceptor_source(bind).await.unwrap();
+    let pipeline = weex_core::Pipeline::builder().source("interceptor", source);
+    weewx_cli::start_pipeline(&state, pipeline).await;
 
     // Send a JSON WeatherPacket over UDP
     let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
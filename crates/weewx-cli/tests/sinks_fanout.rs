@@ -0,0 +1,100 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+};
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn post_ingest_feeds_configured_fs_sink() {
+    let dir = tempfile::tempdir().unwrap();
+    let cfg: weewx_config::AppConfig = toml::from_str(&format!(
+        "[sinks.fs]\ndir = {:?}\n",
+        dir.path().to_str().unwrap()
+    ))
+    .unwrap();
+
+    let (app, state) = weewx_cli::build_app();
+    let fanout = weewx_cli::build_sinks(&cfg).await;
+    assert_eq!(fanout.len(), 1);
+    let pipeline = weewx_cli::with_output_sinks(&state, weex_core::Pipeline::builder(), fanout);
+    weewx_cli::start_pipeline(&state, pipeline).await;
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/data")
+                .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(Body::from("stationtype=GW1100&dateutc=now&tempf=72.0"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Per-sink status is reported
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/sinks")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("\"name\":\"fs\""));
+
+    // So is every pipeline stage
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/pipeline")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("\"name\":\"http\",\"kind\":\"source\""));
+    assert!(text.contains("\"name\":\"outputs\",\"kind\":\"sink\""));
+
+    // Graceful shutdown drains, flushes and closes the sinks
+    weewx_cli::shutdown(&state).await;
+    let content = std::fs::read_to_string(dir.path().join("packets.jsonl")).unwrap();
+    assert!(content.contains("outTemp"));
+
+    // The live view is a pipeline sink too
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/current")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = to_bytes(res.into_body(), 1024 * 1024).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("weewx_sink_written_total"));
+    assert!(text.contains("weewx_pipeline_emitted_total"));
+}
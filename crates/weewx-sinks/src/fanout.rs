@@ -0,0 +1,439 @@
+//! Sink fan-out runtime
+//!
+//! Every registered sink gets its own bounded queue and task, so a slow or
+//! failing sink (e.g. Influx over a flaky link) never stalls ingest or the
+//! other sinks. When a queue is full the packet is dropped for that sink only
+//! and counted. Whatever has queued up while a sink was busy is handed over in
+//! one `emit_batch` call. Sinks that ask for a `tick` (e.g. an outbox waiting
+//! out a backoff) get one even while no packets arrive. On shutdown each sink
+//! is flushed and closed.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use weex_core::{sleep_until, Sink, WeatherPacket, TICK_RETRY_SECS};
+
+use crate::outbox::{Outbox, OutboxOptions};
+
+/// Default per-sink queue capacity (packets)
+pub const DEFAULT_QUEUE_CAP: usize = 1024;
+
+/// Maximum packets taken off a queue per `emit_batch` call
+const LANE_BATCH: usize = 256;
+
+/// Live counters for a single sink lane
+#[derive(Debug, Default)]
+pub struct SinkStats {
+    written: AtomicU64,
+    errors: AtomicU64,
+    dropped: AtomicU64,
+    backlog: AtomicU64,
+    outbox_depth: Option<Arc<AtomicU64>>,
+}
+
+impl SinkStats {
+    /// Packets successfully emitted
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Emit calls that returned an error
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Packets dropped because the queue was full or closed
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Packets queued but not yet emitted
+    pub fn backlog(&self) -> u64 {
+        self.backlog.load(Ordering::Relaxed)
+    }
+
+    /// Packets held in the sink's durable outbox (0 without an outbox)
+    pub fn outbox_depth(&self) -> u64 {
+        self.outbox_depth
+            .as_ref()
+            .map_or(0, |d| d.load(Ordering::Relaxed))
+    }
+
+    /// Snapshot of these counters under `name`
+    pub fn status(&self, name: &str) -> SinkStatus {
+        SinkStatus {
+            name: name.to_string(),
+            written: self.written(),
+            errors: self.errors(),
+            dropped: self.dropped(),
+            backlog: self.backlog(),
+            outbox_depth: self.outbox_depth(),
+        }
+    }
+}
+
+/// Point-in-time snapshot of a sink lane
+#[derive(Debug, Clone, Serialize)]
+pub struct SinkStatus {
+    pub name: String,
+    pub written: u64,
+    pub errors: u64,
+    pub dropped: u64,
+    pub backlog: u64,
+    pub outbox_depth: u64,
+}
+
+struct SinkLane {
+    name: String,
+    tx: mpsc::Sender<WeatherPacket>,
+    stats: Arc<SinkStats>,
+    task: JoinHandle<()>,
+}
+
+/// Dispatches packets to N sinks, each behind its own bounded queue
+#[derive(Default)]
+pub struct SinkFanout {
+    lanes: Vec<SinkLane>,
+}
+
+impl SinkFanout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a sink with its own queue of `capacity` packets and spawn its task
+    pub fn add<S>(&mut self, name: impl Into<String>, sink: S, capacity: usize)
+    where
+        S: Sink + 'static,
+    {
+        self.add_lane(name.into(), sink, capacity, SinkStats::default());
+    }
+
+    /// Register a sink behind a durable on-disk outbox in `dir`, so packets
+    /// survive target outages and restarts
+    pub fn add_with_outbox<S>(
+        &mut self,
+        name: impl Into<String>,
+        sink: S,
+        dir: impl AsRef<std::path::Path>,
+        opts: OutboxOptions,
+        capacity: usize,
+    ) -> anyhow::Result<()>
+    where
+        S: Sink + 'static,
+    {
+        let outbox = Outbox::open(dir, sink, opts)?;
+        let stats = SinkStats {
+            outbox_depth: Some(outbox.depth_handle()),
+            ..Default::default()
+        };
+        self.add_lane(name.into(), outbox, capacity, stats);
+        Ok(())
+    }
+
+    fn add_lane<S>(&mut self, name: String, mut sink: S, capacity: usize, stats: SinkStats)
+    where
+        S: Sink + 'static,
+    {
+        let (tx, mut rx) = mpsc::channel::<WeatherPacket>(capacity.max(1));
+        let stats = Arc::new(stats);
+        let task_stats = Arc::clone(&stats);
+        let task_name = name.clone();
+        let task = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(LANE_BATCH);
+            let mut retry_at = i64::MIN;
+            loop {
+                let deadline = sink.next_tick().map(|t| t.max(retry_at));
+                let received = tokio::select! {
+                    biased;
+                    n = rx.recv_many(&mut batch, LANE_BATCH) => n,
+                    now = sleep_until(deadline) => {
+                        if let Err(e) = sink.tick(now).await {
+                            task_stats.errors.fetch_add(1, Ordering::Relaxed);
+                            tracing::warn!(sink = %task_name, error = ?e, "sink tick failed");
+                            retry_at = now + TICK_RETRY_SECS;
+                        }
+                        continue;
+                    }
+                };
+                if received == 0 {
+                    break;
+                }
+                let n = batch.len() as u64;
+                task_stats.backlog.fetch_sub(n, Ordering::Relaxed);
+                match sink.emit_batch(&batch).await {
+                    Ok(()) => {
+                        task_stats.written.fetch_add(n, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        task_stats.errors.fetch_add(n, Ordering::Relaxed);
+                        tracing::warn!(sink = %task_name, error = ?e, "sink emit failed");
+                    }
+                }
+                batch.clear();
+            }
+            // `close` flushes first
+            if let Err(e) = sink.close().await {
+                task_stats.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(sink = %task_name, error = ?e, "sink close failed");
+            }
+        });
+        self.lanes.push(SinkLane {
+            name,
+            tx,
+            stats,
+            task,
+        });
+    }
+
+    /// Queue a packet on every sink without waiting
+    pub fn dispatch(&self, packet: &WeatherPacket) {
+        for lane in &self.lanes {
+            lane.stats.backlog.fetch_add(1, Ordering::Relaxed);
+            match lane.tx.try_send(packet.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                    lane.stats.backlog.fetch_sub(1, Ordering::Relaxed);
+                    let dropped = lane.stats.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                    tracing::warn!(sink = %lane.name, dropped, "sink queue full; packet dropped");
+                }
+            }
+        }
+    }
+
+    /// Names and shared counters of all registered sinks
+    pub fn stats(&self) -> Vec<(String, Arc<SinkStats>)> {
+        self.lanes
+            .iter()
+            .map(|l| (l.name.clone(), Arc::clone(&l.stats)))
+            .collect()
+    }
+
+    /// Snapshot of every sink's counters
+    pub fn status(&self) -> Vec<SinkStatus> {
+        self.lanes.iter().map(|l| l.stats.status(&l.name)).collect()
+    }
+
+    /// Number of registered sinks
+    pub fn len(&self) -> usize {
+        self.lanes.len()
+    }
+
+    /// Check if no sinks are registered
+    pub fn is_empty(&self) -> bool {
+        self.lanes.is_empty()
+    }
+
+    /// Close all queues and wait for each sink to drain its backlog, flush
+    /// and close
+    pub async fn shutdown(self) {
+        let (senders, tasks): (Vec<_>, Vec<_>) = self
+            .lanes
+            .into_iter()
+            .map(|l| (l.tx, (l.name, l.task)))
+            .unzip();
+        drop(senders);
+        for (name, task) in tasks {
+            if let Err(e) = task.await {
+                tracing::warn!(sink = %name, error = ?e, "sink task panicked");
+            }
+        }
+    }
+}
+
+/// A fan-out is itself a sink, so it can sit at the end of a pipeline: each
+/// packet is queued on every lane without waiting, and closing shuts the lanes
+/// down
+#[async_trait::async_trait]
+impl Sink for SinkFanout {
+    async fn emit(&mut self, packet: &WeatherPacket) -> anyhow::Result<()> {
+        self.dispatch(packet);
+        Ok(())
+    }
+
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> anyhow::Result<()> {
+        for packet in packets {
+            self.dispatch(packet);
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> anyhow::Result<()> {
+        std::mem::take(self).shutdown().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Result};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    struct CountingSink(Arc<AtomicU64>);
+
+    #[async_trait::async_trait]
+    impl Sink for CountingSink {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    /// Records lifecycle calls
+    #[derive(Clone, Default)]
+    struct LifecycleSink(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[async_trait::async_trait]
+    impl Sink for LifecycleSink {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            self.0.lock().unwrap().push("emit".into());
+            Ok(())
+        }
+
+        async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("batch:{}", packets.len()));
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            self.0.lock().unwrap().push("flush".into());
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<()> {
+            self.0.lock().unwrap().push("close".into());
+            Ok(())
+        }
+    }
+
+    struct SlowFailingSink;
+
+    #[async_trait::async_trait]
+    impl Sink for SlowFailingSink {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Err(anyhow!("unavailable"))
+        }
+    }
+
+    fn packet(ts: i64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_sink_does_not_block_others() {
+        let count = Arc::new(AtomicU64::new(0));
+        let mut fanout = SinkFanout::new();
+        fanout.add("fast", CountingSink(Arc::clone(&count)), 16);
+        fanout.add("slow", SlowFailingSink, 2);
+
+        for ts in 0..10 {
+            fanout.dispatch(&packet(ts));
+        }
+
+        let status = fanout.status();
+        let slow = status.iter().find(|s| s.name == "slow").unwrap();
+        assert!(slow.dropped >= 7);
+
+        fanout.shutdown().await;
+        assert_eq!(count.load(Ordering::Relaxed), 10);
+    }
+
+    #[tokio::test]
+    async fn counts_errors_per_sink() {
+        let mut fanout = SinkFanout::new();
+        fanout.add("slow", SlowFailingSink, 4);
+        fanout.dispatch(&packet(1));
+        fanout.dispatch(&packet(2));
+        let stats = fanout.stats();
+        fanout.shutdown().await;
+
+        let (_, slow) = &stats[0];
+        assert_eq!(slow.errors(), 2);
+        assert_eq!(slow.written(), 0);
+        assert_eq!(slow.backlog(), 0);
+    }
+
+    #[tokio::test]
+    async fn batches_backlog_then_closes() {
+        let sink = LifecycleSink::default();
+        let mut fanout = SinkFanout::new();
+        fanout.add("rec", sink.clone(), 16);
+        // Queued before the lane task gets to run, so they arrive as one batch
+        for ts in 0..5 {
+            fanout.dispatch(&packet(ts));
+        }
+        let stats = fanout.stats();
+        fanout.shutdown().await;
+
+        assert_eq!(*sink.0.lock().unwrap(), vec!["batch:5", "close"]);
+        assert_eq!(stats[0].1.written(), 5);
+    }
+
+    /// Fails while `down` is set
+    #[derive(Clone, Default)]
+    struct FlakySink {
+        down: Arc<std::sync::Mutex<bool>>,
+        count: Arc<AtomicU64>,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for FlakySink {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            if *self.down.lock().unwrap() {
+                return Err(anyhow!("connection refused"));
+            }
+            self.count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn outbox_lane_replays_without_new_packets() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        *sink.down.lock().unwrap() = true;
+        let opts = OutboxOptions {
+            initial_backoff: Duration::ZERO,
+            fsync: false,
+            ..Default::default()
+        };
+        let mut fanout = SinkFanout::new();
+        fanout
+            .add_with_outbox("flaky", sink.clone(), dir.path(), opts, 16)
+            .unwrap();
+        fanout.dispatch(&packet(1));
+        let stats = fanout.stats();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while stats[0].1.outbox_depth() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("packet not queued");
+
+        // The target recovers; the lane's tick replays the outbox
+        *sink.down.lock().unwrap() = false;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.count.load(Ordering::Relaxed) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("outbox was not replayed");
+        assert_eq!(stats[0].1.outbox_depth(), 0);
+        fanout.shutdown().await;
+    }
+}
@@ -1,78 +1,670 @@
+//! InfluxDB line-protocol sink (v2 `/api/v2/write` and v1 `/write`)
+//!
+//! `emit` encodes the packet and queues the line; a background writer task
+//! batches lines (up to `max_batch`, or `linger` after the first queued line)
+//! and retries 429/5xx responses with exponential backoff. Lines still queued
+//! when the sink is closed or dropped are sent before the writer exits.
+
 use anyhow::{anyhow, Result};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
+use std::fmt::Write as _;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
 use weex_core::{ObservationValue, Sink, WeatherPacket};
 
+/// Timestamp precision sent with each write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    #[default]
+    Seconds,
+}
+
+impl Precision {
+    /// Query parameter value (`precision=...`)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Nanoseconds => "ns",
+            Precision::Microseconds => "us",
+            Precision::Milliseconds => "ms",
+            Precision::Seconds => "s",
+        }
+    }
+
+    /// Scale a Unix-seconds timestamp to this precision
+    fn scale(&self, seconds: i64) -> i64 {
+        match self {
+            Precision::Nanoseconds => seconds * 1_000_000_000,
+            Precision::Microseconds => seconds * 1_000_000,
+            Precision::Milliseconds => seconds * 1_000,
+            Precision::Seconds => seconds,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ns" => Ok(Precision::Nanoseconds),
+            "us" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            other => Err(anyhow!("invalid influx precision: {}", other)),
+        }
+    }
+}
+
+/// Write endpoint and credentials
+#[derive(Debug, Clone)]
+pub enum InfluxTarget {
+    /// InfluxDB 2.x: `/api/v2/write?org=..&bucket=..`, token auth
+    V2 {
+        org: String,
+        bucket: String,
+        token: String,
+    },
+    /// InfluxDB 1.x: `/write?db=..[&rp=..]`, optional basic auth
+    V1 {
+        database: String,
+        retention_policy: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+/// Encoding, batching and retry tuning
+#[derive(Debug, Clone)]
+pub struct InfluxSinkOptions {
+    pub measurement: String,
+    pub precision: Precision,
+    /// Send as soon as this many lines are queued
+    pub max_batch: usize,
+    /// Maximum time a queued line waits before its batch is sent
+    pub linger: Duration,
+    /// Retries for 429/5xx and transport errors before a batch is dropped
+    pub max_retries: u32,
+    /// Initial retry delay; doubled on each attempt
+    pub retry_backoff: Duration,
+}
+
+impl Default for InfluxSinkOptions {
+    fn default() -> Self {
+        Self {
+            measurement: "weather".to_string(),
+            precision: Precision::Seconds,
+            max_batch: 500,
+            linger: Duration::from_secs(1),
+            max_retries: 5,
+            retry_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+enum Op {
+    Line(String),
+    Flush(oneshot::Sender<Result<()>>),
+}
+
 pub struct InfluxSink {
-    client: Client,
-    base_url: String,
-    org: String,
-    bucket: String,
-    token: String,
+    measurement: String,
+    precision: Precision,
+    tx: Option<mpsc::Sender<Op>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
 }
 
 impl InfluxSink {
+    /// InfluxDB 2.x sink with default options
     pub fn new(base_url: String, org: String, bucket: String, token: String) -> Result<Self> {
-        if base_url.is_empty() || org.is_empty() || bucket.is_empty() || token.is_empty() {
+        if org.is_empty() || bucket.is_empty() || token.is_empty() {
             return Err(anyhow!("invalid influx configuration"));
         }
-        let client = Client::builder().build()?;
-        Ok(Self {
-            client,
+        Self::with_options(
             base_url,
-            org,
-            bucket,
-            token,
-        })
+            InfluxTarget::V2 { org, bucket, token },
+            InfluxSinkOptions::default(),
+        )
     }
 
-    fn to_line_protocol(&self, packet: &WeatherPacket) -> String {
-        // measurement name "weather"
-        let mut tags: Vec<String> = Vec::new();
-        if let Some(station) = &packet.station {
-            tags.push(format!("station={}", station.replace(' ', "\\ "))); // basic escaping
+    /// Sink for any target; spawns the batching writer on the current runtime
+    pub fn with_options(
+        base_url: String,
+        target: InfluxTarget,
+        opts: InfluxSinkOptions,
+    ) -> Result<Self> {
+        if base_url.is_empty() || opts.measurement.is_empty() {
+            return Err(anyhow!("invalid influx configuration"));
         }
-        let mut fields: Vec<String> = Vec::new();
-        for (k, v) in &packet.observations {
-            match v {
-                ObservationValue::Float(f) => fields.push(format!("{}={}", k, f)),
-                ObservationValue::Integer(i) => fields.push(format!("{}={}i", k, i)),
-                _ => {}
+        if let InfluxTarget::V1 { database, .. } = &target {
+            if database.is_empty() {
+                return Err(anyhow!("invalid influx configuration"));
             }
         }
-        if let Some(iv) = packet.interval {
-            fields.push(format!("interval={}i", iv));
-        }
-        let tags_str = if tags.is_empty() {
-            String::new()
-        } else {
-            format!(",{}", tags.join(","))
+        let writer = Writer {
+            client: Client::builder().build()?,
+            url: write_url(&base_url, &target, opts.precision)?,
+            target,
+            max_retries: opts.max_retries,
+            retry_backoff: opts.retry_backoff,
         };
-        let fields_str = fields.join(",");
-        format!("weather{} {} {}", tags_str, fields_str, packet.date_time)
+
+        let (tx, rx) = mpsc::channel(opts.max_batch.max(1) * 2);
+        let last_error = Arc::new(Mutex::new(None));
+        let task = tokio::spawn(run_writer(
+            writer,
+            rx,
+            opts.max_batch.max(1),
+            opts.linger,
+            Arc::clone(&last_error),
+        ));
+
+        Ok(Self {
+            measurement: opts.measurement,
+            precision: opts.precision,
+            tx: Some(tx),
+            task: Some(task),
+            last_error,
+        })
+    }
+
+    /// Encode a packet as one line of InfluxDB line protocol.
+    ///
+    /// Returns `None` when the packet has no encodable fields, since a line
+    /// without fields is rejected by the server.
+    pub fn to_line_protocol(&self, packet: &WeatherPacket) -> Option<String> {
+        encode_line(&self.measurement, self.precision, packet)
+    }
+
+    fn sender(&self) -> Result<&mpsc::Sender<Op>> {
+        self.tx
+            .as_ref()
+            .ok_or_else(|| anyhow!("influx sink closed"))
+    }
+
+    fn take_error(&self) -> Option<String> {
+        self.last_error.lock().ok().and_then(|mut e| e.take())
     }
 }
 
 #[async_trait::async_trait]
 impl Sink for InfluxSink {
     async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
-        let line = self.to_line_protocol(packet);
-        let url = format!(
-            "{}/api/v2/write?org={}&bucket={}",
-            self.base_url, self.org, self.bucket
-        );
-        let resp = self
-            .client
-            .post(&url)
-            .bearer_auth(&self.token)
-            .header("Content-Type", "text/plain; charset=utf-8")
-            .body(line)
-            .send()
-            .await?;
-        if !resp.status().is_success() {
-            let status = resp.status();
-            let text = resp.text().await.unwrap_or_default();
-            return Err(anyhow!("influx write failed: {} {}", status, text));
+        self.emit_batch(std::slice::from_ref(packet)).await
+    }
+
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        // Surface a failed background write on the next call
+        if let Some(e) = self.take_error() {
+            return Err(anyhow!("influx write failed: {}", e));
+        }
+        let tx = self.sender()?;
+        for line in packets.iter().filter_map(|p| self.to_line_protocol(p)) {
+            tx.send(Op::Line(line))
+                .await
+                .map_err(|_| anyhow!("influx writer stopped"))?;
         }
         Ok(())
     }
+
+    /// Send all queued lines and wait for the result
+    async fn flush(&mut self) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        self.sender()?
+            .send(Op::Flush(reply))
+            .await
+            .map_err(|_| anyhow!("influx writer stopped"))?;
+        let res = rx.await.map_err(|_| anyhow!("influx writer stopped"))?;
+        // The reply already carries this batch's failure; earlier ones are reported here
+        let earlier = self.take_error();
+        res?;
+        match earlier {
+            Some(e) => Err(anyhow!("influx write failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Send what is queued and stop the writer task
+    async fn close(&mut self) -> Result<()> {
+        self.tx.take();
+        if let Some(task) = self.task.take() {
+            task.await?;
+        }
+        match self.take_error() {
+            Some(e) => Err(anyhow!("influx write failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_url(base_url: &str, target: &InfluxTarget, precision: Precision) -> Result<reqwest::Url> {
+    let base = base_url.trim_end_matches('/');
+    let mut url = match target {
+        InfluxTarget::V2 { org, bucket, .. } => {
+            let mut url = reqwest::Url::parse(&format!("{}/api/v2/write", base))?;
+            url.query_pairs_mut()
+                .append_pair("org", org)
+                .append_pair("bucket", bucket);
+            url
+        }
+        InfluxTarget::V1 {
+            database,
+            retention_policy,
+            ..
+        } => {
+            let mut url = reqwest::Url::parse(&format!("{}/write", base))?;
+            url.query_pairs_mut().append_pair("db", database);
+            if let Some(rp) = retention_policy {
+                url.query_pairs_mut().append_pair("rp", rp);
+            }
+            url
+        }
+    };
+    url.query_pairs_mut()
+        .append_pair("precision", precision.as_str());
+    Ok(url)
+}
+
+/// Escape a measurement name (commas and spaces)
+fn escape_measurement(s: &str) -> String {
+    escape(s, &[',', ' '])
+}
+
+/// Escape a tag key, tag value or field key (commas, equals signs and spaces)
+fn escape_key(s: &str) -> String {
+    escape(s, &[',', '=', ' '])
+}
+
+/// Quote a string field value, escaping backslashes and double quotes
+fn quote_string(s: &str) -> String {
+    format!("\"{}\"", escape(s, &['\\', '"']))
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\n' {
+            out.push_str("\\n");
+            continue;
+        }
+        if special.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn encode_line(measurement: &str, precision: Precision, packet: &WeatherPacket) -> Option<String> {
+    let mut keys: Vec<&String> = packet.observations.keys().collect();
+    keys.sort();
+
+    let mut fields: Vec<String> = Vec::with_capacity(keys.len() + 1);
+    for key in keys {
+        let value = match &packet.observations[key] {
+            ObservationValue::Float(f) if f.is_finite() => format!("{}", f),
+            ObservationValue::Float(_) => continue,
+            ObservationValue::Integer(i) => format!("{}i", i),
+            ObservationValue::Bool(b) => b.to_string(),
+            ObservationValue::String(s) => quote_string(s),
+            ObservationValue::Null => continue,
+        };
+        fields.push(format!("{}={}", escape_key(key), value));
+    }
+    if let Some(iv) = packet.interval {
+        fields.push(format!("interval={}i", iv));
+    }
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape_measurement(measurement);
+    if let Some(station) = &packet.station {
+        let _ = write!(line, ",station={}", escape_key(station));
+    }
+    let _ = write!(
+        line,
+        " {} {}",
+        fields.join(","),
+        precision.scale(packet.date_time)
+    );
+    Some(line)
+}
+
+struct Writer {
+    client: Client,
+    url: reqwest::Url,
+    target: InfluxTarget,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl Writer {
+    async fn send(&self, lines: &[String]) -> Result<()> {
+        let body = lines.join("\n");
+        let mut delay = self.retry_backoff;
+        let mut attempt = 0;
+        loop {
+            let mut req = self
+                .client
+                .post(self.url.clone())
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(body.clone());
+            req = match &self.target {
+                InfluxTarget::V2 { token, .. } => {
+                    req.header("Authorization", format!("Token {}", token))
+                }
+                InfluxTarget::V1 {
+                    username: Some(user),
+                    password,
+                    ..
+                } => req.basic_auth(user, password.as_ref()),
+                InfluxTarget::V1 { .. } => req,
+            };
+
+            let (retryable, err, retry_after) = match req.send().await {
+                Ok(resp) if resp.status().is_success() => return Ok(()),
+                Ok(resp) => {
+                    let status = resp.status();
+                    let retry_after = resp
+                        .headers()
+                        .get("Retry-After")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map(Duration::from_secs);
+                    let text = resp.text().await.unwrap_or_default();
+                    let retryable =
+                        status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                    // 400 means the server refused the line protocol itself
+                    let err = if status == StatusCode::BAD_REQUEST {
+                        crate::rejected(format!("{} {}", status, text))
+                    } else {
+                        anyhow!("{} {}", status, text)
+                    };
+                    (retryable, err, retry_after)
+                }
+                Err(e) => (true, anyhow!(e), None),
+            };
+
+            if !retryable || attempt >= self.max_retries {
+                return Err(err);
+            }
+            attempt += 1;
+            let wait = retry_after.unwrap_or(delay);
+            tracing::debug!(attempt, ?wait, error = ?err, "retrying influx write");
+            tokio::time::sleep(wait).await;
+            delay = (delay * 2).min(Duration::from_secs(60));
+        }
+    }
+}
+
+async fn run_writer(
+    writer: Writer,
+    mut rx: mpsc::Receiver<Op>,
+    max_batch: usize,
+    linger: Duration,
+    last_error: Arc<Mutex<Option<String>>>,
+) {
+    let mut pending: Vec<String> = Vec::with_capacity(max_batch);
+    let mut deadline = tokio::time::Instant::now();
+
+    let send = |pending: Vec<String>| {
+        let writer = &writer;
+        let last_error = &last_error;
+        async move {
+            let res = writer.send(&pending).await;
+            if let Err(e) = &res {
+                tracing::warn!(error = ?e, lines = pending.len(), "influx batch write failed");
+                if let Ok(mut slot) = last_error.lock() {
+                    *slot = Some(e.to_string());
+                }
+            }
+            res
+        }
+    };
+
+    loop {
+        let op = if pending.is_empty() {
+            rx.recv().await
+        } else {
+            tokio::select! {
+                op = rx.recv() => op,
+                _ = tokio::time::sleep_until(deadline) => {
+                    let _ = send(std::mem::take(&mut pending)).await;
+                    continue;
+                }
+            }
+        };
+
+        match op {
+            Some(Op::Line(line)) => {
+                if pending.is_empty() {
+                    deadline = tokio::time::Instant::now() + linger;
+                }
+                pending.push(line);
+                if pending.len() >= max_batch {
+                    let _ = send(std::mem::take(&mut pending)).await;
+                }
+            }
+            Some(Op::Flush(reply)) => {
+                let res = if pending.is_empty() {
+                    Ok(())
+                } else {
+                    send(std::mem::take(&mut pending)).await
+                };
+                let _ = reply.send(res);
+            }
+            None => {
+                if !pending.is_empty() {
+                    let _ = send(std::mem::take(&mut pending)).await;
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Query, State},
+        http::{HeaderMap, StatusCode as AxumStatus},
+        routing::post,
+        Router,
+    };
+    use std::collections::HashMap;
+
+    /// (path, query, body, authorization header)
+    type Recorded = (String, HashMap<String, String>, String, Option<String>);
+
+    #[derive(Default)]
+    struct Mock {
+        /// Statuses to return, in order; 204 once exhausted
+        statuses: Vec<u16>,
+        requests: Vec<Recorded>,
+    }
+
+    type MockState = Arc<Mutex<Mock>>;
+
+    async fn handle(
+        State(state): State<MockState>,
+        uri: axum::http::Uri,
+        Query(q): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        body: String,
+    ) -> AxumStatus {
+        let mut mock = state.lock().unwrap();
+        let auth = headers
+            .get("authorization")
+            .map(|v| v.to_str().unwrap().to_string());
+        mock.requests.push((uri.path().to_string(), q, body, auth));
+        let status = if mock.statuses.is_empty() {
+            204
+        } else {
+            mock.statuses.remove(0)
+        };
+        AxumStatus::from_u16(status).unwrap()
+    }
+
+    async fn mock_server(statuses: Vec<u16>) -> (String, MockState) {
+        let state: MockState = Arc::new(Mutex::new(Mock {
+            statuses,
+            ..Default::default()
+        }));
+        let app = Router::new()
+            .route("/api/v2/write", post(handle))
+            .route("/write", post(handle))
+            .with_state(Arc::clone(&state));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), state)
+    }
+
+    fn packet(obs: Vec<(&str, ObservationValue)>) -> WeatherPacket {
+        WeatherPacket {
+            date_time: 1_700_000_000,
+            station: Some("my station,1".into()),
+            interval: None,
+            observations: obs.into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+        }
+    }
+
+    fn fast_opts() -> InfluxSinkOptions {
+        InfluxSinkOptions {
+            linger: Duration::from_millis(20),
+            retry_backoff: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn encodes_with_full_escaping() {
+        let pkt = packet(vec![
+            ("out Temp", ObservationValue::Float(21.5)),
+            ("a,b=c", ObservationValue::Integer(3)),
+            ("label", ObservationValue::String("say \"hi\" \\o/".into())),
+            ("alarm", ObservationValue::Bool(true)),
+            ("missing", ObservationValue::Null),
+            ("bad", ObservationValue::Float(f64::NAN)),
+        ]);
+        let line = encode_line("wx data", Precision::Seconds, &pkt).unwrap();
+        assert_eq!(
+            line,
+            "wx\\ data,station=my\\ station\\,1 \
+             a\\,b\\=c=3i,alarm=true,label=\"say \\\"hi\\\" \\\\o/\",out\\ Temp=21.5 \
+             1700000000"
+        );
+    }
+
+    #[test]
+    fn scales_timestamp_to_precision() {
+        let pkt = packet(vec![("outTemp", ObservationValue::Float(1.0))]);
+        let line = encode_line("weather", Precision::Milliseconds, &pkt).unwrap();
+        assert!(line.ends_with(" 1700000000000"));
+        let line = encode_line("weather", Precision::Nanoseconds, &pkt).unwrap();
+        assert!(line.ends_with(" 1700000000000000000"));
+    }
+
+    #[test]
+    fn skips_packets_without_fields() {
+        let pkt = packet(vec![("x", ObservationValue::Null)]);
+        assert!(encode_line("weather", Precision::Seconds, &pkt).is_none());
+    }
+
+    #[tokio::test]
+    async fn batches_v2_writes_with_precision() {
+        let (url, state) = mock_server(vec![]).await;
+        let target = InfluxTarget::V2 {
+            org: "org".into(),
+            bucket: "wx".into(),
+            token: "secret".into(),
+        };
+        let mut sink = InfluxSink::with_options(url, target, fast_opts()).unwrap();
+        for _ in 0..3 {
+            sink.emit(&packet(vec![("outTemp", ObservationValue::Float(1.0))]))
+                .await
+                .unwrap();
+        }
+        sink.flush().await.unwrap();
+
+        let mock = state.lock().unwrap();
+        assert_eq!(mock.requests.len(), 1);
+        let (path, query, body, auth) = &mock.requests[0];
+        assert_eq!(path, "/api/v2/write");
+        assert_eq!(query["precision"], "s");
+        assert_eq!(query["bucket"], "wx");
+        assert_eq!(body.lines().count(), 3);
+        assert_eq!(auth.as_deref(), Some("Token secret"));
+    }
+
+    #[tokio::test]
+    async fn retries_429_and_5xx() {
+        let (url, state) = mock_server(vec![429, 503]).await;
+        let target = InfluxTarget::V1 {
+            database: "weewx".into(),
+            retention_policy: Some("autogen".into()),
+            username: None,
+            password: None,
+        };
+        let mut sink = InfluxSink::with_options(url, target, fast_opts()).unwrap();
+        sink.emit(&packet(vec![("outTemp", ObservationValue::Float(1.0))]))
+            .await
+            .unwrap();
+        sink.flush().await.unwrap();
+
+        let mock = state.lock().unwrap();
+        assert_eq!(mock.requests.len(), 3);
+        let (path, query, _, _) = &mock.requests[2];
+        assert_eq!(path, "/write");
+        assert_eq!(query["db"], "weewx");
+        assert_eq!(query["rp"], "autogen");
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_client_errors() {
+        let (url, state) = mock_server(vec![400]).await;
+        let target = InfluxTarget::V2 {
+            org: "org".into(),
+            bucket: "wx".into(),
+            token: "secret".into(),
+        };
+        let mut sink = InfluxSink::with_options(url, target, fast_opts()).unwrap();
+        sink.emit(&packet(vec![("outTemp", ObservationValue::Float(1.0))]))
+            .await
+            .unwrap();
+        let err = sink.flush().await.unwrap_err();
+        assert!(err.downcast_ref::<crate::Rejected>().is_some());
+        assert_eq!(state.lock().unwrap().requests.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn close_sends_queued_lines() {
+        let (url, state) = mock_server(vec![]).await;
+        let target = InfluxTarget::V2 {
+            org: "org".into(),
+            bucket: "wx".into(),
+            token: "secret".into(),
+        };
+        let opts = InfluxSinkOptions {
+            linger: Duration::from_secs(60),
+            ..fast_opts()
+        };
+        let mut sink = InfluxSink::with_options(url, target, opts).unwrap();
+        let pkts: Vec<WeatherPacket> = (0..2)
+            .map(|_| packet(vec![("outTemp", ObservationValue::Float(1.0))]))
+            .collect();
+        sink.emit_batch(&pkts).await.unwrap();
+        sink.close().await.unwrap();
+
+        let mock = state.lock().unwrap();
+        assert_eq!(mock.requests.len(), 1);
+        assert_eq!(mock.requests[0].2.lines().count(), 2);
+    }
 }
@@ -1,19 +1,27 @@
 use anyhow::Result;
+pub mod fanout;
 #[cfg(feature = "influx")]
 pub mod influx;
+pub mod outbox;
 #[cfg(feature = "postgres")]
 pub mod postgres;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
 
-use std::fs::{create_dir_all, OpenOptions};
-use std::io::Write;
+use anyhow::anyhow;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
 use weex_core::{Sink, WeatherPacket};
 
+pub use fanout::{SinkFanout, SinkStats, SinkStatus};
+pub use outbox::{rejected, Outbox, OutboxOptions, Rejected};
+
+/// Appends packets as JSON lines to `<dir>/packets.jsonl`, keeping the file open
 pub struct FsSink {
     _dir: PathBuf,
     file: PathBuf,
+    writer: Option<BufWriter<File>>,
 }
 
 impl FsSink {
@@ -21,20 +29,53 @@ impl FsSink {
         let dir = dir.as_ref().to_path_buf();
         create_dir_all(&dir)?;
         let file = dir.join("packets.jsonl");
-        Ok(Self { _dir: dir, file })
+        let writer = OpenOptions::new().create(true).append(true).open(&file)?;
+        Ok(Self {
+            _dir: dir,
+            file,
+            writer: Some(BufWriter::new(writer)),
+        })
+    }
+
+    fn writer(&mut self) -> Result<&mut BufWriter<File>> {
+        let file = &self.file;
+        self.writer
+            .as_mut()
+            .ok_or_else(|| anyhow!("fs sink closed: {}", file.display()))
     }
 }
 
 #[async_trait::async_trait]
 impl Sink for FsSink {
     async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
-        let mut f = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.file)?;
-        let line = serde_json::to_string(packet)?;
-        f.write_all(line.as_bytes())?;
-        f.write_all(b"\n")?;
+        self.emit_batch(std::slice::from_ref(packet)).await
+    }
+
+    /// Encode every packet first, then hand the lines to the OS in one write
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        let mut buf = String::new();
+        for packet in packets {
+            buf.push_str(&serde_json::to_string(packet).map_err(rejected)?);
+            buf.push('\n');
+        }
+        let w = self.writer()?;
+        w.write_all(buf.as_bytes())?;
+        w.flush()?;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let w = self.writer()?;
+        w.flush()?;
+        w.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if self.writer.is_some() {
+            self.flush().await?;
+            self.writer = None;
+        }
         Ok(())
     }
 }
@@ -60,4 +101,24 @@ mod tests {
         let content = std::fs::read_to_string(dir.path().join("packets.jsonl")).unwrap();
         assert!(content.contains("outTemp"));
     }
+
+    #[tokio::test]
+    async fn batch_and_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FsSink::new(dir.path()).unwrap();
+        let pkts: Vec<WeatherPacket> = (1..=3)
+            .map(|ts| WeatherPacket {
+                date_time: ts,
+                station: None,
+                interval: None,
+                observations: HashMap::new(),
+            })
+            .collect();
+        sink.emit_batch(&pkts).await.unwrap();
+        sink.close().await.unwrap();
+        assert!(sink.emit(&pkts[0]).await.is_err());
+
+        let content = std::fs::read_to_string(dir.path().join("packets.jsonl")).unwrap();
+        assert_eq!(content.lines().count(), 3);
+    }
 }
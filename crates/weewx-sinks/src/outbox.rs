@@ -0,0 +1,779 @@
+//! Disk-backed durable outbox for any `Sink`
+//!
+//! Every packet is first appended to an on-disk segment log (`<seq>.seg`, one
+//! JSON packet per line) and then replayed in order to the wrapped sink. When
+//! the target fails, replay stops and is retried with exponential backoff, on
+//! later emits or on a `tick` once the backoff has passed, so nothing is lost
+//! while Postgres or Influx is down.
+//!
+//! Packets are replayed in batches (`emit_batch` then `flush`) and the cursor
+//! only moves once the target has confirmed the flush. Delivery is
+//! at-least-once: a crash between a successful flush and the cursor update
+//! replays that batch. Packets the target rejects outright
+//! (see [`rejected`]), packets that keep failing while the target takes others
+//! (see [`OutboxOptions::max_attempts`]) and unreadable log lines go to
+//! `dead-letter.jsonl`.
+
+use anyhow::Result;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use weex_core::{Sink, WeatherPacket};
+
+const CURSOR_FILE: &str = "cursor";
+const DEAD_LETTER_FILE: &str = "dead-letter.jsonl";
+const SEGMENT_EXT: &str = "seg";
+/// Maximum packets handed to the target per replay round
+const REPLAY_BATCH: usize = 100;
+
+/// Error marker for packets the target will never accept (e.g. a value that
+/// cannot be encoded). The outbox dead-letters these instead of retrying.
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Wrap an error as a permanent, packet-specific rejection
+pub fn rejected(e: impl fmt::Display) -> anyhow::Error {
+    anyhow::Error::new(Rejected(e.to_string()))
+}
+
+/// Outbox tuning
+#[derive(Debug, Clone)]
+pub struct OutboxOptions {
+    /// Start a new segment once the active one reaches this size
+    pub max_segment_bytes: u64,
+    /// First retry delay after a failed replay; doubled per failure
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Dead-letter a packet after it has failed this many times while the
+    /// target took other packets (None = retry forever). Failures while the
+    /// target is down do not count.
+    pub max_attempts: Option<u32>,
+    /// fsync each append and cursor update
+    pub fsync: bool,
+}
+
+impl Default for OutboxOptions {
+    fn default() -> Self {
+        Self {
+            max_segment_bytes: 4 * 1024 * 1024,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            max_attempts: None,
+            fsync: true,
+        }
+    }
+}
+
+/// Replay position: segment number and byte offset of the next packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cursor {
+    segment: u64,
+    offset: u64,
+}
+
+pub struct Outbox<S> {
+    inner: S,
+    dir: PathBuf,
+    opts: OutboxOptions,
+    depth: Arc<AtomicU64>,
+    head: Cursor,
+    reader: Option<(u64, BufReader<File>)>,
+    tail_segment: u64,
+    tail_bytes: u64,
+    writer: File,
+    attempts: u32,
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+}
+
+impl<S: Sink> Outbox<S> {
+    /// Open (or create) the outbox in `dir`, resuming any pending packets
+    pub fn open<P: AsRef<Path>>(dir: P, inner: S, opts: OutboxOptions) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let segments = list_segments(&dir)?;
+        let first = segments.first().copied().unwrap_or(0);
+        let tail_segment = segments.last().copied().unwrap_or(0);
+
+        let mut head = read_cursor(&dir)?.unwrap_or(Cursor {
+            segment: first,
+            offset: 0,
+        });
+        if head.segment < first {
+            head = Cursor {
+                segment: first,
+                offset: 0,
+            };
+        }
+
+        truncate_torn_write(&segment_path(&dir, tail_segment))?;
+
+        let mut depth = 0u64;
+        for &seg in segments.iter().filter(|&&s| s >= head.segment) {
+            let mut reader = BufReader::new(File::open(segment_path(&dir, seg))?);
+            if seg == head.segment {
+                reader.seek(SeekFrom::Start(head.offset))?;
+            }
+            depth += reader.lines().count() as u64;
+        }
+
+        let writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&dir, tail_segment))?;
+        let tail_bytes = writer.metadata()?.len();
+
+        Ok(Self {
+            inner,
+            dir,
+            backoff: opts.initial_backoff,
+            opts,
+            depth: Arc::new(AtomicU64::new(depth)),
+            head,
+            reader: None,
+            tail_segment,
+            tail_bytes,
+            writer,
+            attempts: 0,
+            next_attempt: None,
+        })
+    }
+
+    /// Packets written to disk but not yet delivered
+    pub fn depth(&self) -> u64 {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Shared queue-depth counter, for metrics
+    pub fn depth_handle(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.depth)
+    }
+
+    /// Replay pending packets in order until the log is empty or the target fails.
+    /// Returns the number of packets delivered.
+    pub async fn drain(&mut self) -> Result<usize> {
+        let mut delivered = 0;
+        loop {
+            let batch = self.next_batch()?;
+            if batch.is_empty() {
+                return Ok(delivered);
+            }
+            let packets: Vec<WeatherPacket> = batch.iter().map(|(p, _)| p.clone()).collect();
+            match self.deliver(&packets).await {
+                Ok(()) => {
+                    for (_, len) in &batch {
+                        self.advance(*len)?;
+                    }
+                    delivered += batch.len();
+                }
+                Err(e) if batch.len() == 1 => {
+                    let (packet, len) = batch.into_iter().next().expect("one packet");
+                    self.failed(packet, len, e, false, None).await?;
+                }
+                Err(_) => {
+                    // Retry one packet at a time so only the bad ones are
+                    // dead-lettered
+                    let mut batch = batch.into_iter().peekable();
+                    let mut taken = false;
+                    while let Some((packet, len)) = batch.next() {
+                        match self.deliver(std::slice::from_ref(&packet)).await {
+                            Ok(()) => {
+                                self.advance(len)?;
+                                delivered += 1;
+                                taken = true;
+                            }
+                            Err(e) => {
+                                if self.failed(packet, len, e, taken, batch.peek()).await? {
+                                    // The next packet went through as the probe
+                                    if let Some((_, len)) = batch.next() {
+                                        self.advance(len)?;
+                                        delivered += 1;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn deliver(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        self.inner.emit_batch(packets).await?;
+        self.inner.flush().await
+    }
+
+    /// Handle a packet the target failed on its own. Rejected packets are
+    /// dead-lettered; any other failure is returned, unless the packet has
+    /// used up `max_attempts`. An attempt only counts while the target is
+    /// evidently up: it took an earlier packet (`taken`), or takes `next` when
+    /// probed with it. A target that is down never uses up attempts. Returns
+    /// whether `next` was delivered.
+    ///
+    /// A probe the packet survives is replayed again later, which delivery
+    /// being at-least-once allows.
+    async fn failed(
+        &mut self,
+        packet: WeatherPacket,
+        len: u64,
+        e: anyhow::Error,
+        taken: bool,
+        next: Option<&(WeatherPacket, u64)>,
+    ) -> Result<bool> {
+        let mut probed = false;
+        if e.downcast_ref::<Rejected>().is_none() {
+            let Some(max_attempts) = self.opts.max_attempts else {
+                self.reader = None;
+                return Err(e);
+            };
+            if !taken {
+                probed = match next {
+                    Some((next, _)) => self.deliver(std::slice::from_ref(next)).await.is_ok(),
+                    None => false,
+                };
+            }
+            if taken || probed {
+                self.attempts += 1;
+            }
+            if self.attempts < max_attempts {
+                // Re-read from this packet on the next attempt
+                self.reader = None;
+                return Err(e);
+            }
+        }
+        self.dead_letter(serde_json::to_value(&packet)?, &format!("{:#}", e))?;
+        self.advance(len)?;
+        Ok(probed)
+    }
+
+    /// Read up to `REPLAY_BATCH` packets from the head segment without moving
+    /// the cursor. Unreadable lines at the head are dead-lettered on the way.
+    fn next_batch(&mut self) -> Result<Vec<(WeatherPacket, u64)>> {
+        let mut batch = Vec::new();
+        while batch.len() < REPLAY_BATCH && (batch.len() as u64) < self.depth() {
+            // Only the first line may move on to the next segment: the cursor
+            // cannot skip a segment while packets from it are unconfirmed
+            let line = if batch.is_empty() {
+                self.peek()?
+            } else {
+                self.read_line()?
+            };
+            let Some(line) = line else {
+                break;
+            };
+            let len = line.len() as u64;
+            match serde_json::from_str::<WeatherPacket>(line.trim_end()) {
+                Ok(p) => batch.push((p, len)),
+                Err(e) if batch.is_empty() => {
+                    self.dead_letter(serde_json::Value::String(line), &e.to_string())?;
+                    self.advance(len)?;
+                }
+                Err(_) => {
+                    // Handled at the head of the next batch
+                    self.reader = None;
+                    break;
+                }
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Append a packet to the active segment
+    fn append(&mut self, packet: &WeatherPacket) -> Result<()> {
+        if self.tail_bytes >= self.opts.max_segment_bytes {
+            self.tail_segment += 1;
+            self.writer = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(segment_path(&self.dir, self.tail_segment))?;
+            self.tail_bytes = 0;
+        }
+        let mut line = serde_json::to_string(packet).map_err(rejected)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        if self.opts.fsync {
+            self.writer.sync_data()?;
+        }
+        self.tail_bytes += line.len() as u64;
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Read the line at the head cursor, moving past exhausted segments
+    fn peek(&mut self) -> Result<Option<String>> {
+        loop {
+            if self.depth() == 0 {
+                return Ok(None);
+            }
+            if let Some(line) = self.read_line()? {
+                return Ok(Some(line));
+            }
+            // End of segment (or a torn final write in the active one)
+            self.reader = None;
+            if self.head.segment >= self.tail_segment {
+                return Ok(None);
+            }
+            let done = self.head.segment;
+            self.head = Cursor {
+                segment: done + 1,
+                offset: 0,
+            };
+            self.save_cursor()?;
+            let _ = fs::remove_file(segment_path(&self.dir, done));
+        }
+    }
+
+    /// Read the next complete line of the head segment, if any
+    fn read_line(&mut self) -> Result<Option<String>> {
+        let reader = match &mut self.reader {
+            Some((seg, reader)) if *seg == self.head.segment => reader,
+            _ => {
+                let mut f = File::open(segment_path(&self.dir, self.head.segment))?;
+                f.seek(SeekFrom::Start(self.head.offset))?;
+                &mut self.reader.insert((self.head.segment, BufReader::new(f))).1
+            }
+        };
+        let mut line = String::new();
+        let n = reader.read_line(&mut line)?;
+        Ok((n > 0 && line.ends_with('\n')).then_some(line))
+    }
+
+    fn advance(&mut self, len: u64) -> Result<()> {
+        self.head.offset += len;
+        self.attempts = 0;
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        self.save_cursor()
+    }
+
+    fn save_cursor(&self) -> Result<()> {
+        let tmp = self.dir.join(format!("{}.tmp", CURSOR_FILE));
+        let mut f = File::create(&tmp)?;
+        write!(f, "{} {}", self.head.segment, self.head.offset)?;
+        if self.opts.fsync {
+            f.sync_data()?;
+        }
+        fs::rename(tmp, self.dir.join(CURSOR_FILE))?;
+        Ok(())
+    }
+
+    /// Drain, backing off exponentially while the target fails
+    async fn replay(&mut self) {
+        match self.drain().await {
+            Ok(_) => {
+                self.backoff = self.opts.initial_backoff;
+                // Anything left (there should be nothing) waits a round
+                self.next_attempt = (self.depth() > 0).then(|| Instant::now() + self.backoff);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    dir = %self.dir.display(),
+                    depth = self.depth(),
+                    retry_in = ?self.backoff,
+                    error = ?e,
+                    "outbox target unavailable"
+                );
+                self.next_attempt = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(self.opts.max_backoff);
+            }
+        }
+    }
+
+    fn dead_letter(&self, packet: serde_json::Value, error: &str) -> Result<()> {
+        tracing::warn!(dir = %self.dir.display(), error, "outbox packet dead-lettered");
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(DEAD_LETTER_FILE))?;
+        let entry = serde_json::json!({ "error": error, "packet": packet });
+        writeln!(f, "{}", entry)?;
+        if self.opts.fsync {
+            f.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: Sink> Sink for Outbox<S> {
+    async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+        self.emit_batch(std::slice::from_ref(packet)).await
+    }
+
+    /// Durably queue the packets, then replay the backlog unless backing off.
+    /// Target failures are not returned: the packets are safe on disk.
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        for packet in packets {
+            self.append(packet)?;
+        }
+
+        if self.next_attempt.is_some_and(|t| Instant::now() < t) {
+            return Ok(());
+        }
+        self.replay().await;
+        Ok(())
+    }
+
+    /// When the backoff ends, or now if packets are pending without one (e.g.
+    /// left over from the last run); `None` once the log is empty
+    fn next_tick(&self) -> Option<i64> {
+        if self.depth() == 0 {
+            return None;
+        }
+        let wait = self.next_attempt.map_or(Duration::ZERO, |t| {
+            t.saturating_duration_since(Instant::now())
+        });
+        let at = (SystemTime::now() + wait).duration_since(UNIX_EPOCH).ok()?;
+        Some(at.as_secs() as i64 + i64::from(at.subsec_nanos() > 0))
+    }
+
+    /// Replay the backlog without waiting for the next packet
+    async fn tick(&mut self, _now: i64) -> Result<()> {
+        self.replay().await;
+        Ok(())
+    }
+
+    /// Replay the whole backlog now, ignoring any backoff window
+    async fn flush(&mut self) -> Result<()> {
+        self.drain().await?;
+        self.backoff = self.opts.initial_backoff;
+        self.next_attempt = None;
+        Ok(())
+    }
+
+    /// Make a last replay attempt, then close the target. Undelivered packets
+    /// stay on disk for the next start.
+    async fn close(&mut self) -> Result<()> {
+        if let Err(e) = self.flush().await {
+            tracing::warn!(
+                dir = %self.dir.display(),
+                depth = self.depth(),
+                error = ?e,
+                "outbox not drained at shutdown"
+            );
+        }
+        self.inner.close().await
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", segment, SEGMENT_EXT))
+}
+
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXT) {
+            continue;
+        }
+        if let Some(n) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            segments.push(n);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Drop a partial last line left by a crash mid-append
+fn truncate_torn_write(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let bytes = fs::read(path)?;
+    if bytes.last().is_some_and(|&b| b != b'\n') {
+        let keep = bytes.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(keep as u64)?;
+    }
+    Ok(())
+}
+
+fn read_cursor(dir: &Path) -> Result<Option<Cursor>> {
+    let path = dir.join(CURSOR_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    let mut parts = text.split_whitespace().map(str::parse::<u64>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(segment)), Some(Ok(offset))) => Ok(Some(Cursor { segment, offset })),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Records delivered timestamps; fails while `down` is set, always fails
+    /// ts == 7 and rejects ts == 13
+    #[derive(Clone, Default)]
+    struct FlakySink {
+        down: Arc<Mutex<bool>>,
+        seen: Arc<Mutex<Vec<i64>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for FlakySink {
+        async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+            if *self.down.lock().unwrap() {
+                return Err(anyhow!("connection refused"));
+            }
+            if packet.date_time == 7 {
+                return Err(anyhow!("value out of range"));
+            }
+            if packet.date_time == 13 {
+                return Err(rejected("bad value"));
+            }
+            self.seen.lock().unwrap().push(packet.date_time);
+            Ok(())
+        }
+    }
+
+    fn packet(ts: i64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: HashMap::new(),
+        }
+    }
+
+    fn opts() -> OutboxOptions {
+        OutboxOptions {
+            max_segment_bytes: 64,
+            initial_backoff: Duration::ZERO,
+            fsync: false,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_in_order_after_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+
+        *sink.down.lock().unwrap() = true;
+        for ts in 1..=5 {
+            outbox.emit(&packet(ts)).await.unwrap();
+        }
+        assert_eq!(outbox.depth(), 5);
+        assert!(sink.seen.lock().unwrap().is_empty());
+
+        *sink.down.lock().unwrap() = false;
+        outbox.emit(&packet(6)).await.unwrap();
+        assert_eq!(outbox.depth(), 0);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+
+        // Consumed segments are removed
+        assert_eq!(list_segments(dir.path()).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_pending_packets_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        *sink.down.lock().unwrap() = true;
+        {
+            let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+            for ts in 1..=3 {
+                outbox.emit(&packet(ts)).await.unwrap();
+            }
+        }
+
+        *sink.down.lock().unwrap() = false;
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+        assert_eq!(outbox.depth(), 3);
+        assert_eq!(outbox.drain().await.unwrap(), 3);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1, 2, 3]);
+
+        // Cursor survives a second restart: nothing is replayed twice
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+        assert_eq!(outbox.depth(), 0);
+        assert_eq!(outbox.drain().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn discards_torn_write_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        *sink.down.lock().unwrap() = true;
+        {
+            let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+            outbox.emit(&packet(1)).await.unwrap();
+        }
+        let seg = segment_path(dir.path(), 0);
+        let mut f = OpenOptions::new().append(true).open(&seg).unwrap();
+        f.write_all(b"{\"dateTi").unwrap();
+
+        *sink.down.lock().unwrap() = false;
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+        assert_eq!(outbox.depth(), 1);
+        outbox.emit(&packet(2)).await.unwrap();
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn dead_letters_rejected_packets() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+
+        for ts in 12..=14 {
+            outbox.emit(&packet(ts)).await.unwrap();
+        }
+        assert_eq!(outbox.depth(), 0);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![12, 14]);
+
+        let dead = fs::read_to_string(dir.path().join(DEAD_LETTER_FILE)).unwrap();
+        assert_eq!(dead.lines().count(), 1);
+        assert!(dead.contains("bad value"));
+        assert!(dead.contains("\"dateTime\":13"));
+    }
+
+    #[tokio::test]
+    async fn isolates_rejected_packet_within_a_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let big_segments = OutboxOptions {
+            max_segment_bytes: 1 << 20,
+            ..opts()
+        };
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), big_segments).unwrap();
+
+        *sink.down.lock().unwrap() = true;
+        for ts in 13..=15 {
+            outbox.emit(&packet(ts)).await.unwrap();
+        }
+        *sink.down.lock().unwrap() = false;
+
+        assert_eq!(outbox.drain().await.unwrap(), 2);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![14, 15]);
+        let dead = fs::read_to_string(dir.path().join(DEAD_LETTER_FILE)).unwrap();
+        assert_eq!(dead.lines().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn backs_off_while_target_is_down() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let mut outbox = Outbox::open(
+            dir.path(),
+            sink.clone(),
+            OutboxOptions {
+                initial_backoff: Duration::from_secs(60),
+                fsync: false,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        *sink.down.lock().unwrap() = true;
+        outbox.emit(&packet(1)).await.unwrap();
+        *sink.down.lock().unwrap() = false;
+
+        // Still inside the backoff window: queued, not replayed
+        outbox.emit(&packet(2)).await.unwrap();
+        assert_eq!(outbox.depth(), 2);
+
+        assert_eq!(outbox.drain().await.unwrap(), 2);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn ticks_replay_once_backoff_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts()).unwrap();
+        assert_eq!(outbox.next_tick(), None);
+
+        *sink.down.lock().unwrap() = true;
+        outbox.emit(&packet(1)).await.unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let due = outbox.next_tick().unwrap();
+        assert!((now..=now + 1).contains(&due));
+
+        *sink.down.lock().unwrap() = false;
+        outbox.tick(due).await.unwrap();
+        assert_eq!(outbox.depth(), 0);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1]);
+        assert_eq!(outbox.next_tick(), None);
+    }
+
+    #[tokio::test]
+    async fn outage_does_not_use_up_attempts() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let opts = OutboxOptions {
+            max_attempts: Some(2),
+            ..opts()
+        };
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts).unwrap();
+        assert_eq!(OutboxOptions::default().max_attempts, None);
+
+        *sink.down.lock().unwrap() = true;
+        for ts in 1..=5 {
+            outbox.emit(&packet(ts)).await.unwrap();
+        }
+        assert_eq!(outbox.depth(), 5);
+        assert!(!dir.path().join(DEAD_LETTER_FILE).exists());
+
+        *sink.down.lock().unwrap() = false;
+        assert_eq!(outbox.drain().await.unwrap(), 5);
+        assert_eq!(*sink.seen.lock().unwrap(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn dead_letters_packet_failing_on_its_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = FlakySink::default();
+        let opts = OutboxOptions {
+            max_segment_bytes: 1 << 20,
+            max_attempts: Some(2),
+            ..opts()
+        };
+        let mut outbox = Outbox::open(dir.path(), sink.clone(), opts).unwrap();
+
+        // Alone, 7 could be failing because the target is down: no attempt
+        outbox.emit(&packet(7)).await.unwrap();
+        // The target takes 8, so 7 fails on its own; on the second such
+        // attempt it is dead-lettered and replay moves on
+        outbox.emit(&packet(8)).await.unwrap();
+        assert_eq!(outbox.depth(), 2);
+        outbox.emit(&packet(9)).await.unwrap();
+        assert_eq!(outbox.depth(), 0);
+        // 8 was also taken by the first probe: at-least-once
+        assert_eq!(*sink.seen.lock().unwrap(), vec![8, 8, 9]);
+
+        let dead = fs::read_to_string(dir.path().join(DEAD_LETTER_FILE)).unwrap();
+        assert_eq!(dead.lines().count(), 1);
+        assert!(dead.contains("\"dateTime\":7"));
+        assert!(dead.contains("value out of range"));
+    }
+}
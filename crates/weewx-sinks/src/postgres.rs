@@ -29,7 +29,7 @@ impl PostgresSink {
 #[async_trait::async_trait]
 impl Sink for PostgresSink {
     async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
-        let json = serde_json::to_string(packet)?;
+        let json = serde_json::to_string(packet).map_err(crate::rejected)?;
         sqlx::query("INSERT INTO packets (dt, json) VALUES ($1, $2)")
             .bind(packet.date_time)
             .bind(json)
@@ -37,4 +37,31 @@ impl Sink for PostgresSink {
             .await?;
         Ok(())
     }
+
+    /// One multi-row INSERT per batch
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        if packets.is_empty() {
+            return Ok(());
+        }
+        let rows = packets
+            .iter()
+            .map(|p| {
+                Ok((
+                    p.date_time,
+                    serde_json::to_string(p).map_err(crate::rejected)?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut qb = sqlx::QueryBuilder::<Postgres>::new("INSERT INTO packets (dt, json) ");
+        qb.push_values(rows, |mut b, (dt, json)| {
+            b.push_bind(dt).push_bind(json);
+        });
+        qb.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        self.pool.close().await;
+        Ok(())
+    }
 }
@@ -1,14 +1,71 @@
-use anyhow::Result;
+//! SQLite packet sink
+//!
+//! `rusqlite::Connection` is not `Sync`, so the connection lives on a dedicated
+//! thread (a connection actor). `emit` only queues the packet; the actor batches
+//! queued packets into a single transaction committed every `commit_interval`
+//! or once `max_batch` packets are pending, whichever comes first. A batch
+//! whose commit fails (e.g. the database is locked) is kept and retried at the
+//! next deadline or flush; while commits keep failing, at most `max_batch`
+//! packets are held and the oldest beyond that are dropped.
+//!
+//! `close` waits for the actor to commit and exit. A sink dropped without
+//! `close` only closes the queue: the actor still commits what is pending,
+//! but nothing waits for it.
+
+use anyhow::{anyhow, Result};
 use rusqlite::{params, Connection};
-use weex_core::WeatherPacket;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use weex_core::{Sink, WeatherPacket};
+
+/// Tuning for the SQLite connection actor
+#[derive(Debug, Clone)]
+pub struct SqliteSinkOptions {
+    /// Maximum time a queued packet waits before its batch is committed
+    pub commit_interval: Duration,
+    /// Commit as soon as this many packets are pending
+    pub max_batch: usize,
+    /// Use write-ahead logging (recommended on SD cards / Raspberry Pi)
+    pub wal: bool,
+}
+
+impl Default for SqliteSinkOptions {
+    fn default() -> Self {
+        Self {
+            commit_interval: Duration::from_secs(1),
+            max_batch: 500,
+            wal: true,
+        }
+    }
+}
+
+enum Op {
+    Insert(WeatherPacket),
+    Flush(oneshot::Sender<Result<()>>),
+}
 
 pub struct SqliteSink {
-    conn: Connection,
+    tx: Option<mpsc::Sender<Op>>,
+    thread: Option<JoinHandle<()>>,
+    last_error: Arc<Mutex<Option<String>>>,
 }
 
 impl SqliteSink {
-    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+    /// Open (or create) the database with default options
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::with_options(path, SqliteSinkOptions::default())
+    }
+
+    /// Open (or create) the database and start the connection actor
+    pub fn with_options<P: AsRef<Path>>(path: P, opts: SqliteSinkOptions) -> Result<Self> {
         let conn = Connection::open(path)?;
+        if opts.wal {
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+        }
         conn.execute_batch(
             "CREATE TABLE IF NOT EXISTS packets (
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -16,42 +73,308 @@ impl SqliteSink {
                 json TEXT NOT NULL
             );",
         )?;
-        Ok(Self { conn })
+
+        let (tx, rx) = mpsc::channel(opts.max_batch.max(1) * 2);
+        let last_error = Arc::new(Mutex::new(None));
+        let actor_error = Arc::clone(&last_error);
+        let thread = std::thread::Builder::new()
+            .name("sqlite-sink".into())
+            .spawn(move || run_actor(conn, rx, opts, actor_error))?;
+
+        Ok(Self {
+            tx: Some(tx),
+            thread: Some(thread),
+            last_error,
+        })
     }
 
-    pub fn emit_sync(&mut self, packet: &WeatherPacket) -> Result<()> {
-        let json = serde_json::to_string(packet)?;
-        self.conn.execute(
-            "INSERT INTO packets (dt, json) VALUES (?1, ?2)",
-            params![packet.date_time, json],
-        )?;
+    fn sender(&self) -> Result<&mpsc::Sender<Op>> {
+        self.tx
+            .as_ref()
+            .ok_or_else(|| anyhow!("sqlite sink closed"))
+    }
+
+    fn take_error(&self) -> Option<String> {
+        self.last_error.lock().ok().and_then(|mut e| e.take())
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for SqliteSink {
+    async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+        self.emit_batch(std::slice::from_ref(packet)).await
+    }
+
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        // Surface a failed background commit on the next call
+        if let Some(e) = self.take_error() {
+            return Err(anyhow!("sqlite batch commit failed: {}", e));
+        }
+        let tx = self.sender()?;
+        for packet in packets {
+            tx.send(Op::Insert(packet.clone()))
+                .await
+                .map_err(|_| anyhow!("sqlite sink actor stopped"))?;
+        }
         Ok(())
     }
+
+    /// Commit all queued packets and wait for the result
+    async fn flush(&mut self) -> Result<()> {
+        let (reply, rx) = oneshot::channel();
+        self.sender()?
+            .send(Op::Flush(reply))
+            .await
+            .map_err(|_| anyhow!("sqlite sink actor stopped"))?;
+        rx.await
+            .map_err(|_| anyhow!("sqlite sink actor stopped"))??;
+        match self.take_error() {
+            Some(e) => Err(anyhow!("sqlite batch commit failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+
+    /// Commit what is pending and stop the connection actor
+    async fn close(&mut self) -> Result<()> {
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            tokio::task::spawn_blocking(move || thread.join())
+                .await?
+                .map_err(|_| anyhow!("sqlite sink actor panicked"))?;
+        }
+        match self.take_error() {
+            Some(e) => Err(anyhow!("sqlite batch commit failed: {}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn run_actor(
+    mut conn: Connection,
+    mut rx: mpsc::Receiver<Op>,
+    opts: SqliteSinkOptions,
+    last_error: Arc<Mutex<Option<String>>>,
+) {
+    let rt = match tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+    {
+        Ok(rt) => rt,
+        Err(e) => {
+            tracing::error!(error = ?e, "sqlite sink actor failed to start");
+            return;
+        }
+    };
+
+    rt.block_on(async move {
+        let mut pending: Vec<WeatherPacket> = Vec::with_capacity(opts.max_batch);
+        let mut deadline = tokio::time::Instant::now();
+        // The last commit failed; its packets wait for the next deadline
+        let mut failing = false;
+
+        loop {
+            let op = if pending.is_empty() {
+                rx.recv().await
+            } else {
+                tokio::select! {
+                    op = rx.recv() => op,
+                    _ = tokio::time::sleep_until(deadline) => {
+                        failing = !commit(&mut conn, &mut pending, opts.max_batch, &last_error);
+                        deadline = tokio::time::Instant::now() + opts.commit_interval;
+                        continue;
+                    }
+                }
+            };
+
+            match op {
+                Some(Op::Insert(pkt)) => {
+                    if pending.is_empty() {
+                        deadline = tokio::time::Instant::now() + opts.commit_interval;
+                    }
+                    pending.push(pkt);
+                    if failing {
+                        // Hold at most a batch for the retry
+                        trim(&mut pending, opts.max_batch, &last_error);
+                    } else if pending.len() >= opts.max_batch {
+                        failing = !commit(&mut conn, &mut pending, opts.max_batch, &last_error);
+                    }
+                }
+                Some(Op::Flush(reply)) => {
+                    let res = write_batch(&mut conn, &pending);
+                    failing = res.is_err();
+                    if !failing {
+                        pending.clear();
+                    }
+                    let _ = reply.send(res.map_err(Into::into));
+                }
+                None => {
+                    if !commit(&mut conn, &mut pending, opts.max_batch, &last_error) {
+                        tracing::error!(
+                            packets = pending.len(),
+                            "sqlite sink closed with uncommitted packets"
+                        );
+                    }
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Commit the pending packets. On failure they are kept for the next commit
+/// or flush (at most `max_batch` of them) and the error is recorded; returns
+/// whether the commit succeeded.
+fn commit(
+    conn: &mut Connection,
+    pending: &mut Vec<WeatherPacket>,
+    max_batch: usize,
+    last_error: &Mutex<Option<String>>,
+) -> bool {
+    match write_batch(conn, pending) {
+        Ok(()) => {
+            pending.clear();
+            true
+        }
+        Err(e) => {
+            tracing::warn!(error = ?e, packets = pending.len(), "sqlite batch commit failed");
+            if let Ok(mut slot) = last_error.lock() {
+                *slot = Some(e.to_string());
+            }
+            trim(pending, max_batch, last_error);
+            false
+        }
+    }
 }
 
-// NOTE: Cannot implement Sink trait because rusqlite::Connection is not Sync
-// TODO: Use tokio_rusqlite or Arc<Mutex<Connection>> for async support
+/// Drop the oldest packets beyond `max_batch` while commits fail
+fn trim(pending: &mut Vec<WeatherPacket>, max_batch: usize, last_error: &Mutex<Option<String>>) {
+    if pending.len() <= max_batch {
+        return;
+    }
+    let dropped = pending.len() - max_batch;
+    pending.drain(..dropped);
+    tracing::warn!(dropped, "sqlite commits failing; oldest packets dropped");
+    if let Ok(mut slot) = last_error.lock() {
+        *slot = Some(format!("{} packets dropped while commits failed", dropped));
+    }
+}
+
+fn write_batch(conn: &mut Connection, packets: &[WeatherPacket]) -> rusqlite::Result<()> {
+    if packets.is_empty() {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("INSERT INTO packets (dt, json) VALUES (?1, ?2)")?;
+        for packet in packets {
+            let json = serde_json::to_string(packet)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+            stmt.execute(params![packet.date_time, json])?;
+        }
+    }
+    tx.commit()
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn inserts_packet() {
-        let dir = tempfile::tempdir().unwrap();
-        let db_path = dir.path().join("weewx.db");
-        let mut sink = SqliteSink::new(&db_path).unwrap();
-        let pkt = weex_core::WeatherPacket {
-            date_time: 1,
+    fn count(path: &Path) -> i64 {
+        Connection::open(path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM packets", [], |r| r.get(0))
+            .unwrap()
+    }
+
+    fn packet(ts: i64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
             station: None,
             interval: None,
             observations: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn inserts_packet() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("weewx.db");
+        let mut sink = SqliteSink::new(&db_path).unwrap();
+        sink.emit(&packet(1)).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(count(&db_path), 1);
+    }
+
+    #[tokio::test]
+    async fn batches_until_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("weewx.db");
+        let opts = SqliteSinkOptions {
+            commit_interval: Duration::from_secs(3600),
+            ..Default::default()
         };
-        sink.emit_sync(&pkt).unwrap();
-        let count: i64 = sink
-            .conn
-            .query_row("SELECT COUNT(*) FROM packets", [], |r| r.get(0))
+        let mut sink = SqliteSink::with_options(&db_path, opts).unwrap();
+        for ts in 0..5 {
+            sink.emit(&packet(ts)).await.unwrap();
+        }
+        assert_eq!(count(&db_path), 0);
+
+        sink.flush().await.unwrap();
+        assert_eq!(count(&db_path), 5);
+    }
+
+    #[tokio::test]
+    async fn keeps_batch_after_failed_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("weewx.db");
+        let opts = SqliteSinkOptions {
+            commit_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let mut sink = SqliteSink::with_options(&db_path, opts).unwrap();
+        sink.emit_batch(&[packet(1), packet(2)]).await.unwrap();
+
+        let other = Connection::open(&db_path).unwrap();
+        other
+            .execute_batch("ALTER TABLE packets RENAME TO moved")
+            .unwrap();
+        assert!(sink.flush().await.is_err());
+
+        // Once the database is usable again the same packets are committed
+        other
+            .execute_batch("ALTER TABLE moved RENAME TO packets")
+            .unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(count(&db_path), 2);
+    }
+
+    #[tokio::test]
+    async fn commits_on_drop_or_close_and_uses_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("weewx.db");
+        let mut sink = SqliteSink::new(&db_path).unwrap();
+        sink.emit(&packet(1)).await.unwrap();
+        sink.emit(&packet(2)).await.unwrap();
+        drop(sink);
+        // The actor commits in the background once the queue is closed
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while count(&db_path) < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("dropped sink did not commit");
+
+        let mut sink = SqliteSink::new(&db_path).unwrap();
+        sink.emit_batch(&[packet(3), packet(4)]).await.unwrap();
+        sink.close().await.unwrap();
+        assert_eq!(count(&db_path), 4);
+        assert!(sink.emit(&packet(5)).await.is_err());
+
+        let mode: String = Connection::open(&db_path)
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |r| r.get(0))
             .unwrap();
-        assert_eq!(count, 1);
+        assert_eq!(mode, "wal");
     }
 }
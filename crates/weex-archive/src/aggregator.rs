@@ -1,17 +1,96 @@
 //! Archive interval aggregation logic
 
-use crate::{ArchiveResult, PacketBuffer};
-use std::collections::HashMap;
-use tracing::{debug, info, instrument};
-use weex_core::{aggregate_packets, WeatherPacket};
-use weex_db::{schema::ArchiveRow, DbClient};
+use crate::daysummary::{self, DailySummaries};
+use crate::{ArchiveResult, PacketBuffer, Wal};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{debug, info, instrument, warn};
+use weex_core::{
+    aggregate_packets_with, windrun, AggregateType, ArchiveRecord, ObservationValue, RollupOptions,
+    WeatherPacket,
+};
+use weex_db::{schema::tables, ConflictPolicy, DbClient};
 
 /// Aggregator for converting packets to archive records
 pub struct IntervalAggregator {
     interval: i32,
     unit_system: i32,
+    rollup: RollupOptions,
     buffer: PacketBuffer,
     db_client: DbClient,
+    summaries: DailySummaries,
+    summary_tables_ready: bool,
+    archive_delay: i64,
+    gap_markers: bool,
+    /// End of the oldest interval not yet archived
+    next_close: Option<i64>,
+    /// End of the newest interval archived
+    last_closed: Option<i64>,
+    /// Newest record the archive had at startup
+    resumed_through: Option<i64>,
+    conflict_policy: ConflictPolicy,
+    late_grace: i64,
+    late_upsert: bool,
+    /// Packets of archived intervals still within the late grace window,
+    /// keyed by interval end
+    recent: BTreeMap<i64, Vec<WeatherPacket>>,
+    stats: Arc<ArchiveStats>,
+    wal: Option<Wal>,
+    /// Fields the archive table has no column for, already logged
+    unknown_fields: HashSet<String>,
+}
+
+/// Default seconds past an interval's end before it is archived, as WeeWX's
+/// `archive_delay`
+pub const DEFAULT_ARCHIVE_DELAY: i64 = 15;
+
+/// Default seconds an archived interval still takes late packets
+pub const DEFAULT_LATE_GRACE: i64 = 300;
+
+/// What became of a packet for an interval already archived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatePacket {
+    /// Rolled into the interval's record, which was rewritten
+    Merged,
+    /// Within the grace window, but late upserts are disabled
+    Dropped,
+    /// Older than the grace window
+    TooOld,
+}
+
+impl LatePacket {
+    pub const ALL: [LatePacket; 3] = [Self::Merged, Self::Dropped, Self::TooOld];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Merged => "merged",
+            Self::Dropped => "dropped",
+            Self::TooOld => "too_old",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Counters of late packets, shared with whoever reports them
+#[derive(Debug, Default)]
+pub struct ArchiveStats {
+    late: [AtomicU64; 3],
+}
+
+impl ArchiveStats {
+    /// Late packets with the given outcome
+    pub fn late(&self, outcome: LatePacket) -> u64 {
+        self.late[outcome.index()].load(Ordering::Relaxed)
+    }
+
+    fn record(&self, outcome: LatePacket) {
+        self.late[outcome.index()].fetch_add(1, Ordering::Relaxed);
+    }
 }
 
 impl IntervalAggregator {
@@ -20,93 +99,423 @@ impl IntervalAggregator {
         Self {
             interval,
             unit_system,
+            rollup: RollupOptions::default(),
             buffer: PacketBuffer::new(interval),
             db_client,
+            summaries: DailySummaries::new(Tz::UTC),
+            summary_tables_ready: false,
+            archive_delay: DEFAULT_ARCHIVE_DELAY,
+            gap_markers: false,
+            next_close: None,
+            last_closed: None,
+            resumed_through: None,
+            conflict_policy: ConflictPolicy::default(),
+            late_grace: DEFAULT_LATE_GRACE,
+            late_upsert: false,
+            recent: BTreeMap::new(),
+            stats: Arc::default(),
+            wal: None,
+            unknown_fields: HashSet::new(),
+        }
+    }
+
+    /// Pick up after the newest record the archive already has: intervals up
+    /// to it count as archived, so a restart does not write them again.
+    /// Call before adding packets. Returns that record's timestamp.
+    pub async fn resume(&mut self) -> ArchiveResult<Option<i64>> {
+        let latest = self
+            .db_client
+            .get_latest_archive()
+            .await?
+            .map(|r| r.date_time);
+        if let Some(latest) = latest.filter(|t| Some(*t) > self.last_closed) {
+            self.last_closed = Some(latest);
+            self.resumed_through = Some(latest);
+            info!("Resuming after archive record at {}", latest);
+        }
+        Ok(latest)
+    }
+
+    /// What to do with an interval the archive already has a record for
+    /// (default: keep the existing record)
+    pub fn conflict_policy(mut self, policy: ConflictPolicy) -> Self {
+        self.conflict_policy = policy;
+        self
+    }
+
+    /// Log accepted packets to `wal` until their interval is archived. Call
+    /// [`replay_wal`](Self::replay_wal) before adding packets.
+    pub fn wal(mut self, wal: Wal) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    /// Rebuild the open intervals from packets a previous run left in the
+    /// WAL. Packets of intervals the archive already has are skipped. Returns
+    /// the number of packets restored.
+    pub async fn replay_wal(&mut self) -> ArchiveResult<usize> {
+        let Some(pending) = self.wal.as_mut().map(|wal| wal.take_pending()) else {
+            return Ok(0);
+        };
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        self.resume().await?;
+
+        let mut restored = 0;
+        for packet in pending {
+            let end = self.buffer.calculate_interval_end(packet.date_time);
+            if self.last_closed.is_some_and(|closed| end <= closed) {
+                continue;
+            }
+            self.buffer.add(packet)?;
+            self.next_close = Some(self.next_close.map_or(end, |next| next.min(end)));
+            restored += 1;
         }
+        self.sync_wal().await;
+        info!("Restored {} packets from the WAL", restored);
+        Ok(restored)
     }
 
-    /// Add a weather packet to the aggregation buffer
+    /// Rewrite the WAL to hold just the buffered packets
+    async fn sync_wal(&mut self) {
+        let Some(wal) = self.wal.as_mut() else {
+            return;
+        };
+        // Committed records are safe either way; a stale WAL only means
+        // replaying packets the archive already has
+        if let Err(e) = wal.reset(self.buffer.packets()).await {
+            warn!(error = %e, wal = %wal.path().display(), "failed to truncate WAL");
+        }
+    }
+
+    /// Seconds (of packet time, behind the newest archived interval) that an
+    /// archived interval still accepts late packets; older ones are rejected
+    pub fn late_grace(mut self, secs: i64) -> Self {
+        self.late_grace = secs.max(0);
+        self
+    }
+
+    /// Roll late packets within the grace window into their interval's record
+    /// by rewriting it, instead of dropping them
+    pub fn late_upsert(mut self, enabled: bool) -> Self {
+        self.late_upsert = enabled;
+        self
+    }
+
+    /// Late packet counters
+    pub fn stats(&self) -> Arc<ArchiveStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Seconds past an interval's end to wait for its last packets before
+    /// archiving it
+    pub fn archive_delay(mut self, secs: i64) -> Self {
+        self.archive_delay = secs.max(0);
+        self
+    }
+
+    /// Archive intervals without packets as records with no observations
+    /// (gap markers), instead of skipping them
+    pub fn gap_markers(mut self, enabled: bool) -> Self {
+        self.gap_markers = enabled;
+        self
+    }
+
+    /// Station timezone, whose local days the daily summaries cover
+    pub fn timezone(mut self, tz: Tz) -> Self {
+        self.summaries = DailySummaries::new(tz);
+        self
+    }
+
+    /// Time-weight interval averages by packet timestamps, so irregular
+    /// packet spacing does not skew them
+    pub fn time_weighted(mut self, enabled: bool) -> Self {
+        self.rollup.time_weighted = enabled;
+        self
+    }
+
+    /// Per-observation aggregates, overriding the obs-type registry (e.g.
+    /// `outTemp` as [`AggregateType::Median`])
+    pub fn aggregates(mut self, aggregates: HashMap<String, AggregateType>) -> Self {
+        self.rollup.aggregates = aggregates;
+        self
+    }
+
+    /// Add a weather packet to the bucket of its interval. Intervals are
+    /// archived by [`close_due`](Self::close_due), not by the next packet.
     #[instrument(skip(self, packet))]
     pub async fn add_packet(&mut self, packet: WeatherPacket) -> ArchiveResult<()> {
-        let interval_end = self.buffer.add(packet)?;
+        let packet_time = packet.date_time;
+        let end = self.buffer.calculate_interval_end(packet_time);
+        if let Some(last_closed) = self.last_closed.filter(|closed| end <= *closed) {
+            return self.add_late_packet(packet, end, last_closed).await;
+        }
+
+        if self.buffer.is_full() {
+            // Rather than refuse packets, archive the oldest interval early
+            if let Some(oldest) = self.buffer.oldest_end() {
+                warn!(
+                    "Packet buffer full; archiving interval ending at {} early",
+                    oldest
+                );
+                self.close_through(oldest).await?;
+            }
+        }
 
-        // Check if interval is complete
-        if let Some(end_time) = interval_end {
-            self.flush_interval(end_time).await?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.append(&packet).await?;
         }
+        self.buffer.add(packet)?;
+        self.next_close = Some(self.next_close.map_or(end, |next| next.min(end)));
 
+        // A packet this far on shows the interval is over, even if the timer
+        // has not fired yet
+        self.close_due(packet_time).await
+    }
+
+    /// Handle a packet whose interval (ending `end`) is already archived
+    async fn add_late_packet(
+        &mut self,
+        packet: WeatherPacket,
+        end: i64,
+        last_closed: i64,
+    ) -> ArchiveResult<()> {
+        // An interval archived before startup has no packets to rebuild from
+        let resumed = self.resumed_through.is_some_and(|t| end <= t);
+        let outcome = if last_closed - end >= self.late_grace {
+            LatePacket::TooOld
+        } else if !self.late_upsert || (resumed && !self.recent.contains_key(&end)) {
+            LatePacket::Dropped
+        } else {
+            let mut packets = self.recent.remove(&end).unwrap_or_default();
+            packets.push(packet.clone());
+            let result = self.rewrite_interval(end, &packets).await;
+            self.recent.insert(end, packets);
+            result?;
+            LatePacket::Merged
+        };
+        debug!(
+            "Late packet at {} for interval ending at {}: {}",
+            packet.date_time,
+            end,
+            outcome.as_str()
+        );
+        self.stats.record(outcome);
         Ok(())
     }
 
-    /// Flush the current interval to database
-    #[instrument(skip(self))]
-    async fn flush_interval(&mut self, end_time: i64) -> ArchiveResult<()> {
-        let packets = self.buffer.drain();
+    /// Rewrite an archived interval's record from its (now larger) set of
+    /// packets, and recompute that day's summaries
+    async fn rewrite_interval(&mut self, end: i64, packets: &[WeatherPacket]) -> ArchiveResult<()> {
+        let record = self.aggregate_interval(end, packets);
+        self.write_record(&record, ConflictPolicy::Replace).await?;
+        info!("Archive record rewritten for timestamp {}", end);
+        self.rebuild_day(end).await;
+        Ok(())
+    }
+
+    /// Recompute the daily summaries of the day holding `date_time`, after
+    /// one of its records changed
+    async fn rebuild_day(&mut self, date_time: i64) {
+        let tz = self.summaries.timezone();
+        let day = daysummary::day_start(date_time, tz);
+        if let Err(e) = daysummary::rebuild_day(&self.db_client, tz, day).await {
+            warn!(error = %e, "failed to update daily summaries");
+        }
+        // Cached rows are reloaded from the rebuilt tables
+        self.summaries = DailySummaries::new(tz);
+    }
+
+    /// When the oldest open interval is due to be archived (its end plus the
+    /// archive delay), if any
+    pub fn next_close_time(&self) -> Option<i64> {
+        self.next_close.map(|end| end + self.archive_delay)
+    }
+
+    /// Archive every interval whose end plus the archive delay is at or
+    /// before `now`. Records are stamped with the interval end.
+    pub async fn close_due(&mut self, now: i64) -> ArchiveResult<()> {
+        self.close_through(now - self.archive_delay).await
+    }
+
+    /// Archive every open interval ending at or before `limit`
+    async fn close_through(&mut self, limit: i64) -> ArchiveResult<()> {
+        let mut closed = false;
+        while let Some(end) = self.next_close.filter(|end| *end <= limit) {
+            let packets = self.buffer.drain_through(end);
+            if packets.is_empty() {
+                self.write_gap(end).await?;
+            } else if let Err(e) = self.write_interval(end, &packets).await {
+                self.buffer.requeue(packets);
+                if closed {
+                    self.sync_wal().await;
+                }
+                return Err(e);
+            }
+            closed = true;
 
-        if packets.is_empty() {
+            self.last_closed = Some(end);
+            if self.late_upsert && !packets.is_empty() {
+                self.recent.insert(end, packets);
+            }
+            let horizon = end - self.late_grace;
+            self.recent.retain(|closed, _| *closed > horizon);
+
+            self.next_close = if self.gap_markers {
+                Some(end + self.interval as i64)
+            } else {
+                // Skip straight to the next interval with packets, if any
+                self.buffer.oldest_end()
+            };
+        }
+        if closed {
+            self.sync_wal().await;
+        }
+        Ok(())
+    }
+
+    /// Archive an interval without packets, if gap markers are enabled
+    async fn write_gap(&mut self, end_time: i64) -> ArchiveResult<()> {
+        if !self.gap_markers {
             debug!("No packets to flush for interval ending at {}", end_time);
             return Ok(());
         }
+        let marker =
+            build_archive_record(end_time, self.interval, self.unit_system, HashMap::new());
+        // A marker never overwrites a record
+        if self.write_record(&marker, ConflictPolicy::Skip).await? {
+            info!("Gap marker written for timestamp {}", end_time);
+        }
+        Ok(())
+    }
 
+    /// Aggregate one interval's packets and write its archive record
+    #[instrument(skip(self, packets))]
+    async fn write_interval(
+        &mut self,
+        end_time: i64,
+        packets: &[WeatherPacket],
+    ) -> ArchiveResult<()> {
         info!(
             "Flushing {} packets for interval ending at {}",
             packets.len(),
             end_time
         );
 
+        let record = self.aggregate_interval(end_time, packets);
+
+        // Write to database; an existing record is resolved by the conflict
+        // policy, and its day's summaries rebuilt rather than added to
+        if !self.write_record(&record, ConflictPolicy::Skip).await? {
+            if self.conflict_policy == ConflictPolicy::Skip {
+                info!(
+                    "Archive already has a record for timestamp {}; keeping it",
+                    end_time
+                );
+            } else {
+                self.write_record(&record, self.conflict_policy).await?;
+                info!(
+                    "Existing archive record for timestamp {} updated ({:?})",
+                    end_time, self.conflict_policy
+                );
+                self.rebuild_day(end_time).await;
+            }
+            return Ok(());
+        }
+
+        info!("Archive record written for timestamp {}", end_time);
+
+        // The archive record is safe; stale summaries can be rebuilt from it
+        if let Err(e) = self.update_daily_summaries(&record).await {
+            warn!(error = %e, "failed to update daily summaries");
+        }
+        Ok(())
+    }
+
+    /// Aggregate one interval's packets into its archive record
+    fn aggregate_interval(&self, end_time: i64, packets: &[WeatherPacket]) -> ArchiveRecord {
         // Aggregate all observations
-        let aggregates = aggregate_packets(&packets);
+        let mut aggregates = aggregate_packets_with(packets, &self.rollup);
 
-        // Convert to ArchiveRow
-        let archive_row = self.build_archive_row(end_time, aggregates);
+        // Wind run over the interval, unless the station reports it
+        let run = aggregates
+            .get("windSpeed")
+            .and_then(|(_, speed)| *speed)
+            .and_then(|speed| windrun(speed, self.interval as i64, self.unit_system));
+        if let Some(run) = run {
+            aggregates
+                .entry("windrun".to_string())
+                .or_insert((AggregateType::Sum, Some(run)));
+        }
 
-        // Write to database
-        self.db_client.insert_archive(&archive_row).await?;
+        build_archive_record(end_time, self.interval, self.unit_system, aggregates)
+    }
 
-        info!("Archive record written for timestamp {}", end_time);
+    /// Write a record into the archive table's columns, resolving an existing
+    /// one by `policy`; returns whether a row was written. Fields the table
+    /// has no column for are dropped, and logged the first time.
+    async fn write_record(
+        &mut self,
+        record: &ArchiveRecord,
+        policy: ConflictPolicy,
+    ) -> ArchiveResult<bool> {
+        let write = self.db_client.upsert_archive(record, policy).await?;
+        for field in write.dropped {
+            if !self.unknown_fields.contains(&field) {
+                warn!(
+                    field = %field,
+                    "archive table has no column for packet field; dropping it"
+                );
+                self.unknown_fields.insert(field);
+            }
+        }
+        Ok(write.written > 0)
+    }
+
+    /// Fold an archive record into the `archive_day_*` tables
+    async fn update_daily_summaries(&mut self, record: &ArchiveRecord) -> ArchiveResult<()> {
+        if !self.summary_tables_ready {
+            daysummary::create_daily_summaries(&self.db_client).await?;
+            self.summary_tables_ready = true;
+        }
+
+        // Only today's rows are cached; the first record of a day (or after a
+        // restart) picks up whatever the table already holds
+        let day = daysummary::day_start(record.date_time, self.summaries.timezone());
+        self.summaries.retain_day(day);
+        // Fields without an archive column have no summary table either
+        let columns = self.db_client.archive_columns().await?;
+        let mut types = DailySummaries::types_in(record);
+        types.retain(|obs| obs == tables::WIND_SUMMARY || columns.get(obs).is_some());
+        for obs in &types {
+            if self.summaries.get(obs, day).is_none() {
+                if let Some(row) = self.db_client.get_daily_summary(obs, day).await? {
+                    self.summaries.insert(obs, row);
+                }
+            }
+        }
+
+        self.summaries.add_record(record);
+        for obs in &types {
+            if let Some(row) = self.summaries.get(obs, day) {
+                self.db_client.put_daily_summary(obs, row).await?;
+            }
+        }
+        self.db_client
+            .set_daily_summary_last_update(record.date_time)
+            .await?;
         Ok(())
     }
 
-    /// Build an ArchiveRow from aggregated data
-    fn build_archive_row(
-        &self,
-        date_time: i64,
-        aggregates: HashMap<String, (weex_core::AggregateType, Option<f64>)>,
-    ) -> ArchiveRow {
-        let get_value =
-            |key: &str| -> Option<f64> { aggregates.get(key).and_then(|(_, val)| *val) };
-
-        ArchiveRow {
-            date_time,
-            us_units: self.unit_system,
-            interval: self.interval,
-            out_temp: get_value("outTemp"),
-            in_temp: get_value("inTemp"),
-            extra_temp1: get_value("extraTemp1"),
-            out_humidity: get_value("outHumidity"),
-            in_humidity: get_value("inHumidity"),
-            barometer: get_value("barometer"),
-            pressure: get_value("pressure"),
-            altimeter: get_value("altimeter"),
-            wind_speed: get_value("windSpeed"),
-            wind_dir: get_value("windDir"),
-            wind_gust: get_value("windGust"),
-            wind_gust_dir: get_value("windGustDir"),
-            rain: get_value("rain"),
-            rain_rate: get_value("rainRate"),
-            dewpoint: get_value("dewpoint"),
-            windchill: get_value("windchill"),
-            heatindex: get_value("heatindex"),
-            radiation: get_value("radiation"),
-            uv: get_value("UV"),
-            rx_check_percent: get_value("rxCheckPercent"),
-        }
-    }
-
-    /// Force flush current buffer (for shutdown)
+    /// Archive every buffered interval, including the open one (for
+    /// shutdown). Records are still stamped with their interval end.
     pub async fn force_flush(&mut self) -> ArchiveResult<()> {
-        let now = chrono::Utc::now().timestamp();
-        self.flush_interval(now).await
+        let Some(last) = self.buffer.last_time() else {
+            return Ok(());
+        };
+        let limit = self.buffer.calculate_interval_end(last);
+        self.close_through(limit).await
     }
 
     /// Get current interval setting
@@ -120,25 +529,165 @@ impl IntervalAggregator {
     }
 }
 
+/// Build an archive record from aggregated data. Observations are keyed by
+/// their archive column as the obs-type registry maps them; types the
+/// registry does not know keep their packet name, and are archived if the
+/// table has a column of that name.
+fn build_archive_record(
+    date_time: i64,
+    interval: i32,
+    us_units: i32,
+    aggregates: HashMap<String, (AggregateType, Option<f64>)>,
+) -> ArchiveRecord {
+    let registry = weex_core::obs_registry();
+    let aggregates = aggregates
+        .into_iter()
+        .filter_map(|(name, (_, val))| {
+            let column = match registry.get(&name) {
+                Some(t) => t.column.clone()?,
+                None => name,
+            };
+            Some((column, ObservationValue::Float(val?)))
+        })
+        .collect();
+    ArchiveRecord {
+        date_time,
+        interval,
+        us_units,
+        aggregates,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_build_archive_row() {
-        // Note: Full integration tests with DB are in tests/golden/
-        // This just validates structure construction
-        let mut aggregates = HashMap::new();
-        aggregates.insert(
-            "outTemp".to_string(),
-            (weex_core::AggregateType::Avg, Some(25.5)),
+    fn test_build_archive_record() {
+        // Full integration tests with DB are in tests/golden/
+        let aggregates = HashMap::from([
+            ("outTemp".to_string(), (AggregateType::Avg, Some(25.5))),
+            ("outHumidity".to_string(), (AggregateType::Avg, None)),
+            ("pm2_5".to_string(), (AggregateType::Avg, Some(12.0))),
+            (
+                "myCustomSensor".to_string(),
+                (AggregateType::Avg, Some(1.0)),
+            ),
+        ]);
+
+        let record = build_archive_record(300, 300, 16, aggregates);
+        assert_eq!(
+            (record.date_time, record.interval, record.us_units),
+            (300, 300, 16)
+        );
+        assert_eq!(
+            record.aggregates.get("outTemp"),
+            Some(&ObservationValue::Float(25.5))
+        );
+        assert!(!record.aggregates.contains_key("outHumidity"));
+        assert_eq!(
+            record.aggregates.get("pm2_5"),
+            Some(&ObservationValue::Float(12.0))
+        );
+        // Unknown types are passed through for the writer to place or drop
+        assert!(record.aggregates.contains_key("myCustomSensor"));
+    }
+
+    fn packet(ts: i64, out_temp: f64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: HashMap::from([
+                ("outTemp".to_string(), ObservationValue::Float(out_temp)),
+                ("soilMoist1".to_string(), ObservationValue::Float(30.0)),
+                ("unknownSensor".to_string(), ObservationValue::Float(1.0)),
+            ]),
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_intervals_on_sqlite() {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL, \
+             soilMoist1 REAL)",
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+        let mut aggregator = IntervalAggregator::new(300, 16, db.clone());
+        aggregator.add_packet(packet(100, 20.0)).await.unwrap();
+        aggregator.add_packet(packet(200, 22.0)).await.unwrap();
+        // A packet past the first interval's end plus the archive delay
+        // closes it
+        aggregator.add_packet(packet(400, 25.0)).await.unwrap();
+        assert_eq!(db.count_archive_records().await.unwrap(), 1);
+        aggregator.force_flush().await.unwrap();
+
+        let records = db.get_archive_records(0, i64::MAX).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].date_time, records[1].date_time), (300, 600));
+        assert_eq!(
+            records[0].aggregates.get("outTemp"),
+            Some(&ObservationValue::Float(21.0))
+        );
+        assert_eq!(
+            records[0].aggregates.get("soilMoist1"),
+            Some(&ObservationValue::Float(30.0))
+        );
+        assert!(aggregator.unknown_fields.contains("unknownSensor"));
+
+        let day = db.get_daily_summary("outTemp", 0).await.unwrap().unwrap();
+        assert_eq!(
+            (day.min, day.max, day.count),
+            (Some(21.0), Some(25.0), Some(2))
+        );
+    }
+
+    #[tokio::test]
+    async fn resumes_after_existing_records() {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL, \
+             soilMoist1 REAL)",
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+        let mut first = IntervalAggregator::new(300, 16, db.clone());
+        first.add_packet(packet(100, 20.0)).await.unwrap();
+        first.force_flush().await.unwrap();
+
+        // Without resuming, the existing record is kept (or merged) rather
+        // than failing the write
+        let mut again = IntervalAggregator::new(300, 16, db.clone());
+        again.add_packet(packet(200, 30.0)).await.unwrap();
+        again.force_flush().await.unwrap();
+        let outdoor = |records: Vec<ArchiveRecord>| records[0].aggregates["outTemp"].clone();
+        assert_eq!(
+            outdoor(db.get_archive_records(300, 300).await.unwrap()),
+            ObservationValue::Float(20.0)
         );
-        aggregates.insert(
-            "outHumidity".to_string(),
-            (weex_core::AggregateType::Avg, Some(65.0)),
+        let mut merging =
+            IntervalAggregator::new(300, 16, db.clone()).conflict_policy(ConflictPolicy::Merge);
+        merging.add_packet(packet(200, 30.0)).await.unwrap();
+        merging.force_flush().await.unwrap();
+        assert_eq!(
+            outdoor(db.get_archive_records(300, 300).await.unwrap()),
+            ObservationValue::Float(30.0)
         );
 
-        // Mock DB client would be needed for full test
-        // See golden tests for complete validation
+        // A resumed aggregator treats the archived interval as closed
+        let mut resumed = IntervalAggregator::new(300, 16, db.clone()).late_upsert(true);
+        assert_eq!(resumed.resume().await.unwrap(), Some(300));
+        resumed.add_packet(packet(250, 40.0)).await.unwrap();
+        assert_eq!(resumed.stats().late(LatePacket::Dropped), 1);
+        resumed.add_packet(packet(500, 25.0)).await.unwrap();
+        resumed.force_flush().await.unwrap();
+        assert_eq!(db.count_archive_records().await.unwrap(), 2);
     }
 }
@@ -1,14 +1,15 @@
 //! Packet buffering for interval management
 
 use crate::{ArchiveError, ArchiveResult};
-use std::collections::VecDeque;
+use std::collections::BTreeMap;
 use weex_core::WeatherPacket;
 
-/// Buffer for collecting packets within an interval
+/// Buffer for collecting packets, bucketed by the interval they belong to
 pub struct PacketBuffer {
     interval: i32,
-    packets: VecDeque<WeatherPacket>,
-    current_interval_end: Option<i64>,
+    /// Packets per interval, keyed by interval end
+    buckets: BTreeMap<i64, Vec<WeatherPacket>>,
+    len: usize,
     max_packets: usize,
 }
 
@@ -21,72 +22,99 @@ impl PacketBuffer {
 
         Self {
             interval,
-            packets: VecDeque::with_capacity(max_packets),
-            current_interval_end: None,
+            buckets: BTreeMap::new(),
+            len: 0,
             max_packets,
         }
     }
 
-    /// Add a packet to the buffer
+    /// Add a packet to the bucket of its interval
     ///
-    /// Returns Some(end_time) if the interval is complete and should be flushed
+    /// Returns Some(end_time) of the previously newest interval when this
+    /// packet is the first of a later one, i.e. that interval is complete
     pub fn add(&mut self, packet: WeatherPacket) -> ArchiveResult<Option<i64>> {
-        if self.packets.len() >= self.max_packets {
+        if self.is_full() {
             return Err(ArchiveError::BufferOverflow);
         }
 
-        let packet_time = packet.date_time;
-
-        // Determine interval end time
-        let interval_end = match self.current_interval_end {
-            Some(end) => end,
-            None => {
-                // First packet - calculate interval end
-                let end = self.calculate_interval_end(packet_time);
-                self.current_interval_end = Some(end);
-                end
-            }
-        };
-
-        // Check if packet belongs to current interval
-        if packet_time <= interval_end {
-            self.packets.push_back(packet);
-            Ok(None)
-        } else {
-            // Packet is in next interval - current interval is complete
-            self.packets.push_back(packet);
-            let completed_interval = self.current_interval_end;
-            self.current_interval_end = Some(self.calculate_interval_end(packet_time));
-            Ok(completed_interval)
-        }
+        let end = self.calculate_interval_end(packet.date_time);
+        let newest = self.interval_end();
+        self.buckets.entry(end).or_default().push(packet);
+        self.len += 1;
+
+        Ok(newest.filter(|newest| *newest < end))
     }
 
-    /// Calculate interval end time for a given timestamp
-    fn calculate_interval_end(&self, timestamp: i64) -> i64 {
+    /// End of the interval a timestamp belongs to. As in WeeWX, a record
+    /// stamped `end` covers `(end - interval, end]`.
+    pub fn calculate_interval_end(&self, timestamp: i64) -> i64 {
         let interval = self.interval as i64;
-        ((timestamp / interval) + 1) * interval
+        ((timestamp - 1).div_euclid(interval) + 1) * interval
     }
 
     /// Drain all packets from buffer (for flushing)
     pub fn drain(&mut self) -> Vec<WeatherPacket> {
-        let packets: Vec<_> = self.packets.drain(..).collect();
-        self.current_interval_end = None;
-        packets
+        self.len = 0;
+        std::mem::take(&mut self.buckets)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Take the packets of intervals ending at or before `end`, leaving later
+    /// intervals buffered
+    pub fn drain_through(&mut self, end: i64) -> Vec<WeatherPacket> {
+        let later = self.buckets.split_off(&(end + 1));
+        let through: Vec<_> = std::mem::replace(&mut self.buckets, later)
+            .into_values()
+            .flatten()
+            .collect();
+        self.len -= through.len();
+        through
+    }
+
+    /// Put packets taken by `drain_through` back, e.g. after a failed write
+    pub fn requeue(&mut self, packets: Vec<WeatherPacket>) {
+        for packet in packets {
+            let end = self.calculate_interval_end(packet.date_time);
+            self.buckets.entry(end).or_default().push(packet);
+            self.len += 1;
+        }
+    }
+
+    /// Timestamp of the newest buffered packet
+    pub fn last_time(&self) -> Option<i64> {
+        self.buckets.values().flatten().map(|p| p.date_time).max()
+    }
+
+    /// Buffered packets, oldest interval first
+    pub fn packets(&self) -> impl Iterator<Item = &WeatherPacket> {
+        self.buckets.values().flatten()
+    }
+
+    /// Whether another packet would overflow the buffer
+    pub fn is_full(&self) -> bool {
+        self.len >= self.max_packets
     }
 
     /// Get current packet count
     pub fn len(&self) -> usize {
-        self.packets.len()
+        self.len
     }
 
     /// Check if buffer is empty
     pub fn is_empty(&self) -> bool {
-        self.packets.is_empty()
+        self.len == 0
+    }
+
+    /// End of the oldest interval with buffered packets
+    pub fn oldest_end(&self) -> Option<i64> {
+        self.buckets.keys().next().copied()
     }
 
-    /// Get current interval end time
+    /// End of the newest interval with buffered packets
     pub fn interval_end(&self) -> Option<i64> {
-        self.current_interval_end
+        self.buckets.keys().next_back().copied()
     }
 }
 
@@ -135,6 +163,28 @@ mod tests {
         assert_eq!(result, Some(300)); // First interval should flush at 300
 
         assert_eq!(buffer.len(), 2); // Both packets still in buffer until drain
+
+        // The crossing packet stays with its own interval
+        let packets = buffer.drain_through(300);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].date_time, 100);
+        assert_eq!(buffer.interval_end(), Some(600));
+    }
+
+    #[test]
+    fn test_buffer_out_of_order() {
+        let mut buffer = PacketBuffer::new(300);
+
+        buffer.add(make_packet(400)).unwrap();
+        // An older packet goes to its own, earlier interval
+        assert_eq!(buffer.add(make_packet(250)).unwrap(), None);
+        assert_eq!(buffer.oldest_end(), Some(300));
+        assert_eq!(buffer.interval_end(), Some(600));
+        assert_eq!(buffer.last_time(), Some(400));
+
+        let packets = buffer.drain_through(300);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].date_time, 250);
     }
 
     #[test]
@@ -152,14 +202,35 @@ mod tests {
         assert_eq!(buffer.interval_end(), None);
     }
 
+    #[test]
+    fn test_buffer_drain_through() {
+        let mut buffer = PacketBuffer::new(300);
+
+        buffer.add(make_packet(100)).unwrap();
+        buffer.add(make_packet(300)).unwrap();
+        buffer.add(make_packet(310)).unwrap();
+
+        // The packet stamped 300 closes the first interval
+        let packets = buffer.drain_through(300);
+        assert_eq!(packets.len(), 2);
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.last_time(), Some(310));
+
+        buffer.requeue(packets);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.drain_through(600).len(), 3);
+        assert_eq!(buffer.interval_end(), None);
+    }
+
     #[test]
     fn test_calculate_interval_end() {
         let buffer = PacketBuffer::new(300);
 
-        assert_eq!(buffer.calculate_interval_end(0), 300);
+        assert_eq!(buffer.calculate_interval_end(0), 0);
+        assert_eq!(buffer.calculate_interval_end(1), 300);
         assert_eq!(buffer.calculate_interval_end(100), 300);
-        assert_eq!(buffer.calculate_interval_end(300), 600);
+        assert_eq!(buffer.calculate_interval_end(300), 300);
         assert_eq!(buffer.calculate_interval_end(301), 600);
-        assert_eq!(buffer.calculate_interval_end(600), 900);
+        assert_eq!(buffer.calculate_interval_end(600), 600);
     }
 }
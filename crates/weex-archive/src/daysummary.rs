@@ -0,0 +1,260 @@
+//! WeeWX daily summaries (`archive_day_<obs>` tables)
+//!
+//! Every archive record is folded into one row per observation for the local
+//! day it belongs to: min and max with their times, sum and count, and the
+//! time-weighted sum. Wind also gets a combined `wind` row, whose max is the
+//! strongest gust or speed (with its direction) and which carries the vector
+//! sums. Days follow the station timezone, and a record stamped exactly at
+//! midnight closes the previous day, as in WeeWX.
+
+use chrono_tz::Tz;
+use futures_util::TryStreamExt;
+use std::collections::HashMap;
+use tracing::info;
+use weex_core::ArchiveRecord;
+pub use weex_db::aggregate::day_start;
+use weex_db::{schema::tables, ArchiveColumns, ArchiveQuery, DailySummaryRow, DbClient};
+
+use crate::ArchiveResult;
+
+/// Every observation with a daily summary table: one per observation column
+/// of the archive table, plus `wind`
+pub fn summary_types(columns: &ArchiveColumns) -> Vec<String> {
+    let mut types: Vec<_> = columns.observations().map(str::to_string).collect();
+    types.push(tables::WIND_SUMMARY.to_string());
+    types
+}
+
+/// A numeric field of an archive record
+fn value(record: &ArchiveRecord, name: &str) -> Option<f64> {
+    record.aggregates.get(name).and_then(|v| v.as_f64())
+}
+
+fn has_wind(record: &ArchiveRecord) -> bool {
+    value(record, "windSpeed").is_some() || value(record, "windGust").is_some()
+}
+
+/// Daily summary rows being built up, keyed by observation and day start
+#[derive(Debug)]
+pub struct DailySummaries {
+    tz: Tz,
+    rows: HashMap<(String, i64), DailySummaryRow>,
+}
+
+impl DailySummaries {
+    pub fn new(tz: Tz) -> Self {
+        Self {
+            tz,
+            rows: HashMap::new(),
+        }
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.tz
+    }
+
+    /// Summaries `record` contributes to; empty and non-numeric fields are
+    /// skipped
+    pub fn types_in(record: &ArchiveRecord) -> Vec<String> {
+        let mut types: Vec<_> = record
+            .aggregates
+            .iter()
+            .filter(|(_, v)| v.as_f64().is_some())
+            .map(|(n, _)| n.clone())
+            .collect();
+        types.sort();
+        if has_wind(record) {
+            types.push(tables::WIND_SUMMARY.to_string());
+        }
+        types
+    }
+
+    pub fn get(&self, obs: &str, day_start: i64) -> Option<&DailySummaryRow> {
+        self.rows.get(&(obs.to_string(), day_start))
+    }
+
+    /// Seed a row (e.g. loaded from the database) to fold records into
+    pub fn insert(&mut self, obs: &str, row: DailySummaryRow) {
+        self.rows.insert((obs.to_string(), row.date_time), row);
+    }
+
+    /// Forget every day but `day_start`
+    pub fn retain_day(&mut self, day_start: i64) {
+        self.rows.retain(|(_, day), _| *day == day_start);
+    }
+
+    /// Fold an archive record into its day. Each record is weighted by its
+    /// interval (seconds).
+    pub fn add_record(&mut self, record: &ArchiveRecord) {
+        let day = day_start(record.date_time, self.tz);
+        let weight = i64::from(record.interval);
+        for (obs, value) in &record.aggregates {
+            if let Some(value) = value.as_f64() {
+                self.row(obs, day)
+                    .add_value(value, record.date_time, weight);
+            }
+        }
+        if has_wind(record) {
+            self.row(tables::WIND_SUMMARY, day).add_wind(record, weight);
+        }
+    }
+
+    fn row(&mut self, obs: &str, day: i64) -> &mut DailySummaryRow {
+        self.rows
+            .entry((obs.to_string(), day))
+            .or_insert_with(|| DailySummaryRow {
+                date_time: day,
+                ..Default::default()
+            })
+    }
+
+    /// All rows, as (observation, row)
+    pub fn rows(&self) -> impl Iterator<Item = (&str, &DailySummaryRow)> {
+        self.rows.iter().map(|((obs, _), row)| (obs.as_str(), row))
+    }
+}
+
+/// Create any missing daily summary tables
+pub async fn create_daily_summaries(db: &DbClient) -> ArchiveResult<()> {
+    for obs in summary_types(&*db.archive_columns().await?) {
+        db.create_daily_summary(&obs).await?;
+    }
+    Ok(())
+}
+
+/// Recompute one day's summaries (the day starting at `day`) from its
+/// archive records
+pub async fn rebuild_day(db: &DbClient, tz: Tz, day: i64) -> ArchiveResult<()> {
+    // Some time into the next local day, whatever DST did to this one
+    let next_day = day_start(day + 26 * 3600, tz);
+    let records = db.get_archive_records(day + 1, next_day).await?;
+    let mut summaries = DailySummaries::new(tz);
+    for record in &records {
+        summaries.add_record(record);
+    }
+    for (obs, row) in summaries.rows() {
+        db.put_daily_summary(obs, row).await?;
+    }
+    Ok(())
+}
+
+/// Rebuild every daily summary from the archive table (like WeeWX's
+/// `wee_database --rebuild-daily`). The archive is streamed and each day's
+/// rows written once it is complete, so memory does not grow with the
+/// archive. Returns the number of archive records and of summary rows
+/// written.
+pub async fn rebuild_daily_summaries(db: &DbClient, tz: Tz) -> ArchiveResult<(usize, usize)> {
+    create_daily_summaries(db).await?;
+    for obs in summary_types(&*db.archive_columns().await?) {
+        db.clear_daily_summary(&obs).await?;
+    }
+
+    let mut records = std::pin::pin!(db.stream_archive(ArchiveQuery::new(i64::MIN, i64::MAX)));
+    let mut summaries = DailySummaries::new(tz);
+    let (mut count, mut written) = (0, 0);
+    let mut current = None;
+    let mut last = None;
+    while let Some(record) = records.try_next().await? {
+        // Records come in time order, so a new day completes the last one
+        let day = day_start(record.date_time, tz);
+        if current.is_some_and(|current| current != day) {
+            written += put_rows(db, &summaries).await?;
+            summaries.retain_day(day);
+        }
+        current = Some(day);
+        summaries.add_record(&record);
+        count += 1;
+        last = Some(record.date_time);
+    }
+    written += put_rows(db, &summaries).await?;
+    if let Some(last) = last {
+        db.set_daily_summary_last_update(last).await?;
+    }
+
+    info!(records = count, rows = written, "daily summaries rebuilt");
+    Ok((count, written))
+}
+
+async fn put_rows(db: &DbClient, summaries: &DailySummaries) -> ArchiveResult<usize> {
+    let mut written = 0;
+    for (obs, row) in summaries.rows() {
+        db.put_daily_summary(obs, row).await?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weex_core::ObservationValue;
+
+    fn record(date_time: i64, out_temp: f64, wind: (f64, f64, f64, f64)) -> ArchiveRecord {
+        let fields = [
+            ("outTemp", out_temp),
+            ("windSpeed", wind.0),
+            ("windDir", wind.1),
+            ("windGust", wind.2),
+            ("windGustDir", wind.3),
+        ];
+        ArchiveRecord {
+            date_time,
+            us_units: 17,
+            interval: 300,
+            aggregates: fields
+                .into_iter()
+                .map(|(n, v)| (n.to_string(), ObservationValue::Float(v)))
+                .chain([("soilMoist1".to_string(), ObservationValue::Null)])
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn day_start_follows_station_timezone() {
+        let chicago: Tz = "America/Chicago".parse().unwrap();
+        // 2024-07-01 00:00 CDT = 05:00 UTC
+        let midnight = 1_719_810_000;
+        assert_eq!(day_start(midnight + 300, chicago), midnight);
+        // A record stamped at midnight closes the previous day
+        assert_eq!(day_start(midnight, chicago), midnight - 86_400);
+        assert_eq!(day_start(midnight + 300, Tz::UTC), 1_719_792_000);
+    }
+
+    #[test]
+    fn folds_records_into_daily_rows() {
+        let day = 1_719_792_000;
+        let mut summaries = DailySummaries::new(Tz::UTC);
+        summaries.add_record(&record(day + 300, 20.0, (2.0, 90.0, 5.0, 80.0)));
+        summaries.add_record(&record(day + 600, 24.0, (4.0, 90.0, 9.0, 100.0)));
+        summaries.add_record(&record(day + 900, 18.0, (0.0, 0.0, 3.0, 270.0)));
+
+        let t = summaries.get("outTemp", day).unwrap();
+        assert_eq!((t.min, t.mintime), (Some(18.0), Some(day + 900)));
+        assert_eq!((t.max, t.maxtime), (Some(24.0), Some(day + 600)));
+        assert_eq!((t.sum, t.count), (Some(62.0), Some(3)));
+        assert_eq!((t.wsum, t.sumtime), (Some(62.0 * 300.0), Some(900)));
+        assert_eq!(t.xsum, None);
+        assert!(summaries.get("soilMoist1", day).is_none());
+
+        let w = summaries.get("wind", day).unwrap();
+        assert_eq!(
+            (w.max, w.max_dir, w.maxtime),
+            (Some(9.0), Some(100.0), Some(day + 600))
+        );
+        assert_eq!(w.min, Some(0.0));
+        assert_eq!((w.sum, w.count), (Some(6.0), Some(3)));
+        assert!((w.xsum.unwrap() - 6.0 * 300.0).abs() < 1e-6);
+        assert!(w.ysum.unwrap().abs() < 1e-6);
+        assert_eq!(w.dirsumtime, Some(900));
+        assert_eq!(w.squaresum, Some(20.0));
+
+        // The next day starts a new row
+        summaries.add_record(&record(day + 86_400 + 300, 10.0, (1.0, 0.0, 1.0, 0.0)));
+        summaries.retain_day(day + 86_400);
+        assert!(summaries.get("outTemp", day).is_none());
+        assert_eq!(
+            summaries.get("outTemp", day + 86_400).unwrap().count,
+            Some(1)
+        );
+    }
+}
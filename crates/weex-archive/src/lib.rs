@@ -1,13 +1,19 @@
 //! Archive interval aggregator
 //!
 //! Accumulates weather packets over configured intervals and
-//! generates archive records for database storage.
+//! generates archive records and daily summaries for database storage.
 
 pub mod aggregator;
 pub mod buffer;
+pub mod daysummary;
+pub mod sink;
+pub mod wal;
 
 pub use aggregator::*;
 pub use buffer::*;
+pub use daysummary::{rebuild_daily_summaries, DailySummaries};
+pub use sink::*;
+pub use wal::{FsyncPolicy, Wal};
 
 use thiserror::Error;
 
@@ -24,6 +30,9 @@ pub enum ArchiveError {
 
     #[error("Buffer overflow")]
     BufferOverflow,
+
+    #[error("WAL error: {0}")]
+    WalError(#[from] std::io::Error),
 }
 
 pub type ArchiveResult<T> = Result<T, ArchiveError>;
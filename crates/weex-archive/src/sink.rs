@@ -0,0 +1,44 @@
+//! Pipeline `Sink` that archives packets through an `IntervalAggregator`
+
+use anyhow::Result;
+use std::sync::Arc;
+use weex_core::{Sink, WeatherPacket};
+
+use crate::{ArchiveStats, IntervalAggregator};
+
+/// Feeds every packet to the aggregator and closes intervals on the clock,
+/// once their end plus the archive delay has passed. The open interval is
+/// only written early on `close`, when the pipeline shuts down.
+pub struct ArchiveSink {
+    aggregator: IntervalAggregator,
+}
+
+impl ArchiveSink {
+    pub fn new(aggregator: IntervalAggregator) -> Self {
+        Self { aggregator }
+    }
+
+    /// Late packet counters of the aggregator
+    pub fn stats(&self) -> Arc<ArchiveStats> {
+        self.aggregator.stats()
+    }
+}
+
+#[async_trait::async_trait]
+impl Sink for ArchiveSink {
+    async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+        Ok(self.aggregator.add_packet(packet.clone()).await?)
+    }
+
+    fn next_tick(&self) -> Option<i64> {
+        self.aggregator.next_close_time()
+    }
+
+    async fn tick(&mut self, now: i64) -> Result<()> {
+        Ok(self.aggregator.close_due(now).await?)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        Ok(self.aggregator.force_flush().await?)
+    }
+}
@@ -0,0 +1,288 @@
+//! Write-ahead log for packets of intervals not yet archived
+//!
+//! Every packet the aggregator accepts is appended to the log (one JSON packet
+//! per line) before it is buffered. On startup the log is replayed to rebuild
+//! the open intervals, so a crash loses nothing that was logged. Once a record
+//! is committed the log is rewritten to hold only the packets still buffered.
+//! A torn last line (a crash mid-append) is skipped on replay.
+//!
+//! Appends and rewrites run on Tokio's blocking pool, so an fsync per packet
+//! does not hold up the runtime's workers.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+use weex_core::WeatherPacket;
+
+use crate::ArchiveResult;
+
+/// When appends are fsynced to disk
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every packet: nothing logged is lost, at one fsync per packet
+    #[default]
+    Always,
+    /// At most once per period: a crash loses at most that much
+    Interval(Duration),
+    /// Left to the OS: survives a process crash, not a power loss
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    /// `always`, `never`, or a period in seconds such as `5s`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            other => other
+                .strip_suffix('s')
+                .and_then(|n| n.parse::<u64>().ok())
+                .map(|n| Self::Interval(Duration::from_secs(n)))
+                .ok_or_else(|| {
+                    format!(
+                        "invalid fsync policy '{}': expected always, never or e.g. 5s",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+/// Append-only log of accepted packets
+pub struct Wal {
+    path: PathBuf,
+    file: Arc<File>,
+    policy: FsyncPolicy,
+    last_sync: Instant,
+    /// Packets read at open, not yet handed to the aggregator
+    pending: Vec<WeatherPacket>,
+}
+
+impl Wal {
+    /// Open (or create) the log at `path`, reading any packets left by a
+    /// previous run
+    pub fn open(path: impl AsRef<Path>, policy: FsyncPolicy) -> ArchiveResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let (pending, clean) = read_packets(&path)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        if !clean {
+            // Drop the torn line, so the next append starts on a line of its own
+            if let Some(rewritten) = rewrite(&path, &file, encode_all(&pending)?)? {
+                file = rewritten;
+            }
+        }
+        Ok(Self {
+            path,
+            file: Arc::new(file),
+            policy,
+            last_sync: Instant::now(),
+            pending,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Packets left by the previous run, in log order; empty after the first
+    /// call
+    pub fn take_pending(&mut self) -> Vec<WeatherPacket> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Log one packet
+    pub async fn append(&mut self, packet: &WeatherPacket) -> ArchiveResult<()> {
+        let mut line = encode(packet)?;
+        line.push('\n');
+        let due = match self.policy {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval(period) => self.last_sync.elapsed() >= period,
+            FsyncPolicy::Never => false,
+        };
+        let file = Arc::clone(&self.file);
+        blocking(move || {
+            (&*file).write_all(line.as_bytes())?;
+            if due {
+                file.sync_data()?;
+            }
+            Ok(())
+        })
+        .await?;
+        if due {
+            self.last_sync = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Replace the log with `packets` (those still buffered) once a record
+    /// has been committed. The new log is written aside and renamed over the
+    /// old one, so a crash leaves one or the other.
+    pub async fn reset<'a>(
+        &mut self,
+        packets: impl IntoIterator<Item = &'a WeatherPacket>,
+    ) -> ArchiveResult<()> {
+        let lines = encode_all(packets)?;
+        let path = self.path.clone();
+        let file = Arc::clone(&self.file);
+        if let Some(rewritten) = blocking(move || rewrite(&path, &file, lines)).await? {
+            self.file = Arc::new(rewritten);
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+fn encode(packet: &WeatherPacket) -> std::io::Result<String> {
+    serde_json::to_string(packet)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn encode_all<'a>(packets: impl IntoIterator<Item = &'a WeatherPacket>) -> std::io::Result<String> {
+    let mut lines = String::new();
+    for packet in packets {
+        lines.push_str(&encode(packet)?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+/// Run file I/O on the blocking pool
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> std::io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
+}
+
+/// Make the log at `path` (open as `file`) hold just `lines`. Returns the
+/// file to append to from now on if it was replaced.
+fn rewrite(path: &Path, file: &File, lines: String) -> std::io::Result<Option<File>> {
+    if lines.is_empty() {
+        file.set_len(0)?;
+        file.sync_all()?;
+        return Ok(None);
+    }
+    let tmp = path.with_extension("tmp");
+    {
+        let mut out = File::create(&tmp)?;
+        out.write_all(lines.as_bytes())?;
+        out.sync_all()?;
+    }
+    fs::rename(&tmp, path)?;
+    // The rename itself is only durable once the directory is synced
+    sync_dir(path)?;
+    Ok(Some(OpenOptions::new().append(true).open(path)?))
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Packets in the log, and whether every line was readable
+fn read_packets(path: &Path) -> ArchiveResult<(Vec<WeatherPacket>, bool)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), true)),
+        Err(e) => return Err(e.into()),
+    };
+    let mut packets = Vec::new();
+    let mut clean = true;
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(packet) => packets.push(packet),
+            Err(e) => {
+                warn!(wal = %path.display(), error = %e, "skipping unreadable WAL line");
+                clean = false;
+            }
+        }
+    }
+    Ok((packets, clean))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use weex_core::ObservationValue;
+
+    fn packet(ts: i64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: HashMap::from([("outTemp".to_string(), ObservationValue::Float(20.5))]),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_after_restart_and_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.wal");
+
+        let mut wal = Wal::open(&path, FsyncPolicy::Always).unwrap();
+        assert!(wal.take_pending().is_empty());
+        for ts in [100, 200, 400] {
+            wal.append(&packet(ts)).await.unwrap();
+        }
+        drop(wal);
+
+        // A crash mid-append leaves a torn line
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"dateTime\": 5").unwrap();
+        drop(file);
+
+        let mut wal = Wal::open(&path, FsyncPolicy::Never).unwrap();
+        let replayed = wal.take_pending();
+        assert_eq!(replayed, vec![packet(100), packet(200), packet(400)]);
+        wal.append(&packet(420)).await.unwrap();
+        let mut wal = Wal::open(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(wal.take_pending().len(), 4);
+
+        // The first interval is committed; only the later packets remain
+        wal.reset(&[packet(400)]).await.unwrap();
+        wal.append(&packet(450)).await.unwrap();
+        let mut wal = Wal::open(&path, FsyncPolicy::Never).unwrap();
+        assert_eq!(wal.take_pending(), vec![packet(400), packet(450)]);
+
+        wal.reset(&[]).await.unwrap();
+        assert!(Wal::open(&path, FsyncPolicy::Never)
+            .unwrap()
+            .take_pending()
+            .is_empty());
+    }
+
+    #[test]
+    fn parses_fsync_policy() {
+        assert_eq!("always".parse(), Ok(FsyncPolicy::Always));
+        assert_eq!("Never".parse(), Ok(FsyncPolicy::Never));
+        assert_eq!(
+            "5s".parse(),
+            Ok(FsyncPolicy::Interval(Duration::from_secs(5)))
+        );
+        assert!("sometimes".parse::<FsyncPolicy>().is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Derived observations (WeeWX `StdWXCalculate`)
+//!
+//! A pipeline [`Processor`] that fills in dewpoint, heat index, wind chill,
+//! humidex, apparent temperature, cloud base, altimeter and barometer from
+//! the observations a station does report. Whether a field comes from the
+//! hardware or is always calculated is decided per field by a [`Policy`].
+
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::pipeline::Processor;
+use crate::types::{unit_systems, ObservationValue, WeatherPacket};
+use crate::units::{convert, get_unit_group, UnitGroup};
+use crate::wxformulas::{self, AltimeterAlgorithm};
+
+/// Fields this processor knows how to derive
+pub const DERIVED_FIELDS: &[&str] = &[
+    "dewpoint",
+    "heatindex",
+    "windchill",
+    "humidex",
+    "appTemp",
+    "cloudbase",
+    "altimeter",
+    "barometer",
+];
+
+/// How long outTemp history is kept for the barometer's 12h temperature
+const TEMP_12H_SECS: i64 = 12 * 3600;
+
+/// How far a sample may be from exactly 12h ago and still be used
+const TEMP_12H_MAX_DELTA: i64 = 1800;
+
+/// Minimum spacing between retained outTemp samples
+const TEMP_SAMPLE_SPACING: i64 = 60;
+
+/// Where a derived field comes from (WeeWX `[[Calculations]]` directives)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    /// Use the station's value when it sends one, otherwise calculate
+    #[default]
+    PreferHardware,
+    /// Always calculate, overwriting any station value
+    Software,
+    /// Never calculate
+    Hardware,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefer_hardware" => Ok(Self::PreferHardware),
+            "software" => Ok(Self::Software),
+            "hardware" => Ok(Self::Hardware),
+            _ => Err(format!("unknown calculation policy: {}", s)),
+        }
+    }
+}
+
+/// Configuration for [`StdWxCalculate`]
+#[derive(Debug, Clone)]
+pub struct WxCalculateOptions {
+    /// Station altitude above sea level (meters)
+    pub altitude_m: f64,
+    /// Unit system assumed for packets without a `usUnits` field
+    pub unit_system: i32,
+    pub altimeter_algorithm: AltimeterAlgorithm,
+    /// Per-field overrides; fields not listed use [`Policy::PreferHardware`]
+    pub policies: HashMap<String, Policy>,
+}
+
+impl Default for WxCalculateOptions {
+    fn default() -> Self {
+        Self {
+            altitude_m: 0.0,
+            unit_system: unit_systems::METRICWX,
+            altimeter_algorithm: AltimeterAlgorithm::default(),
+            policies: HashMap::new(),
+        }
+    }
+}
+
+/// Station inputs in METRICWX units
+#[derive(Clone, Copy)]
+struct Inputs {
+    out_temp: Option<f64>,
+    out_temp_12h: Option<f64>,
+    humidity: Option<f64>,
+    wind_speed: Option<f64>,
+    pressure: Option<f64>,
+}
+
+/// Calculates derived observations on every packet
+pub struct StdWxCalculate {
+    opts: WxCalculateOptions,
+    /// Recent (timestamp, outTemp degC) samples, oldest first
+    temp_history: Mutex<VecDeque<(i64, f64)>>,
+}
+
+impl StdWxCalculate {
+    pub fn new(opts: WxCalculateOptions) -> Self {
+        Self {
+            opts,
+            temp_history: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The policy in effect for `field`
+    pub fn policy(&self, field: &str) -> Policy {
+        self.opts.policies.get(field).copied().unwrap_or_default()
+    }
+
+    /// Record the current outTemp and return the one from about 12h ago, if
+    /// held. Without history (e.g. after a restart) the barometer falls back
+    /// to the current temperature rather than going missing for 12 hours.
+    fn temp_12h_ago(&self, ts: i64, t_c: f64) -> f64 {
+        let Ok(mut history) = self.temp_history.lock() else {
+            return t_c;
+        };
+        while history
+            .front()
+            .is_some_and(|(t, _)| *t < ts - TEMP_12H_SECS - TEMP_12H_MAX_DELTA)
+        {
+            history.pop_front();
+        }
+        if history
+            .back()
+            .map_or(true, |(t, _)| ts - *t >= TEMP_SAMPLE_SPACING)
+        {
+            history.push_back((ts, t_c));
+        }
+        let target = ts - TEMP_12H_SECS;
+        history
+            .iter()
+            .filter(|(t, _)| (t - target).abs() <= TEMP_12H_MAX_DELTA)
+            .min_by_key(|(t, _)| (t - target).abs())
+            .map_or(t_c, |(_, v)| *v)
+    }
+
+    fn calculate(&self, field: &str, inputs: &Inputs) -> Option<Option<f64>> {
+        let Inputs {
+            out_temp,
+            out_temp_12h,
+            humidity,
+            wind_speed,
+            pressure,
+        } = *inputs;
+        let alt = self.opts.altitude_m;
+        Some(match field {
+            "dewpoint" => wxformulas::dewpoint_c(out_temp?, humidity?),
+            "heatindex" => Some(wxformulas::heatindex_c(out_temp?, humidity?)),
+            "windchill" => Some(wxformulas::windchill_c(out_temp?, wind_speed? * 3.6)),
+            "humidex" => wxformulas::humidex_c(out_temp?, humidity?),
+            "appTemp" => Some(wxformulas::apptemp_c(out_temp?, humidity?, wind_speed?)),
+            "cloudbase" => wxformulas::cloudbase_m(out_temp?, humidity?, alt),
+            "altimeter" => Some(wxformulas::altimeter_hpa(
+                pressure?,
+                alt,
+                self.opts.altimeter_algorithm,
+            )),
+            "barometer" => Some(wxformulas::sealevel_pressure_hpa(
+                pressure?,
+                alt,
+                out_temp?,
+                out_temp_12h?,
+            )),
+            _ => return None,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Processor for StdWxCalculate {
+    async fn process(&self, mut packet: WeatherPacket) -> Result<WeatherPacket> {
+        let us = packet.unit_system().unwrap_or(self.opts.unit_system);
+        if let Err(e) = UnitGroup::Temperature.unit_for(us) {
+            tracing::warn!(error = %e, "skipping derived observations");
+            return Ok(packet);
+        }
+
+        // Formulas take METRICWX units: degC, %, m/s, hPa and meters
+        let get = |key: &str| {
+            let value = packet.observations.get(key)?.as_f64()?;
+            match get_unit_group(key) {
+                Some(group) => convert(value, us, unit_systems::METRICWX, group).ok(),
+                None => Some(value),
+            }
+        };
+        let out_temp = get("outTemp");
+        let inputs = Inputs {
+            out_temp,
+            out_temp_12h: out_temp.map(|t| self.temp_12h_ago(packet.date_time, t)),
+            humidity: get("outHumidity"),
+            wind_speed: get("windSpeed"),
+            pressure: get("pressure"),
+        };
+
+        for &field in DERIVED_FIELDS {
+            match self.policy(field) {
+                Policy::Hardware => continue,
+                Policy::PreferHardware
+                    if packet.observations.get(field).is_some_and(|v| !v.is_null()) =>
+                {
+                    continue
+                }
+                _ => {}
+            }
+            let Some(value) = self.calculate(field, &inputs) else {
+                continue;
+            };
+            let value = match (value, get_unit_group(field)) {
+                (Some(v), Some(group)) => convert(v, unit_systems::METRICWX, us, group)
+                    .map_or(ObservationValue::Null, ObservationValue::Float),
+                (Some(v), None) => ObservationValue::Float(v),
+                (None, _) => ObservationValue::Null,
+            };
+            packet.observations.insert(field.to_string(), value);
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(ts: i64, obs: &[(&str, f64)]) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: obs
+                .iter()
+                .map(|(k, v)| (k.to_string(), ObservationValue::Float(*v)))
+                .collect(),
+        }
+    }
+
+    fn value(packet: &WeatherPacket, key: &str) -> f64 {
+        packet.observations[key].as_f64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn derives_missing_fields_in_packet_units() {
+        let calc = StdWxCalculate::new(WxCalculateOptions {
+            altitude_m: 1000.0,
+            ..Default::default()
+        });
+        let out = calc
+            .process(packet(
+                0,
+                &[
+                    ("outTemp", 20.0),
+                    ("outHumidity", 50.0),
+                    ("windSpeed", 2.0),
+                    ("pressure", 948.08),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert!((value(&out, "dewpoint") - 9.25).abs() < 0.01);
+        assert!((value(&out, "altimeter") - 1067.59).abs() < 0.01);
+        assert!((value(&out, "barometer") - 1063.80).abs() < 0.01);
+        assert_eq!(value(&out, "windchill"), 20.0);
+
+        // Same station reporting in US units
+        let out = calc
+            .process(packet(
+                0,
+                &[
+                    ("usUnits", 1.0),
+                    ("outTemp", 68.0),
+                    ("outHumidity", 50.0),
+                    ("pressure", 948.08 / 33.8639),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert!((value(&out, "dewpoint") - 48.7).abs() < 0.05);
+        assert!((value(&out, "altimeter") - 1067.59 / 33.8639).abs() < 0.001);
+        assert!(!out.observations.contains_key("windchill"));
+    }
+
+    #[tokio::test]
+    async fn policy_decides_between_hardware_and_software() {
+        let obs = [("outTemp", 20.0), ("outHumidity", 50.0), ("dewpoint", 1.0)];
+
+        let calc = StdWxCalculate::new(WxCalculateOptions::default());
+        let out = calc.process(packet(0, &obs)).await.unwrap();
+        assert_eq!(value(&out, "dewpoint"), 1.0);
+
+        let calc = StdWxCalculate::new(WxCalculateOptions {
+            policies: HashMap::from([
+                ("dewpoint".to_string(), "software".parse().unwrap()),
+                ("heatindex".to_string(), Policy::Hardware),
+            ]),
+            ..Default::default()
+        });
+        let out = calc.process(packet(0, &obs)).await.unwrap();
+        assert!((value(&out, "dewpoint") - 9.25).abs() < 0.01);
+        assert!(!out.observations.contains_key("heatindex"));
+        assert!(out.observations.contains_key("humidex"));
+    }
+
+    #[tokio::test]
+    async fn barometer_uses_temperature_from_12h_ago() {
+        let calc = StdWxCalculate::new(WxCalculateOptions {
+            altitude_m: 1000.0,
+            ..Default::default()
+        });
+        calc.process(packet(0, &[("outTemp", 0.0), ("pressure", 948.08)]))
+            .await
+            .unwrap();
+        let out = calc
+            .process(packet(
+                TEMP_12H_SECS,
+                &[("outTemp", 20.0), ("pressure", 948.08)],
+            ))
+            .await
+            .unwrap();
+        let expected = wxformulas::sealevel_pressure_hpa(948.08, 1000.0, 20.0, 0.0);
+        assert!((value(&out, "barometer") - expected).abs() < 1e-9);
+    }
+}
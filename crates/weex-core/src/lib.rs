@@ -3,12 +3,23 @@
 //! This crate provides the fundamental data structures and operations
 //! for weather data processing, maintaining strict parity with Python WeeWX.
 
+pub mod derived;
+pub mod obstypes;
 pub mod pipeline;
+pub mod qc;
+pub mod rain;
 pub mod rollups;
+pub mod store;
 pub mod types;
 pub mod units;
+pub mod wxformulas;
 
+pub use derived::*;
+pub use obstypes::*;
 pub use pipeline::*;
+pub use qc::*;
+pub use rain::*;
 pub use rollups::*;
+pub use store::*;
 pub use types::*;
 pub use units::*;
@@ -0,0 +1,422 @@
+//! Observation-type registry
+//!
+//! The one place that knows, for each observation type, its unit group,
+//! default archive aggregation, valid range, archive column and the aliases
+//! it arrives under (e.g. `uv` for `UV`). The built-in table follows the
+//! wview schema and WeeWX defaults; deployments can add or override types at
+//! startup with [`register_obs_type`].
+
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+
+use crate::types::{AggregateType, WeatherPacket};
+use crate::units::{convert_unit, Unit, UnitError, UnitGroup};
+
+/// Valid range for an observation, in `unit` (or unitless when `None`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValidRange {
+    pub min: f64,
+    pub max: f64,
+    pub unit: Option<Unit>,
+}
+
+impl ValidRange {
+    /// The range expressed in the unit `unit_system` uses for `group`
+    pub fn in_unit_system(
+        &self,
+        group: Option<UnitGroup>,
+        unit_system: i32,
+    ) -> Result<(f64, f64), UnitError> {
+        match (self.unit, group) {
+            (Some(unit), Some(group)) => {
+                let to = group.unit_for(unit_system)?;
+                Ok((
+                    convert_unit(self.min, unit, to)?,
+                    convert_unit(self.max, unit, to)?,
+                ))
+            }
+            _ => Ok((self.min, self.max)),
+        }
+    }
+}
+
+/// Everything known about one observation type
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObsType {
+    /// Canonical name, as used in packets and the archive (e.g. `outTemp`)
+    pub name: String,
+    pub group: Option<UnitGroup>,
+    /// How packets are reduced to an archive record
+    pub aggregate: AggregateType,
+    pub range: Option<ValidRange>,
+    /// Archive table column, if the type is archived
+    pub column: Option<String>,
+    /// Other names the type arrives under
+    pub aliases: Vec<String>,
+}
+
+impl ObsType {
+    /// A type with no unit group, aggregated by last value and archived in a
+    /// column of the same name
+    pub fn new(name: impl Into<String>) -> Self {
+        let name = name.into();
+        Self {
+            column: Some(name.clone()),
+            name,
+            group: None,
+            aggregate: AggregateType::Last,
+            range: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    pub fn group(mut self, group: UnitGroup) -> Self {
+        self.group = Some(group);
+        self
+    }
+
+    pub fn aggregate(mut self, aggregate: AggregateType) -> Self {
+        self.aggregate = aggregate;
+        self
+    }
+
+    pub fn range(mut self, min: f64, max: f64, unit: Option<Unit>) -> Self {
+        self.range = Some(ValidRange { min, max, unit });
+        self
+    }
+
+    pub fn alias(mut self, alias: impl Into<String>) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+}
+
+/// Lookup table of observation types by name or alias
+#[derive(Debug, Clone, Default)]
+pub struct ObsRegistry {
+    types: HashMap<String, ObsType>,
+    aliases: HashMap<String, String>,
+}
+
+impl ObsRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in wview types with WeeWX's default aggregation and QC ranges
+    pub fn builtin() -> Self {
+        use AggregateType::{Avg, GustDir, Max, Sum, VecDir};
+        use UnitGroup::*;
+
+        let mut registry = Self::new();
+        let temps = [
+            "inTemp",
+            "extraTemp1",
+            "extraTemp2",
+            "extraTemp3",
+            "dewpoint",
+            "heatindex",
+            "windchill",
+            "humidex",
+            "appTemp",
+        ];
+        for name in temps {
+            registry.register(ObsType::new(name).group(Temperature).aggregate(Avg));
+        }
+        let types = [
+            ObsType::new("outTemp")
+                .group(Temperature)
+                .aggregate(Avg)
+                .range(-40.0, 120.0, Some(Unit::DegreeF)),
+            ObsType::new("barometer")
+                .group(Pressure)
+                .aggregate(Avg)
+                .range(26.0, 32.5, Some(Unit::InHg)),
+            ObsType::new("pressure")
+                .group(Pressure)
+                .aggregate(Avg)
+                .range(24.0, 34.5, Some(Unit::InHg))
+                .alias("barometerAbs"),
+            ObsType::new("altimeter").group(Pressure).aggregate(Avg),
+            ObsType::new("outHumidity")
+                .group(Humidity)
+                .aggregate(Avg)
+                .range(0.0, 100.0, Some(Unit::Percent))
+                .alias("humidity"),
+            ObsType::new("inHumidity")
+                .group(Humidity)
+                .aggregate(Avg)
+                .range(0.0, 100.0, Some(Unit::Percent)),
+            ObsType::new("rxCheckPercent")
+                .group(Humidity)
+                .aggregate(Avg),
+            ObsType::new("windSpeed").group(Speed).aggregate(Avg).range(
+                0.0,
+                120.0,
+                Some(Unit::MilePerHour),
+            ),
+            ObsType::new("windGust").group(Speed).aggregate(Max),
+            ObsType::new("windDir").group(Direction).aggregate(VecDir),
+            ObsType::new("windGustDir")
+                .group(Direction)
+                .aggregate(GustDir),
+            ObsType::new("rain")
+                .group(Rain)
+                .aggregate(Sum)
+                .range(0.0, 10.0, Some(Unit::Inch)),
+            ObsType::new("dayRain").group(Rain).alias("dailyRain"),
+            ObsType::new("weekRain").group(Rain).alias("weeklyRain"),
+            ObsType::new("monthRain").group(Rain).alias("monthlyRain"),
+            ObsType::new("yearRain").group(Rain).alias("yearlyRain"),
+            ObsType::new("totalRain").group(Rain),
+            ObsType::new("rainRate").group(RainRate).aggregate(Avg),
+            ObsType::new("radiation")
+                .group(Radiation)
+                .aggregate(Avg)
+                .alias("solarRadiation"),
+            ObsType::new("UV").group(Uv).aggregate(Avg).alias("uv"),
+            ObsType::new("cloudbase").group(Altitude).aggregate(Avg),
+            ObsType::new("windrun").group(Distance).aggregate(Sum),
+        ];
+        for t in types {
+            registry.register(t);
+        }
+
+        // The rest of the wview_extended schema
+        for i in 4..=8 {
+            registry.register(
+                ObsType::new(format!("extraTemp{}", i))
+                    .group(Temperature)
+                    .aggregate(Avg),
+            );
+        }
+        for i in 1..=8 {
+            registry.register(
+                ObsType::new(format!("extraHumid{}", i))
+                    .group(Humidity)
+                    .aggregate(Avg),
+            );
+        }
+        for i in 1..=4 {
+            registry.register(
+                ObsType::new(format!("soilTemp{}", i))
+                    .group(Temperature)
+                    .aggregate(Avg),
+            );
+            registry.register(ObsType::new(format!("soilMoist{}", i)).aggregate(Avg));
+        }
+        for i in 1..=2 {
+            registry.register(
+                ObsType::new(format!("leafTemp{}", i))
+                    .group(Temperature)
+                    .aggregate(Avg),
+            );
+            registry.register(ObsType::new(format!("leafWet{}", i)).aggregate(Avg));
+        }
+        let averaged = [
+            "pm1_0",
+            "pm2_5",
+            "pm10_0",
+            "co",
+            "co2",
+            "nh3",
+            "no2",
+            "o3",
+            "so2",
+            "illuminance",
+            "noise",
+            "lightning_distance",
+            "heatingTemp",
+            "heatingVoltage",
+            "referenceVoltage",
+            "supplyVoltage",
+        ];
+        for name in averaged {
+            registry.register(ObsType::new(name).aggregate(Avg));
+        }
+        registry.register(ObsType::new("maxSolarRad").group(Radiation).aggregate(Avg));
+        registry.register(ObsType::new("ET").group(Rain).aggregate(Sum));
+        registry.register(ObsType::new("hail").group(Rain).aggregate(Sum));
+        registry.register(ObsType::new("snow").group(Rain).aggregate(Sum));
+        registry.register(ObsType::new("hailRate").group(RainRate).aggregate(Avg));
+        registry.register(ObsType::new("snowRate").group(RainRate).aggregate(Avg));
+        registry.register(ObsType::new("rainDur").aggregate(Sum));
+        registry.register(
+            ObsType::new("lightning_strike_count")
+                .group(Count)
+                .aggregate(Sum),
+        );
+        registry
+    }
+
+    /// Add a type, replacing any existing type of the same name
+    pub fn register(&mut self, obs_type: ObsType) {
+        if let Some(old) = self.types.remove(&obs_type.name) {
+            for alias in &old.aliases {
+                self.aliases.remove(alias);
+            }
+        }
+        for alias in &obs_type.aliases {
+            self.aliases.insert(alias.clone(), obs_type.name.clone());
+        }
+        self.types.insert(obs_type.name.clone(), obs_type);
+    }
+
+    /// Look up a type by canonical name or alias
+    pub fn get(&self, name: &str) -> Option<&ObsType> {
+        self.types.get(self.canonical(name))
+    }
+
+    /// The canonical name for `name`; unknown names are returned unchanged
+    pub fn canonical<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, String::as_str)
+    }
+
+    pub fn unit_group(&self, name: &str) -> Option<UnitGroup> {
+        self.get(name).and_then(|t| t.group)
+    }
+
+    /// Default archive aggregation (last value for unknown types)
+    pub fn aggregate_type(&self, name: &str) -> AggregateType {
+        self.get(name).map_or(AggregateType::Last, |t| t.aggregate)
+    }
+
+    /// Valid (min, max) in the units of `unit_system`, if a range is known
+    pub fn valid_range(&self, name: &str, unit_system: i32) -> Option<(f64, f64)> {
+        let t = self.get(name)?;
+        t.range?.in_unit_system(t.group, unit_system).ok()
+    }
+
+    /// Archive column for `name`, if the type is archived
+    pub fn archive_column(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(|t| t.column.as_deref())
+    }
+
+    /// All registered types
+    pub fn types(&self) -> impl Iterator<Item = &ObsType> {
+        self.types.values()
+    }
+
+    /// Rename aliased observations in `packet` to their canonical names. A
+    /// value already present under the canonical name wins.
+    pub fn normalize(&self, packet: &mut WeatherPacket) {
+        let aliased: Vec<String> = packet
+            .observations
+            .keys()
+            .filter(|k| self.aliases.contains_key(k.as_str()))
+            .cloned()
+            .collect();
+        for alias in aliased {
+            if let Some(value) = packet.observations.remove(&alias) {
+                let name = self.canonical(&alias).to_string();
+                packet.observations.entry(name).or_insert(value);
+            }
+        }
+    }
+}
+
+fn global() -> &'static RwLock<ObsRegistry> {
+    static REGISTRY: OnceLock<RwLock<ObsRegistry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| RwLock::new(ObsRegistry::builtin()))
+}
+
+/// The process-wide registry consulted by units, rollups, QC and the archive
+pub fn obs_registry() -> RwLockReadGuard<'static, ObsRegistry> {
+    global().read().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Add or replace a type in the process-wide registry (e.g. from config)
+pub fn register_obs_type(obs_type: ObsType) {
+    global()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .register(obs_type);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{unit_systems, ObservationValue};
+
+    #[test]
+    fn resolves_aliases_to_one_type() {
+        let registry = ObsRegistry::builtin();
+        assert_eq!(registry.canonical("uv"), "UV");
+        assert_eq!(registry.get("uv"), registry.get("UV"));
+        assert_eq!(registry.unit_group("humidity"), Some(UnitGroup::Humidity));
+        assert_eq!(registry.archive_column("uv"), Some("UV"));
+        assert_eq!(registry.aggregate_type("rain"), AggregateType::Sum);
+        assert_eq!(registry.aggregate_type("unknownObs"), AggregateType::Last);
+        assert_eq!(registry.canonical("unknownObs"), "unknownObs");
+        // wview_extended types
+        assert_eq!(registry.archive_column("pm2_5"), Some("pm2_5"));
+        assert_eq!(
+            registry.aggregate_type("lightning_strike_count"),
+            AggregateType::Sum
+        );
+        assert_eq!(
+            registry.unit_group("soilTemp3"),
+            Some(UnitGroup::Temperature)
+        );
+    }
+
+    #[test]
+    fn valid_range_follows_unit_system() {
+        let registry = ObsRegistry::builtin();
+        assert_eq!(
+            registry.valid_range("outTemp", unit_systems::US),
+            Some((-40.0, 120.0))
+        );
+        let (min, max) = registry
+            .valid_range("outTemp", unit_systems::METRICWX)
+            .unwrap();
+        assert!((min + 40.0).abs() < 1e-9 && (max - 48.888).abs() < 1e-3);
+        assert_eq!(
+            registry.valid_range("outHumidity", unit_systems::METRIC),
+            Some((0.0, 100.0))
+        );
+        assert_eq!(registry.valid_range("windDir", unit_systems::US), None);
+    }
+
+    #[test]
+    fn register_replaces_type_and_aliases() {
+        let mut registry = ObsRegistry::builtin();
+        registry.register(
+            ObsType::new("UV")
+                .group(UnitGroup::Uv)
+                .aggregate(AggregateType::Max)
+                .alias("uvIndex"),
+        );
+        assert_eq!(registry.aggregate_type("uvIndex"), AggregateType::Max);
+        assert_eq!(registry.canonical("uv"), "uv");
+
+        registry.register(ObsType::new("soilMoist1").range(0.0, 200.0, None));
+        assert_eq!(
+            registry.valid_range("soilMoist1", unit_systems::US),
+            Some((0.0, 200.0))
+        );
+    }
+
+    #[test]
+    fn normalize_renames_aliases() {
+        let registry = ObsRegistry::builtin();
+        let mut packet = WeatherPacket {
+            date_time: 0,
+            station: None,
+            interval: None,
+            observations: HashMap::from([
+                ("uv".to_string(), ObservationValue::Float(3.0)),
+                ("humidity".to_string(), ObservationValue::Float(40.0)),
+                ("outHumidity".to_string(), ObservationValue::Float(41.0)),
+            ]),
+        };
+        registry.normalize(&mut packet);
+        assert_eq!(packet.observations["UV"], ObservationValue::Float(3.0));
+        assert_eq!(
+            packet.observations["outHumidity"],
+            ObservationValue::Float(41.0)
+        );
+        assert_eq!(packet.observations.len(), 2);
+    }
+}
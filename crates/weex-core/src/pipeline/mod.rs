@@ -0,0 +1,67 @@
+//! Pipeline stages and the runtime that connects them
+
+mod runtime;
+
+pub use runtime::*;
+
+use anyhow::Result;
+
+use crate::WeatherPacket;
+
+#[async_trait::async_trait]
+pub trait Source: Send + Sync {
+    async fn next_packet(&mut self) -> Result<WeatherPacket>;
+
+    /// Packets already accepted but not yet returned by `next_packet`. Called
+    /// once the pipeline is cancelled so they still go through the chain.
+    fn drain(&mut self) -> Vec<WeatherPacket> {
+        Vec::new()
+    }
+
+    /// Stop producing and release resources; called once the pipeline stops
+    async fn close(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Processor: Send + Sync {
+    async fn process(&self, packet: WeatherPacket) -> Result<WeatherPacket>;
+}
+
+#[async_trait::async_trait]
+pub trait Sink: Send + Sync {
+    async fn emit(&mut self, packet: &WeatherPacket) -> Result<()>;
+
+    /// Emit several packets at once. Sinks that can write a batch in one
+    /// round trip should override this; on error, any prefix of the batch
+    /// may already have been written.
+    async fn emit_batch(&mut self, packets: &[WeatherPacket]) -> Result<()> {
+        for packet in packets {
+            self.emit(packet).await?;
+        }
+        Ok(())
+    }
+
+    /// Wall-clock time (epoch seconds) at which the sink wants `tick` called,
+    /// e.g. when an archive interval is due to close. `None` never ticks.
+    fn next_tick(&self) -> Option<i64> {
+        None
+    }
+
+    /// Called by the runtime once `next_tick` has passed, with the current
+    /// time, even if no packets arrive
+    async fn tick(&mut self, _now: i64) -> Result<()> {
+        Ok(())
+    }
+
+    /// Push anything buffered by the sink through to its target
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Flush and release resources; the sink is not used afterwards
+    async fn close(&mut self) -> Result<()> {
+        self.flush().await
+    }
+}
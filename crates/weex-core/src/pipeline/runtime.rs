@@ -0,0 +1,812 @@
+//! Pipeline runtime: sources → ordered processor chain → sinks
+//!
+//! Every stage runs in its own task and stages are connected by bounded
+//! channels, so a slow stage applies backpressure instead of growing memory:
+//! a full sink queue blocks the last processor, which blocks the one before
+//! it, and so on back to the sources. Every sink receives every packet: each
+//! packet is queued on every sink with room at once, so a sink that is busy
+//! only holds up the others once its own queue is full. Sinks added with
+//! [`PipelineBuilder::lossy_sink`] never hold anything up; packets their
+//! queue has no room for are dropped for them and counted.
+//!
+//! Cancelling stops the sources; packets already in flight still drain
+//! through the chain, and each sink is then flushed and closed.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use super::{Processor, Sink, Source};
+use crate::WeatherPacket;
+
+/// Default capacity of each inter-stage channel (packets)
+pub const DEFAULT_STAGE_CAPACITY: usize = 256;
+
+/// Maximum packets handed to a sink per `emit_batch` call
+const SINK_BATCH: usize = 256;
+
+/// Wait before calling a sink's `tick` again after it failed (seconds)
+pub const TICK_RETRY_SECS: i64 = 5;
+
+/// Role of a pipeline stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StageKind {
+    Source,
+    Processor,
+    Sink,
+}
+
+impl StageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StageKind::Source => "source",
+            StageKind::Processor => "processor",
+            StageKind::Sink => "sink",
+        }
+    }
+}
+
+/// Live counters for one stage
+#[derive(Debug)]
+pub struct StageStats {
+    name: String,
+    kind: StageKind,
+    received: AtomicU64,
+    emitted: AtomicU64,
+    errors: AtomicU64,
+    dropped: AtomicU64,
+    backlog: AtomicU64,
+}
+
+impl StageStats {
+    fn new(name: String, kind: StageKind) -> Self {
+        Self {
+            name,
+            kind,
+            received: AtomicU64::new(0),
+            emitted: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            backlog: AtomicU64::new(0),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> StageKind {
+        self.kind
+    }
+
+    /// Packets taken off the stage's input queue (always 0 for sources)
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Packets produced (sources), passed on (processors) or written (sinks)
+    pub fn emitted(&self) -> u64 {
+        self.emitted.load(Ordering::Relaxed)
+    }
+
+    /// Failed reads (sources) or packets that failed the stage
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Packets a lossy sink's full queue had no room for
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Packets waiting in the stage's input queue
+    pub fn backlog(&self) -> u64 {
+        self.backlog.load(Ordering::Relaxed)
+    }
+
+    pub fn status(&self) -> StageStatus {
+        StageStatus {
+            name: self.name.clone(),
+            kind: self.kind,
+            received: self.received(),
+            emitted: self.emitted(),
+            errors: self.errors(),
+            dropped: self.dropped(),
+            backlog: self.backlog(),
+        }
+    }
+}
+
+/// Point-in-time snapshot of a stage
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStatus {
+    pub name: String,
+    pub kind: StageKind,
+    pub received: u64,
+    pub emitted: u64,
+    pub errors: u64,
+    pub dropped: u64,
+    pub backlog: u64,
+}
+
+/// Sending half of an inter-stage channel, tracking the receiver's backlog
+#[derive(Clone)]
+struct Link {
+    tx: mpsc::Sender<WeatherPacket>,
+    stats: Arc<StageStats>,
+    /// Drop packets when the queue is full instead of waiting
+    lossy: bool,
+}
+
+impl Link {
+    /// Waits for queue space; false once the receiving stage is gone
+    async fn send(&self, packet: WeatherPacket) -> bool {
+        self.stats.backlog.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(packet).await.is_err() {
+            self.stats.backlog.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Queues the packet if there is room. A full queue hands it back, unless
+    /// the link is lossy, which drops it; false once the receiving stage is
+    /// gone.
+    fn try_send(&self, packet: WeatherPacket) -> Result<bool, WeatherPacket> {
+        self.stats.backlog.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(packet) {
+            Ok(()) => Ok(true),
+            Err(e) => {
+                self.stats.backlog.fetch_sub(1, Ordering::Relaxed);
+                match e {
+                    TrySendError::Full(_) if self.lossy => {
+                        self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                        Ok(true)
+                    }
+                    TrySendError::Full(packet) => Err(packet),
+                    TrySendError::Closed(_) => Ok(false),
+                }
+            }
+        }
+    }
+}
+
+/// Where a stage hands its packets: the next processor, or every sink
+#[derive(Clone)]
+enum Output {
+    Next(Link),
+    Sinks(Vec<Link>),
+}
+
+impl Output {
+    async fn send(&self, packet: WeatherPacket) -> bool {
+        match self {
+            Output::Next(link) => link.send(packet).await,
+            Output::Sinks(links) => {
+                // Every sink with room gets the packet now; only then wait
+                // for the full ones
+                let mut delivered = false;
+                let mut waiting = Vec::new();
+                for link in links {
+                    match link.try_send(packet.clone()) {
+                        Ok(sent) => delivered |= sent,
+                        Err(packet) => waiting.push((link, packet)),
+                    }
+                }
+                for (link, packet) in waiting {
+                    delivered |= link.send(packet).await;
+                }
+                delivered
+            }
+        }
+    }
+}
+
+/// Assembles a [`Pipeline`]
+pub struct PipelineBuilder {
+    sources: Vec<(String, Box<dyn Source>)>,
+    processors: Vec<(String, Box<dyn Processor>)>,
+    sinks: Vec<(String, Box<dyn Sink>, usize, bool)>,
+    capacity: usize,
+    error_backoff: Duration,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self {
+            sources: Vec::new(),
+            processors: Vec::new(),
+            sinks: Vec::new(),
+            capacity: DEFAULT_STAGE_CAPACITY,
+            error_backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Capacity of each inter-stage channel
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Pause after a failed source read before trying again
+    pub fn error_backoff(mut self, backoff: Duration) -> Self {
+        self.error_backoff = backoff;
+        self
+    }
+
+    pub fn source(mut self, name: impl Into<String>, source: impl Source + 'static) -> Self {
+        self.sources.push((name.into(), Box::new(source)));
+        self
+    }
+
+    /// Append a processor; processors run in the order they are added
+    pub fn processor(
+        mut self,
+        name: impl Into<String>,
+        processor: impl Processor + 'static,
+    ) -> Self {
+        self.processors.push((name.into(), Box::new(processor)));
+        self
+    }
+
+    pub fn sink(self, name: impl Into<String>, sink: impl Sink + 'static) -> Self {
+        let capacity = self.capacity;
+        self.sink_with_capacity(name, sink, capacity)
+    }
+
+    /// Add a sink with its own input queue size
+    pub fn sink_with_capacity(
+        mut self,
+        name: impl Into<String>,
+        sink: impl Sink + 'static,
+        capacity: usize,
+    ) -> Self {
+        self.sinks
+            .push((name.into(), Box::new(sink), capacity.max(1), false));
+        self
+    }
+
+    /// Add a sink that never holds up the pipeline: packets arriving while
+    /// its queue of `capacity` is full are dropped for it and counted
+    pub fn lossy_sink(
+        mut self,
+        name: impl Into<String>,
+        sink: impl Sink + 'static,
+        capacity: usize,
+    ) -> Self {
+        self.sinks
+            .push((name.into(), Box::new(sink), capacity.max(1), true));
+        self
+    }
+
+    /// Spawn every stage on the current Tokio runtime
+    pub fn spawn(self) -> Pipeline {
+        let cancel = CancellationToken::new();
+        let mut stats = Vec::new();
+
+        // Sinks first, so every upstream stage knows where to send
+        let mut sink_links = Vec::with_capacity(self.sinks.len());
+        let mut sink_tasks = Vec::with_capacity(self.sinks.len());
+        for (name, sink, capacity, lossy) in self.sinks {
+            let (tx, rx) = mpsc::channel(capacity);
+            let st = Arc::new(StageStats::new(name, StageKind::Sink));
+            sink_links.push(Link {
+                tx,
+                stats: Arc::clone(&st),
+                lossy,
+            });
+            sink_tasks.push(tokio::spawn(run_sink(sink, rx, Arc::clone(&st))));
+            stats.push(st);
+        }
+
+        // Processors, last to first
+        let mut output = Output::Sinks(sink_links);
+        let mut processor_tasks = Vec::with_capacity(self.processors.len());
+        let mut processor_stats = Vec::with_capacity(self.processors.len());
+        for (name, processor) in self.processors.into_iter().rev() {
+            let (tx, rx) = mpsc::channel(self.capacity);
+            let st = Arc::new(StageStats::new(name, StageKind::Processor));
+            let task = tokio::spawn(run_processor(processor, rx, output, Arc::clone(&st)));
+            output = Output::Next(Link {
+                tx,
+                stats: Arc::clone(&st),
+                lossy: false,
+            });
+            processor_tasks.push(task);
+            processor_stats.push(st);
+        }
+        processor_tasks.reverse();
+        processor_stats.reverse();
+
+        let mut source_tasks = Vec::with_capacity(self.sources.len());
+        let mut source_stats = Vec::with_capacity(self.sources.len());
+        for (name, source) in self.sources {
+            let st = Arc::new(StageStats::new(name, StageKind::Source));
+            source_tasks.push(tokio::spawn(run_source(
+                source,
+                output.clone(),
+                Arc::clone(&st),
+                cancel.clone(),
+                self.error_backoff,
+            )));
+            source_stats.push(st);
+        }
+        // Only the sources keep the chain open from here on
+        drop(output);
+
+        let mut ordered = source_stats;
+        ordered.extend(processor_stats);
+        ordered.append(&mut stats);
+
+        Pipeline {
+            cancel,
+            stats: ordered,
+            sources: source_tasks,
+            processors: processor_tasks,
+            sinks: sink_tasks,
+        }
+    }
+}
+
+/// A running pipeline
+pub struct Pipeline {
+    cancel: CancellationToken,
+    stats: Vec<Arc<StageStats>>,
+    sources: Vec<JoinHandle<()>>,
+    processors: Vec<JoinHandle<()>>,
+    sinks: Vec<JoinHandle<()>>,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::new()
+    }
+
+    /// Shared counters of every stage, in source → processor → sink order
+    pub fn stats(&self) -> Vec<Arc<StageStats>> {
+        self.stats.clone()
+    }
+
+    /// Snapshot of every stage's counters
+    pub fn status(&self) -> Vec<StageStatus> {
+        self.stats.iter().map(|s| s.status()).collect()
+    }
+
+    /// Token that stops the sources when cancelled
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Stop the sources, drain what is in flight, then flush and close the sinks
+    pub async fn shutdown(self) {
+        self.cancel.cancel();
+        let tasks = self
+            .sources
+            .into_iter()
+            .chain(self.processors)
+            .chain(self.sinks);
+        for task in tasks {
+            if let Err(e) = task.await {
+                tracing::warn!(error = ?e, "pipeline stage panicked");
+            }
+        }
+    }
+}
+
+async fn run_source(
+    mut source: Box<dyn Source>,
+    output: Output,
+    stats: Arc<StageStats>,
+    cancel: CancellationToken,
+    error_backoff: Duration,
+) {
+    loop {
+        let next = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => break,
+            next = source.next_packet() => next,
+        };
+        match next {
+            Ok(packet) => {
+                stats.emitted.fetch_add(1, Ordering::Relaxed);
+                if !output.send(packet).await {
+                    break;
+                }
+            }
+            Err(e) => {
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(source = %stats.name, error = ?e, "source read failed");
+                tokio::select! {
+                    _ = cancel.cancelled() => break,
+                    _ = tokio::time::sleep(error_backoff) => {}
+                }
+            }
+        }
+    }
+    for packet in source.drain() {
+        stats.emitted.fetch_add(1, Ordering::Relaxed);
+        if !output.send(packet).await {
+            break;
+        }
+    }
+    if let Err(e) = source.close().await {
+        tracing::warn!(source = %stats.name, error = ?e, "source close failed");
+    }
+}
+
+async fn run_processor(
+    processor: Box<dyn Processor>,
+    mut rx: mpsc::Receiver<WeatherPacket>,
+    output: Output,
+    stats: Arc<StageStats>,
+) {
+    while let Some(packet) = rx.recv().await {
+        stats.backlog.fetch_sub(1, Ordering::Relaxed);
+        stats.received.fetch_add(1, Ordering::Relaxed);
+        match processor.process(packet).await {
+            Ok(packet) => {
+                stats.emitted.fetch_add(1, Ordering::Relaxed);
+                if !output.send(packet).await {
+                    break;
+                }
+            }
+            Err(e) => {
+                stats.errors.fetch_add(1, Ordering::Relaxed);
+                tracing::warn!(processor = %stats.name, error = ?e, "packet dropped by processor");
+            }
+        }
+    }
+}
+
+async fn run_sink(
+    mut sink: Box<dyn Sink>,
+    mut rx: mpsc::Receiver<WeatherPacket>,
+    stats: Arc<StageStats>,
+) {
+    let mut batch = Vec::with_capacity(SINK_BATCH);
+    let mut retry_at = i64::MIN;
+    loop {
+        let deadline = sink.next_tick().map(|t| t.max(retry_at));
+        // Packets already queued go first, so a tick sees them
+        let received = tokio::select! {
+            biased;
+            n = rx.recv_many(&mut batch, SINK_BATCH) => n,
+            now = sleep_until(deadline) => {
+                if let Err(e) = sink.tick(now).await {
+                    tracing::warn!(sink = %stats.name, error = ?e, "sink tick failed");
+                    retry_at = now + TICK_RETRY_SECS;
+                }
+                continue;
+            }
+        };
+        if received == 0 {
+            break;
+        }
+        let n = batch.len() as u64;
+        stats.backlog.fetch_sub(n, Ordering::Relaxed);
+        stats.received.fetch_add(n, Ordering::Relaxed);
+        match sink.emit_batch(&batch).await {
+            Ok(()) => {
+                stats.emitted.fetch_add(n, Ordering::Relaxed);
+            }
+            Err(e) => {
+                stats.errors.fetch_add(n, Ordering::Relaxed);
+                tracing::warn!(sink = %stats.name, error = ?e, "sink emit failed");
+            }
+        }
+        batch.clear();
+    }
+    // `close` flushes first
+    if let Err(e) = sink.close().await {
+        tracing::warn!(sink = %stats.name, error = ?e, "sink close failed");
+    }
+}
+
+/// Sleep until the wall-clock time `at` (epoch seconds) and return the time
+/// woken at; never returns without a deadline. For driving [`Sink::tick`]
+/// outside the pipeline.
+pub async fn sleep_until(at: Option<i64>) -> i64 {
+    let Some(at) = at else {
+        return std::future::pending().await;
+    };
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let wait_ms = at.saturating_mul(1000).saturating_sub(now_ms);
+    if wait_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(wait_ms as u64)).await;
+    }
+    chrono::Utc::now().timestamp()
+}
+
+/// Source fed through a channel, for packets pushed in from elsewhere
+/// (e.g. HTTP handlers). Once every sender is gone it idles until the
+/// pipeline is cancelled; packets still queued then are drained, not lost.
+pub struct ChannelSource {
+    rx: mpsc::Receiver<WeatherPacket>,
+}
+
+impl ChannelSource {
+    pub fn new(capacity: usize) -> (mpsc::Sender<WeatherPacket>, Self) {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        (tx, Self { rx })
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for ChannelSource {
+    async fn next_packet(&mut self) -> anyhow::Result<WeatherPacket> {
+        match self.rx.recv().await {
+            Some(packet) => Ok(packet),
+            None => std::future::pending().await,
+        }
+    }
+
+    fn drain(&mut self) -> Vec<WeatherPacket> {
+        self.rx.close();
+        let mut packets = Vec::new();
+        while let Ok(packet) = self.rx.try_recv() {
+            packets.push(packet);
+        }
+        packets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ObservationValue;
+    use anyhow::{anyhow, Result};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    fn packet(ts: i64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: HashMap::new(),
+        }
+    }
+
+    /// Tags packets with its name, in a `trail` string observation
+    struct Tag(&'static str);
+
+    #[async_trait::async_trait]
+    impl Processor for Tag {
+        async fn process(&self, mut packet: WeatherPacket) -> Result<WeatherPacket> {
+            let trail = match packet.observations.remove("trail") {
+                Some(ObservationValue::String(s)) => format!("{}{}", s, self.0),
+                _ => self.0.to_string(),
+            };
+            packet
+                .observations
+                .insert("trail".into(), ObservationValue::String(trail));
+            Ok(packet)
+        }
+    }
+
+    /// Rejects odd timestamps
+    struct EvenOnly;
+
+    #[async_trait::async_trait]
+    impl Processor for EvenOnly {
+        async fn process(&self, packet: WeatherPacket) -> Result<WeatherPacket> {
+            if packet.date_time % 2 == 1 {
+                return Err(anyhow!("odd"));
+            }
+            Ok(packet)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        packets: Arc<Mutex<Vec<WeatherPacket>>>,
+        closed: Arc<Mutex<bool>>,
+        delay: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for Recorder {
+        async fn emit(&mut self, packet: &WeatherPacket) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            self.packets.lock().unwrap().push(packet.clone());
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<()> {
+            *self.closed.lock().unwrap() = true;
+            Ok(())
+        }
+    }
+
+    /// Counts reads, producing packets as fast as it is polled
+    struct Counter(i64);
+
+    #[async_trait::async_trait]
+    impl Source for Counter {
+        async fn next_packet(&mut self) -> Result<WeatherPacket> {
+            self.0 += 1;
+            Ok(packet(self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn runs_processors_in_order_and_fans_out() {
+        let (tx, source) = ChannelSource::new(8);
+        let a = Recorder::default();
+        let b = Recorder::default();
+        let pipeline = Pipeline::builder()
+            .source("chan", source)
+            .processor("first", Tag("a"))
+            .processor("even", EvenOnly)
+            .processor("second", Tag("b"))
+            .sink("a", a.clone())
+            .sink("b", b.clone())
+            .spawn();
+
+        for ts in 0..4 {
+            tx.send(packet(ts)).await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while a.packets.lock().unwrap().len() < 2 || b.packets.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("sinks did not receive the packets");
+        let status = pipeline.status();
+        pipeline.shutdown().await;
+
+        let got = a.packets.lock().unwrap();
+        assert_eq!(got.iter().map(|p| p.date_time).collect::<Vec<_>>(), [0, 2]);
+        assert_eq!(
+            got[0].observations["trail"],
+            ObservationValue::String("ab".into())
+        );
+        assert!(*a.closed.lock().unwrap() && *b.closed.lock().unwrap());
+
+        let even = status.iter().find(|s| s.name == "even").unwrap();
+        assert_eq!((even.received, even.emitted, even.errors), (4, 2, 2));
+        let names: Vec<_> = status.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["chan", "first", "even", "second", "a", "b"]);
+    }
+
+    /// Asks to be ticked `remaining` more times, as soon as possible
+    struct Ticker {
+        remaining: Arc<Mutex<u32>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Sink for Ticker {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            Ok(())
+        }
+
+        fn next_tick(&self) -> Option<i64> {
+            (*self.remaining.lock().unwrap() > 0).then_some(0)
+        }
+
+        async fn tick(&mut self, now: i64) -> Result<()> {
+            assert!(now > 0);
+            *self.remaining.lock().unwrap() -= 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn sinks_tick_without_packets() {
+        let remaining = Arc::new(Mutex::new(3));
+        let (_tx, source) = ChannelSource::new(1);
+        let pipeline = Pipeline::builder()
+            .source("idle", source)
+            .sink(
+                "ticker",
+                Ticker {
+                    remaining: remaining.clone(),
+                },
+            )
+            .spawn();
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while *remaining.lock().unwrap() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("sink was not ticked");
+        pipeline.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_delivers_packets_already_accepted() {
+        let (tx, source) = ChannelSource::new(16);
+        let sink = Recorder::default();
+        let pipeline = Pipeline::builder()
+            .source("chan", source)
+            .sink("rec", sink.clone())
+            .spawn();
+        for ts in 0..10 {
+            tx.send(packet(ts)).await.unwrap();
+        }
+        pipeline.shutdown().await;
+        assert_eq!(sink.packets.lock().unwrap().len(), 10);
+        assert!(tx.send(packet(10)).await.is_err());
+    }
+
+    /// Blocks in `emit` until released
+    struct Stalled(CancellationToken);
+
+    #[async_trait::async_trait]
+    impl Sink for Stalled {
+        async fn emit(&mut self, _packet: &WeatherPacket) -> Result<()> {
+            self.0.cancelled().await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stalled_lossy_sink_does_not_hold_up_others() {
+        let (tx, source) = ChannelSource::new(64);
+        let release = CancellationToken::new();
+        let sink = Recorder::default();
+        let pipeline = Pipeline::builder()
+            .capacity(4)
+            .source("chan", source)
+            .lossy_sink("stalled", Stalled(release.clone()), 1)
+            .sink("rec", sink.clone())
+            .spawn();
+
+        for ts in 0..20 {
+            tx.send(packet(ts)).await.unwrap();
+        }
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while sink.packets.lock().unwrap().len() < 20 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the other sink stopped receiving");
+
+        let stats = pipeline.stats();
+        release.cancel();
+        pipeline.shutdown().await;
+        let stalled = stats.iter().find(|s| s.name() == "stalled").unwrap();
+        assert!(stalled.dropped() > 0);
+        assert_eq!(stalled.emitted() + stalled.dropped(), 20);
+    }
+
+    #[tokio::test]
+    async fn slow_sink_applies_backpressure_to_sources() {
+        let sink = Recorder {
+            delay: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let pipeline = Pipeline::builder()
+            .capacity(2)
+            .source("counter", Counter(0))
+            .processor("tag", Tag("x"))
+            .sink("slow", sink.clone())
+            .spawn();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stats = pipeline.stats();
+        pipeline.shutdown().await;
+
+        // Bounded by the queues, not by how fast the source could go
+        let produced = stats[0].emitted();
+        assert!(produced < 50, "source produced {}", produced);
+        // Nothing in flight is lost on shutdown
+        assert_eq!(sink.packets.lock().unwrap().len() as u64, produced);
+        assert!(stats.iter().all(|s| s.backlog() == 0));
+    }
+}
@@ -0,0 +1,427 @@
+//! Quality control (WeeWX `StdQC` and more)
+//!
+//! A pipeline [`Processor`] that nulls out observations a broken sensor could
+//! not have produced. Every numeric observation is checked against its valid
+//! range from the obs-type registry, in the packet's unit system. Observations
+//! with [`QcLimits`] are also checked for spikes, excessive rate of change and
+//! a stuck (flatlined) sensor. Each failure is logged and counted in
+//! [`QcStats`].
+
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::obstypes::obs_registry;
+use crate::pipeline::Processor;
+use crate::types::{unit_systems, ObservationValue, WeatherPacket};
+use crate::units::convert;
+
+/// Failures kept in the QC log
+const QC_LOG_CAP: usize = 200;
+
+/// Recent good values the spike check takes the median of
+const SPIKE_WINDOW: usize = 5;
+
+/// Good values older than this are not used as a reference (seconds)
+const REFERENCE_MAX_AGE: i64 = 900;
+
+/// Shortest time step the rate check divides by (seconds), so sensor noise
+/// between closely spaced packets is not amplified into a huge rate
+const RATE_MIN_DT: i64 = 60;
+
+/// Which check rejected a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QcCheck {
+    Range,
+    Spike,
+    RateOfChange,
+    Flatline,
+}
+
+impl QcCheck {
+    pub const ALL: [QcCheck; 4] = [
+        QcCheck::Range,
+        QcCheck::Spike,
+        QcCheck::RateOfChange,
+        QcCheck::Flatline,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QcCheck::Range => "range",
+            QcCheck::Spike => "spike",
+            QcCheck::RateOfChange => "rate_of_change",
+            QcCheck::Flatline => "flatline",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// One rejected value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QcEvent {
+    #[serde(rename = "dateTime")]
+    pub date_time: i64,
+    pub obs: String,
+    pub value: f64,
+    pub check: QcCheck,
+    pub reason: String,
+}
+
+/// Failure counters and the recent QC log
+#[derive(Debug, Default)]
+pub struct QcStats {
+    failures: [AtomicU64; 4],
+    log: Mutex<VecDeque<QcEvent>>,
+}
+
+impl QcStats {
+    /// Values rejected by `check`
+    pub fn failures(&self, check: QcCheck) -> u64 {
+        self.failures[check.index()].load(Ordering::Relaxed)
+    }
+
+    /// Most recent failures, oldest first
+    pub fn recent(&self) -> Vec<QcEvent> {
+        self.log
+            .lock()
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn record(&self, event: QcEvent) {
+        tracing::warn!(
+            obs = %event.obs,
+            value = event.value,
+            check = event.check.as_str(),
+            reason = %event.reason,
+            date_time = event.date_time,
+            "QC rejected value"
+        );
+        self.failures[event.check.index()].fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut log) = self.log.lock() {
+            if log.len() == QC_LOG_CAP {
+                log.pop_front();
+            }
+            log.push_back(event);
+        }
+    }
+}
+
+/// Per-observation limits beyond the valid range. Amounts are in METRICWX
+/// units (degC, hPa, m/s, mm, ...) whatever the packet's unit system.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QcLimits {
+    /// Largest allowed deviation from the median of recent good values
+    pub spike: Option<f64>,
+    /// Largest allowed change per hour relative to the last good value
+    pub max_rate_per_hour: Option<f64>,
+    /// Longest time (seconds) a value may stay exactly the same
+    pub flatline_secs: Option<i64>,
+}
+
+/// Configuration for [`QualityControl`]
+#[derive(Debug, Clone)]
+pub struct QcOptions {
+    /// Unit system assumed for packets without a `usUnits` field
+    pub unit_system: i32,
+    /// Check values against the registry's valid ranges
+    pub range_checks: bool,
+    pub limits: HashMap<String, QcLimits>,
+}
+
+impl Default for QcOptions {
+    fn default() -> Self {
+        Self {
+            unit_system: unit_systems::METRICWX,
+            range_checks: true,
+            limits: HashMap::new(),
+        }
+    }
+}
+
+/// What the checks remember about one observation (METRICWX values)
+#[derive(Debug, Default)]
+struct ObsHistory {
+    /// Recent good (timestamp, value) pairs, oldest first
+    good: VecDeque<(i64, f64)>,
+    /// Value the sensor has been reporting unchanged, and since when
+    unchanged: Option<(i64, f64)>,
+}
+
+/// Quality-control processor
+pub struct QualityControl {
+    opts: QcOptions,
+    stats: Arc<QcStats>,
+    history: Mutex<HashMap<String, ObsHistory>>,
+}
+
+impl QualityControl {
+    pub fn new(opts: QcOptions) -> Self {
+        Self {
+            opts,
+            stats: Arc::default(),
+            history: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Shared failure counters and log
+    pub fn stats(&self) -> Arc<QcStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// Spike, rate-of-change and flatline checks on `value` (METRICWX) at `ts`
+    fn check_history(
+        limits: &QcLimits,
+        history: &mut ObsHistory,
+        ts: i64,
+        value: f64,
+    ) -> Option<(QcCheck, String)> {
+        // Flatline tracks every reading, good or not
+        let since = match history.unchanged {
+            Some((since, v)) if v == value => since,
+            _ => {
+                history.unchanged = Some((ts, value));
+                ts
+            }
+        };
+
+        while history
+            .good
+            .front()
+            .is_some_and(|(t, _)| ts - t > REFERENCE_MAX_AGE)
+        {
+            history.good.pop_front();
+        }
+
+        if let Some(limit) = limits.spike {
+            if history.good.len() >= 3 {
+                let mut recent: Vec<f64> = history.good.iter().map(|(_, v)| *v).collect();
+                recent.sort_by(f64::total_cmp);
+                let median = recent[recent.len() / 2];
+                if (value - median).abs() > limit {
+                    return Some((
+                        QcCheck::Spike,
+                        format!("{:.2} from recent median {:.2}", value - median, median),
+                    ));
+                }
+            }
+        }
+        if let Some(limit) = limits.max_rate_per_hour {
+            if let Some(&(t, last)) = history.good.back() {
+                let dt = (ts - t).max(RATE_MIN_DT) as f64;
+                let rate = (value - last).abs() * 3600.0 / dt;
+                if rate > limit {
+                    return Some((
+                        QcCheck::RateOfChange,
+                        format!("{:.2}/h exceeds {:.2}/h", rate, limit),
+                    ));
+                }
+            }
+        }
+        if let Some(limit) = limits.flatline_secs {
+            if ts - since > limit {
+                return Some((QcCheck::Flatline, format!("unchanged for {}s", ts - since)));
+            }
+        }
+
+        history.good.push_back((ts, value));
+        if history.good.len() > SPIKE_WINDOW {
+            history.good.pop_front();
+        }
+        None
+    }
+}
+
+#[async_trait::async_trait]
+impl Processor for QualityControl {
+    async fn process(&self, mut packet: WeatherPacket) -> Result<WeatherPacket> {
+        let us = packet.unit_system().unwrap_or(self.opts.unit_system);
+        let registry = obs_registry();
+        let mut history = self.history.lock().unwrap_or_else(|e| e.into_inner());
+        let mut failed = Vec::new();
+
+        for (key, value) in &packet.observations {
+            if key == "usUnits" {
+                continue;
+            }
+            let Some(v) = value.as_f64() else {
+                continue;
+            };
+            let name = registry.canonical(key);
+
+            if self.opts.range_checks {
+                if let Some((min, max)) = registry.valid_range(name, us) {
+                    if v < min || v > max {
+                        let reason = format!("outside [{:.2}, {:.2}]", min, max);
+                        failed.push((key.clone(), v, QcCheck::Range, reason));
+                        continue;
+                    }
+                }
+            }
+
+            let Some(limits) = self.opts.limits.get(name) else {
+                continue;
+            };
+            let metric = match registry.unit_group(name) {
+                Some(group) => match convert(v, us, unit_systems::METRICWX, group) {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                },
+                None => v,
+            };
+            let obs_history = history.entry(name.to_string()).or_default();
+            if let Some((check, reason)) =
+                Self::check_history(limits, obs_history, packet.date_time, metric)
+            {
+                failed.push((key.clone(), v, check, reason));
+            }
+        }
+
+        for (key, value, check, reason) in failed {
+            packet
+                .observations
+                .insert(key.clone(), ObservationValue::Null);
+            self.stats.record(QcEvent {
+                date_time: packet.date_time,
+                obs: key,
+                value,
+                check,
+                reason,
+            });
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(ts: i64, obs: &[(&str, f64)]) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: obs
+                .iter()
+                .map(|(k, v)| (k.to_string(), ObservationValue::Float(*v)))
+                .collect(),
+        }
+    }
+
+    fn limits(name: &str, limits: QcLimits) -> QcOptions {
+        QcOptions {
+            limits: HashMap::from([(name.to_string(), limits)]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn range_check_uses_packet_units() {
+        let qc = QualityControl::new(QcOptions::default());
+        let out = qc
+            .process(packet(0, &[("outTemp", 85.0), ("barometer", -400.0)]))
+            .await
+            .unwrap();
+        assert!(out.observations["outTemp"].is_null());
+        assert!(out.observations["barometer"].is_null());
+
+        // 85 degF is fine in US units
+        let out = qc
+            .process(packet(0, &[("usUnits", 1.0), ("outTemp", 85.0)]))
+            .await
+            .unwrap();
+        assert_eq!(out.observations["outTemp"], ObservationValue::Float(85.0));
+
+        let stats = qc.stats();
+        assert_eq!(stats.failures(QcCheck::Range), 2);
+        let log = stats.recent();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().any(|e| e.obs == "outTemp" && e.value == 85.0));
+    }
+
+    #[tokio::test]
+    async fn rejects_spikes_against_recent_median() {
+        let qc = QualityControl::new(limits(
+            "outTemp",
+            QcLimits {
+                spike: Some(5.0),
+                ..Default::default()
+            },
+        ));
+        for (ts, t) in [(0, 20.0), (60, 20.2), (120, 20.1)] {
+            let out = qc.process(packet(ts, &[("outTemp", t)])).await.unwrap();
+            assert!(!out.observations["outTemp"].is_null());
+        }
+        let out = qc.process(packet(180, &[("outTemp", 35.0)])).await.unwrap();
+        assert!(out.observations["outTemp"].is_null());
+        // The spike is not used as a reference for the next reading
+        let out = qc.process(packet(240, &[("outTemp", 20.3)])).await.unwrap();
+        assert!(!out.observations["outTemp"].is_null());
+        assert_eq!(qc.stats().failures(QcCheck::Spike), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_excessive_rate_of_change() {
+        let qc = QualityControl::new(limits(
+            "barometer",
+            QcLimits {
+                max_rate_per_hour: Some(10.0),
+                ..Default::default()
+            },
+        ));
+        qc.process(packet(0, &[("barometer", 1010.0)]))
+            .await
+            .unwrap();
+        // 5 hPa in 10 minutes is 30 hPa/h
+        let out = qc
+            .process(packet(600, &[("barometer", 1015.0)]))
+            .await
+            .unwrap();
+        assert!(out.observations["barometer"].is_null());
+        // 1 hPa in 10 minutes is fine
+        let out = qc
+            .process(packet(600, &[("barometer", 1011.0)]))
+            .await
+            .unwrap();
+        assert!(!out.observations["barometer"].is_null());
+        assert_eq!(qc.stats().failures(QcCheck::RateOfChange), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_flatlined_sensor() {
+        let qc = QualityControl::new(limits(
+            "outHumidity",
+            QcLimits {
+                flatline_secs: Some(3600),
+                ..Default::default()
+            },
+        ));
+        for ts in (0..=3600).step_by(600) {
+            let out = qc
+                .process(packet(ts, &[("outHumidity", 55.0)]))
+                .await
+                .unwrap();
+            assert!(!out.observations["outHumidity"].is_null());
+        }
+        let out = qc
+            .process(packet(4200, &[("outHumidity", 55.0)]))
+            .await
+            .unwrap();
+        assert!(out.observations["outHumidity"].is_null());
+        // Any change means the sensor is alive again
+        let out = qc
+            .process(packet(4800, &[("outHumidity", 56.0)]))
+            .await
+            .unwrap();
+        assert!(!out.observations["outHumidity"].is_null());
+        assert_eq!(qc.stats().failures(QcCheck::Flatline), 1);
+    }
+}
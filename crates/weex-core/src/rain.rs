@@ -0,0 +1,311 @@
+//! Rain from cumulative counters
+//!
+//! Many stations (Ecowitt among them) report rain only as running totals
+//! (`dayRain`, `weekRain`, `monthRain`, `yearRain`, `totalRain`) or as a raw
+//! tipping-bucket count. [`RainCounter`] turns successive counter readings into
+//! the per-packet `rain` that archive records sum. The last reading is kept in
+//! a [`StateStore`], so no rain is lost or double counted across restarts.
+
+use anyhow::Result;
+use chrono::{Datelike, Duration, NaiveDate, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::pipeline::Processor;
+use crate::store::StateStore;
+use crate::types::{ObservationValue, WeatherPacket};
+
+/// Prefix of the state-store key holding a counter's last reading
+pub const STATE_KEY_PREFIX: &str = "rain_counter.";
+
+/// A gap between readings longer than this that spans the counter's reset
+/// means the station reset it unseen, even if the counter did not go down
+const MISSED_RESET_GAP: i64 = 900;
+
+/// When a station resets a periodic counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterPeriod {
+    Day,
+    /// Weeks start on Sunday, as in WeeWX
+    Week,
+    Month,
+    Year,
+}
+
+impl CounterPeriod {
+    /// The reset period of a built-in counter observation
+    pub fn for_counter(obs: &str) -> Option<Self> {
+        match obs {
+            "dayRain" => Some(Self::Day),
+            "weekRain" => Some(Self::Week),
+            "monthRain" => Some(Self::Month),
+            "yearRain" => Some(Self::Year),
+            _ => None,
+        }
+    }
+
+    /// First day of the period containing `date`
+    fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Day => date,
+            Self::Week => date - Duration::days(date.weekday().num_days_from_sunday().into()),
+            Self::Month => date.with_day(1).unwrap_or(date),
+            Self::Year => date.with_ordinal(1).unwrap_or(date),
+        }
+    }
+}
+
+/// Configuration for [`RainCounter`]
+#[derive(Debug, Clone)]
+pub struct RainCounterOptions {
+    /// Counter observation, e.g. `dayRain`, `totalRain` or a raw tip count
+    pub counter: String,
+    /// Rain per count, in the packets' rain unit (1 for rain totals)
+    pub tip_size: f64,
+    /// Modulus of a counter that wraps around (e.g. 65536 for a 16-bit tip
+    /// count). Without one, a decreasing counter is taken as a reset.
+    pub wrap: Option<f64>,
+    /// Station timezone, whose local midnight periodic counters reset at
+    pub timezone: Tz,
+}
+
+impl Default for RainCounterOptions {
+    fn default() -> Self {
+        Self {
+            counter: "dayRain".to_string(),
+            tip_size: 1.0,
+            wrap: None,
+            timezone: Tz::UTC,
+        }
+    }
+}
+
+/// One counter reading, as persisted in the state store
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Reading {
+    #[serde(rename = "dateTime")]
+    date_time: i64,
+    value: f64,
+}
+
+#[derive(Default)]
+struct CounterState {
+    loaded: bool,
+    last: Option<Reading>,
+}
+
+/// Derives per-packet `rain` from a cumulative rain counter
+pub struct RainCounter {
+    opts: RainCounterOptions,
+    period: Option<CounterPeriod>,
+    store: Option<Arc<dyn StateStore>>,
+    state: Mutex<CounterState>,
+}
+
+impl RainCounter {
+    pub fn new(opts: RainCounterOptions) -> Self {
+        Self {
+            period: CounterPeriod::for_counter(&opts.counter),
+            opts,
+            store: None,
+            state: Mutex::new(CounterState::default()),
+        }
+    }
+
+    /// Persist the last reading in `store` (e.g. the database's
+    /// `archive_metadata`) so it survives restarts
+    pub fn with_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    fn state_key(&self) -> String {
+        format!("{}{}", STATE_KEY_PREFIX, self.opts.counter)
+    }
+
+    async fn load(&self) -> Option<Reading> {
+        let store = self.store.as_ref()?;
+        match store.load(&self.state_key()).await {
+            Ok(value) => serde_json::from_str(&value?).ok(),
+            Err(e) => {
+                tracing::warn!(error = %e, counter = %self.opts.counter, "failed to load rain counter state");
+                None
+            }
+        }
+    }
+
+    async fn save(&self, reading: Reading) {
+        let Some(store) = self.store.as_ref() else {
+            return;
+        };
+        let Ok(value) = serde_json::to_string(&reading) else {
+            return;
+        };
+        if let Err(e) = store.save(&self.state_key(), &value).await {
+            tracing::warn!(error = %e, counter = %self.opts.counter, "failed to save rain counter state");
+        }
+    }
+
+    /// Whether the counter's reset period changed between two timestamps
+    fn period_rolled(&self, from: i64, to: i64) -> bool {
+        let Some(period) = self.period else {
+            return false;
+        };
+        let local = |ts: i64| {
+            self.opts
+                .timezone
+                .timestamp_opt(ts, 0)
+                .single()
+                .map(|t| period.start(t.date_naive()))
+        };
+        local(from) != local(to)
+    }
+
+    /// Counts between two readings
+    fn delta(&self, last: Reading, now: Reading) -> f64 {
+        if now.value < last.value {
+            return match self.opts.wrap {
+                Some(wrap) => now.value + wrap - last.value,
+                None => {
+                    tracing::info!(
+                        counter = %self.opts.counter,
+                        from = last.value,
+                        to = now.value,
+                        "rain counter reset"
+                    );
+                    now.value
+                }
+            };
+        }
+        if now.date_time - last.date_time > MISSED_RESET_GAP
+            && self.period_rolled(last.date_time, now.date_time)
+        {
+            return now.value;
+        }
+        now.value - last.value
+    }
+}
+
+#[async_trait::async_trait]
+impl Processor for RainCounter {
+    async fn process(&self, mut packet: WeatherPacket) -> Result<WeatherPacket> {
+        let Some(value) = packet
+            .observations
+            .get(&self.opts.counter)
+            .and_then(|v| v.as_f64())
+        else {
+            return Ok(packet);
+        };
+        let now = Reading {
+            date_time: packet.date_time,
+            value,
+        };
+
+        let mut state = self.state.lock().await;
+        if !state.loaded {
+            state.last = self.load().await;
+            state.loaded = true;
+        }
+        let last = state.last;
+        if last.is_some_and(|l| now.date_time < l.date_time) {
+            // A late packet; the counter has moved on since
+            return Ok(packet);
+        }
+        state.last = Some(now);
+        drop(state);
+
+        if last.map_or(true, |l| l.value != now.value) {
+            self.save(now).await;
+        }
+        // The station's own per-packet rain, if it sends one, wins
+        if let Some(last) = last {
+            let rain = self.delta(last, now) * self.opts.tip_size;
+            packet
+                .observations
+                .entry("rain".to_string())
+                .or_insert(ObservationValue::Float(rain));
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn packet(ts: i64, obs: &str, value: f64) -> WeatherPacket {
+        WeatherPacket {
+            date_time: ts,
+            station: None,
+            interval: None,
+            observations: [(obs.to_string(), ObservationValue::Float(value))].into(),
+        }
+    }
+
+    async fn rain(counter: &RainCounter, ts: i64, obs: &str, value: f64) -> Option<f64> {
+        let out = counter.process(packet(ts, obs, value)).await.unwrap();
+        out.observations.get("rain").and_then(|v| v.as_f64())
+    }
+
+    fn close(a: Option<f64>, b: f64) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-9)
+    }
+
+    #[tokio::test]
+    async fn daily_counter_to_deltas_across_midnight() {
+        let counter = RainCounter::new(RainCounterOptions::default());
+        // 2024-03-01 23:50 UTC
+        let t = 1_709_337_000;
+        assert_eq!(rain(&counter, t, "dayRain", 0.30).await, None);
+        assert!(close(rain(&counter, t + 60, "dayRain", 0.32).await, 0.02));
+        assert!(close(rain(&counter, t + 120, "dayRain", 0.32).await, 0.0));
+        // Station resets at midnight
+        assert!(close(rain(&counter, t + 660, "dayRain", 0.01).await, 0.01));
+        assert!(close(rain(&counter, t + 720, "dayRain", 0.05).await, 0.04));
+        // Late packet is left alone
+        assert_eq!(rain(&counter, t + 700, "dayRain", 0.04).await, None);
+    }
+
+    #[tokio::test]
+    async fn tip_counter_wraps_around() {
+        let counter = RainCounter::new(RainCounterOptions {
+            counter: "rainCount".to_string(),
+            tip_size: 0.2,
+            wrap: Some(256.0),
+            ..Default::default()
+        });
+        rain(&counter, 0, "rainCount", 250.0).await;
+        assert!(close(rain(&counter, 60, "rainCount", 253.0).await, 0.6));
+        assert!(close(rain(&counter, 120, "rainCount", 1.0).await, 0.8));
+    }
+
+    #[tokio::test]
+    async fn state_survives_restart_and_missed_reset() {
+        let store: Arc<MemoryStore> = Arc::new(MemoryStore::new());
+        let opts = RainCounterOptions {
+            timezone: "America/New_York".parse().unwrap(),
+            ..Default::default()
+        };
+        // 2024-03-01 22:00 EST
+        let t = 1_709_348_400;
+        let counter = RainCounter::new(opts.clone()).with_store(store.clone());
+        rain(&counter, t, "dayRain", 0.50).await;
+
+        // Restarted an hour later, same local day: continue from 0.50
+        let counter = RainCounter::new(opts.clone()).with_store(store.clone());
+        assert!(close(rain(&counter, t + 3600, "dayRain", 0.60).await, 0.10));
+
+        // Restarted the next morning: the counter was reset at local
+        // midnight, so everything it shows fell since then
+        let counter = RainCounter::new(opts).with_store(store.clone());
+        assert!(close(
+            rain(&counter, t + 12 * 3600, "dayRain", 0.70).await,
+            0.70
+        ));
+        let saved = store.load("rain_counter.dayRain").await.unwrap().unwrap();
+        assert!(saved.contains("0.7"));
+    }
+}
@@ -1,89 +1,415 @@
 //! Aggregation and rollup calculations for archive intervals
 
 use crate::types::{AggregateType, WeatherPacket};
+use crate::units::{convert_unit, Unit, UnitGroup};
 use std::collections::HashMap;
+use tracing::debug;
 
-/// Accumulator for calculating aggregates over multiple observations
+/// Direction observations and the speed that weights them in vector and
+/// gust-direction aggregation
+pub const WIND_VECTORS: &[(&str, &str)] = &[("windDir", "windSpeed"), ("windGustDir", "windGust")];
+
+/// One accumulated value and, when known, its timestamp (epoch seconds)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub value: f64,
+    pub time: Option<i64>,
+}
+
+/// Streaming accumulator for calculating aggregates over multiple
+/// observations. Memory use is constant, as only running statistics are kept,
+/// except for [`AggregateType::Median`] and [`AggregateType::Percentile`],
+/// which need every value.
 #[derive(Debug, Clone)]
 pub struct Accumulator {
-    observations: Vec<f64>,
     aggregate_type: AggregateType,
+    time_weighted: bool,
+    count: usize,
+    sum: f64,
+    sum_sq: f64,
+    /// Running mean and sum of squared deviations (Welford)
+    mean: f64,
+    m2: f64,
+    min: Option<Sample>,
+    max: Option<Sample>,
+    first: Option<Sample>,
+    last: Option<Sample>,
+    /// Integral over time of the linearly interpolated value (value x seconds)
+    /// and the number of seconds it covers
+    integral: f64,
+    span: i64,
+    /// Timestamped samples that arrived after a later one and so were left
+    /// out of the integral
+    out_of_order: usize,
+    /// Sum of the (east, north) components of added vectors
+    vector_sum: (f64, f64),
+    /// The strongest vector added, as (speed, direction)
+    max_vector: Option<(f64, f64)>,
+    /// Every value, kept only for median and percentiles
+    values: Option<Vec<f64>>,
 }
 
 impl Accumulator {
     pub fn new(aggregate_type: AggregateType) -> Self {
         Self {
-            observations: Vec::new(),
             aggregate_type,
+            time_weighted: false,
+            count: 0,
+            sum: 0.0,
+            sum_sq: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            min: None,
+            max: None,
+            first: None,
+            last: None,
+            integral: 0.0,
+            span: 0,
+            out_of_order: 0,
+            vector_sum: (0.0, 0.0),
+            max_vector: None,
+            values: matches!(
+                aggregate_type,
+                AggregateType::Median | AggregateType::Percentile(_)
+            )
+            .then(Vec::new),
         }
     }
 
+    /// Average over time rather than per sample, so bursts of closely spaced
+    /// packets do not outweigh the rest of the interval. Needs timestamped
+    /// values (see [`Accumulator::add_at`]).
+    pub fn time_weighted(mut self, enabled: bool) -> Self {
+        self.time_weighted = enabled;
+        self
+    }
+
+    /// Add a value without a timestamp
     pub fn add(&mut self, value: f64) {
-        self.observations.push(value);
+        self.push(Sample { value, time: None });
+    }
+
+    /// Add a value observed at `date_time` (epoch seconds). Values may
+    /// arrive out of order; first and last follow the timestamps.
+    pub fn add_at(&mut self, date_time: i64, value: f64) {
+        self.push(Sample {
+            value,
+            time: Some(date_time),
+        });
+    }
+
+    /// Add a vector given by its magnitude and compass direction (degrees).
+    /// The magnitude also counts as a scalar value.
+    pub fn add_vector(&mut self, speed: f64, dir: f64) {
+        let rad = dir.to_radians();
+        self.vector_sum.0 += speed * rad.sin();
+        self.vector_sum.1 += speed * rad.cos();
+        if self.max_vector.map_or(true, |(max, _)| speed > max) {
+            self.max_vector = Some((speed, dir));
+        }
+        self.add(speed);
+    }
+
+    fn push(&mut self, sample: Sample) {
+        self.count += 1;
+        self.sum += sample.value;
+        self.sum_sq += sample.value * sample.value;
+        let delta = sample.value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (sample.value - self.mean);
+        if let Some(values) = self.values.as_mut() {
+            values.push(sample.value);
+        }
+        if self.min.map_or(true, |m| sample.value < m.value) {
+            self.min = Some(sample);
+        }
+        if self.max.map_or(true, |m| sample.value > m.value) {
+            self.max = Some(sample);
+        }
+        if self.first.map_or(true, |f| is_before(sample, f)) {
+            self.first = Some(sample);
+        }
+        match self.last {
+            Some(last) if is_before(sample, last) => {
+                self.out_of_order += 1;
+                if self.time_weighted {
+                    debug!(
+                        time = sample.time,
+                        last = last.time,
+                        "out-of-order sample left out of the time-weighted average"
+                    );
+                }
+            }
+            Some(last) => {
+                self.integrate(last, sample);
+                self.last = Some(sample);
+            }
+            None => self.last = Some(sample),
+        }
+    }
+
+    /// Add the trapezoid between two consecutive timestamped samples
+    fn integrate(&mut self, from: Sample, to: Sample) {
+        if let (Some(t0), Some(t1)) = (from.time, to.time) {
+            if t1 > t0 {
+                self.integral += (from.value + to.value) / 2.0 * (t1 - t0) as f64;
+                self.span += t1 - t0;
+            }
+        }
+    }
+
+    /// Combine with an accumulator for another stretch of time (e.g. a
+    /// partial interval), as if all its values had been added here
+    pub fn merge(&mut self, other: &Accumulator) {
+        let (Some(other_first), Some(other_last)) = (other.first, other.last) else {
+            return;
+        };
+        let (Some(first), Some(last)) = (self.first, self.last) else {
+            let (aggregate_type, time_weighted) = (self.aggregate_type, self.time_weighted);
+            let keep_values = self.values.is_some();
+            *self = other.clone();
+            self.aggregate_type = aggregate_type;
+            self.time_weighted = time_weighted;
+            if keep_values && self.values.is_none() {
+                self.values = Some(Vec::new());
+            }
+            return;
+        };
+
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let delta = other.mean - self.mean;
+        self.mean += delta * n_b / (n_a + n_b);
+        self.m2 += other.m2 + delta * delta * n_a * n_b / (n_a + n_b);
+        self.count += other.count;
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        if let (Some(values), Some(more)) = (self.values.as_mut(), other.values.as_ref()) {
+            values.extend_from_slice(more);
+        }
+        self.integral += other.integral;
+        self.span += other.span;
+        self.out_of_order += other.out_of_order;
+        self.vector_sum.0 += other.vector_sum.0;
+        self.vector_sum.1 += other.vector_sum.1;
+        if let Some((speed, dir)) = other.max_vector {
+            if self.max_vector.map_or(true, |(max, _)| speed > max) {
+                self.max_vector = Some((speed, dir));
+            }
+        }
+        if other
+            .min
+            .is_some_and(|o| self.min.map_or(true, |m| o.value < m.value))
+        {
+            self.min = other.min;
+        }
+        if other
+            .max
+            .is_some_and(|o| self.max.map_or(true, |m| o.value > m.value))
+        {
+            self.max = other.max;
+        }
+
+        // Bridge the gap between the two stretches
+        if is_before(last, other_first) {
+            self.integrate(last, other_first);
+        } else if is_before(other_last, first) {
+            self.integrate(other_last, first);
+        }
+        if is_before(other_first, first) {
+            self.first = Some(other_first);
+        }
+        if !is_before(other_last, last) {
+            self.last = Some(other_last);
+        }
     }
 
     pub fn result(&self) -> Option<f64> {
-        if self.observations.is_empty() {
-            return None;
+        if self.count == 0 {
+            // Only nulls (or nothing) were seen
+            return (self.aggregate_type == AggregateType::Count).then_some(0.0);
         }
 
         Some(match self.aggregate_type {
-            AggregateType::Min => self
-                .observations
-                .iter()
-                .copied()
-                .fold(f64::INFINITY, f64::min),
-            AggregateType::Max => self
-                .observations
-                .iter()
-                .copied()
-                .fold(f64::NEG_INFINITY, f64::max),
-            AggregateType::Sum => self.observations.iter().sum(),
-            AggregateType::Avg => {
-                let sum: f64 = self.observations.iter().sum();
-                sum / self.observations.len() as f64
+            AggregateType::Min => self.min?.value,
+            AggregateType::Max => self.max?.value,
+            AggregateType::Sum => self.sum,
+            AggregateType::Avg => self.mean()?,
+            AggregateType::Last => self.last?.value,
+            AggregateType::First => self.first?.value,
+            AggregateType::Count => self.count as f64,
+            AggregateType::VecAvg => {
+                let (x, y) = self.vector_sum;
+                x.hypot(y) / self.count as f64
             }
-            AggregateType::Last => self.observations.last().copied()?,
-            AggregateType::First => self.observations.first().copied()?,
-            AggregateType::Count => self.observations.len() as f64,
+            AggregateType::VecDir => {
+                // All-calm intervals have no direction
+                let (x, y) = self.vector_sum;
+                if x == 0.0 && y == 0.0 {
+                    return None;
+                }
+                x.atan2(y).to_degrees().rem_euclid(360.0)
+            }
+            AggregateType::StdDev => (self.m2 / self.count as f64).sqrt(),
+            AggregateType::Rms => (self.sum_sq / self.count as f64).sqrt(),
+            AggregateType::Median => self.percentile(50.0)?,
+            AggregateType::Percentile(p) => self.percentile(f64::from(p))?,
+            AggregateType::MinTime => self.min?.time? as f64,
+            AggregateType::MaxTime => self.max?.time? as f64,
+            AggregateType::GustDir => self.max_vector?.1,
         })
     }
 
+    /// The `p`-th percentile (0-100) by linear interpolation between closest
+    /// ranks; only available for median and percentile accumulators
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        let mut values = self.values.clone()?;
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let rank = p.clamp(0.0, 100.0) / 100.0 * (values.len() - 1) as f64;
+        let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+        Some(values[lo] + (values[hi] - values[lo]) * (rank - lo as f64))
+    }
+
+    /// Mean of the values: over time when time-weighted and the values span
+    /// some time, per sample otherwise
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        if self.time_weighted && self.span > 0 {
+            return Some(self.integral / self.span as f64);
+        }
+        Some(self.sum / self.count as f64)
+    }
+
     pub fn count(&self) -> usize {
-        self.observations.len()
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// How many timestamped values arrived after a later one; they count
+    /// everywhere except in the time-weighted mean
+    pub fn out_of_order(&self) -> usize {
+        self.out_of_order
+    }
+
+    pub fn min(&self) -> Option<Sample> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Sample> {
+        self.max
+    }
+
+    pub fn first(&self) -> Option<Sample> {
+        self.first
+    }
+
+    pub fn last(&self) -> Option<Sample> {
+        self.last
+    }
+
+    /// When the minimum was observed (the first one added, on ties)
+    pub fn min_time(&self) -> Option<i64> {
+        self.min?.time
+    }
+
+    /// When the maximum was observed (the first one added, on ties)
+    pub fn max_time(&self) -> Option<i64> {
+        self.max?.time
     }
 }
 
-/// Default aggregate type for common observation types
+/// Whether `a` was observed before `b`; untimed samples keep insertion order
+fn is_before(a: Sample, b: Sample) -> bool {
+    matches!((a.time, b.time), (Some(ta), Some(tb)) if ta < tb)
+}
+
+/// How [`aggregate_packets_with`] reduces packets
+#[derive(Debug, Clone, Default)]
+pub struct RollupOptions {
+    /// Time-weight averages by packet timestamps
+    pub time_weighted: bool,
+    /// Per-observation aggregates, overriding the obs-type registry
+    pub aggregates: HashMap<String, AggregateType>,
+}
+
+/// Default aggregate type for an observation type (see [`crate::obstypes`])
 pub fn default_aggregate_type(obs_type: &str) -> AggregateType {
-    match obs_type {
-        "rain" => AggregateType::Sum,
-        "outTemp" | "inTemp" | "dewpoint" | "heatindex" | "windchill" => AggregateType::Avg,
-        "barometer" | "pressure" | "altimeter" => AggregateType::Avg,
-        "windSpeed" => AggregateType::Avg,
-        "windGust" => AggregateType::Max,
-        "windDir" | "windGustDir" => AggregateType::Last,
-        "outHumidity" | "inHumidity" => AggregateType::Avg,
-        "radiation" => AggregateType::Avg,
-        "UV" => AggregateType::Avg,
-        _ => AggregateType::Last,
-    }
+    crate::obstypes::obs_registry().aggregate_type(obs_type)
+}
+
+/// Distance the wind travelled over `interval_secs` at `speed`, in the units
+/// `unit_system` uses for speed and distance (e.g. mph gives miles)
+pub fn windrun(speed: f64, interval_secs: i64, unit_system: i32) -> Option<f64> {
+    let speed_unit = UnitGroup::Speed.unit_for(unit_system).ok()?;
+    let distance_unit = UnitGroup::Distance.unit_for(unit_system).ok()?;
+    let mps = convert_unit(speed, speed_unit, Unit::MeterPerSecond).ok()?;
+    let km = mps * interval_secs as f64 / 1000.0;
+    convert_unit(km, Unit::Km, distance_unit).ok()
 }
 
 /// Aggregate multiple weather packets into summary values
+///
+/// Directions aggregated with [`AggregateType::VecAvg`] or
+/// [`AggregateType::VecDir`] are weighted by their speed from the same packet
+/// (see [`WIND_VECTORS`]). `windGustDir` is the direction reported with the
+/// interval's highest `windGust` ([`AggregateType::GustDir`]). Null values
+/// only count towards [`AggregateType::Count`], which is zero when every value
+/// was null.
 pub fn aggregate_packets(
     packets: &[WeatherPacket],
 ) -> HashMap<String, (AggregateType, Option<f64>)> {
+    aggregate_packets_with(packets, &RollupOptions::default())
+}
+
+/// [`aggregate_packets`] with explicit [`RollupOptions`]
+pub fn aggregate_packets_with(
+    packets: &[WeatherPacket],
+    opts: &RollupOptions,
+) -> HashMap<String, (AggregateType, Option<f64>)> {
+    let registry = crate::obstypes::obs_registry();
     let mut accumulators: HashMap<String, Accumulator> = HashMap::new();
 
     for packet in packets {
+        // Aliases (e.g. "uv") accumulate under the canonical name; nulls are
+        // None
+        let mut values: HashMap<&str, Option<f64>> = HashMap::new();
         for (key, value) in &packet.observations {
-            if let Some(numeric_value) = value.as_f64() {
-                let aggregate_type = default_aggregate_type(key);
-                accumulators
-                    .entry(key.clone())
-                    .or_insert_with(|| Accumulator::new(aggregate_type))
-                    .add(numeric_value);
+            if value.is_null() || value.as_f64().is_some() {
+                let slot = values.entry(registry.canonical(key)).or_default();
+                *slot = slot.or(value.as_f64());
+            }
+        }
+
+        for (&name, &value) in &values {
+            let aggregate_type = opts
+                .aggregates
+                .get(name)
+                .copied()
+                .unwrap_or_else(|| registry.aggregate_type(name));
+            let acc = accumulators.entry(name.to_string()).or_insert_with(|| {
+                Accumulator::new(aggregate_type).time_weighted(opts.time_weighted)
+            });
+            let Some(value) = value else {
+                continue;
+            };
+            match aggregate_type {
+                AggregateType::VecAvg | AggregateType::VecDir | AggregateType::GustDir => {
+                    let speed = WIND_VECTORS
+                        .iter()
+                        .find(|(dir, _)| *dir == name)
+                        .and_then(|(_, speed)| values.get(speed).copied().flatten());
+                    if let Some(speed) = speed {
+                        acc.add_vector(speed, value);
+                    }
+                }
+                _ => acc.add_at(packet.date_time, value),
             }
         }
     }
@@ -152,11 +478,222 @@ mod tests {
         assert_eq!(acc.result(), None);
     }
 
+    #[test]
+    fn test_accumulator_tracks_times() {
+        let mut acc = Accumulator::new(AggregateType::Max);
+        acc.add_at(120, 3.0);
+        acc.add_at(60, 7.0);
+        acc.add_at(180, 7.0);
+        acc.add_at(0, 1.0);
+        assert_eq!(acc.result(), Some(7.0));
+        assert_eq!(acc.max_time(), Some(60));
+        assert_eq!(acc.min_time(), Some(0));
+        assert_eq!(acc.first().map(|s| s.value), Some(1.0));
+        assert_eq!(acc.last().map(|s| s.value), Some(7.0));
+        assert_eq!((acc.count(), acc.sum()), (4, 18.0));
+    }
+
+    #[test]
+    fn test_accumulator_time_weighted_mean() {
+        // A burst of packets at 30 must not dominate the average
+        let mut plain = Accumulator::new(AggregateType::Avg);
+        let mut weighted = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        for (t, v) in [
+            (0, 10.0),
+            (290, 10.0),
+            (295, 30.0),
+            (296, 30.0),
+            (300, 30.0),
+        ] {
+            plain.add_at(t, v);
+            weighted.add_at(t, v);
+        }
+        assert_eq!(plain.result(), Some(22.0));
+        let expected = (10.0 * 290.0 + 20.0 * 5.0 + 30.0 * 5.0) / 300.0;
+        assert!((weighted.result().unwrap() - expected).abs() < 1e-9);
+
+        // A single sample has no span and falls back to the plain mean
+        let mut one = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        one.add_at(0, 4.0);
+        assert_eq!(one.result(), Some(4.0));
+    }
+
+    #[test]
+    fn test_accumulator_time_weighted_out_of_order() {
+        // The late sample at 100 is counted but not integrated
+        let mut acc = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        acc.add_at(0, 10.0);
+        acc.add_at(200, 10.0);
+        acc.add_at(100, 40.0);
+        acc.add_at(300, 20.0);
+        assert_eq!(acc.out_of_order(), 1);
+        assert_eq!(acc.count(), 4);
+        let expected = (10.0 * 200.0 + 15.0 * 100.0) / 300.0;
+        assert!((acc.result().unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_accumulator_merge() {
+        let samples = [(0, 5.0), (60, 9.0), (120, 2.0), (180, 4.0), (240, 6.0)];
+        let mut whole = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        for (t, v) in samples {
+            whole.add_at(t, v);
+        }
+
+        // Merging in either order matches accumulating everything at once
+        let mut early = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        let mut late = Accumulator::new(AggregateType::Avg).time_weighted(true);
+        for (t, v) in samples {
+            if t < 120 {
+                early.add_at(t, v);
+            } else {
+                late.add_at(t, v);
+            }
+        }
+        let mut merged = late.clone();
+        merged.merge(&early);
+        assert!((merged.result().unwrap() - whole.result().unwrap()).abs() < 1e-9);
+        early.merge(&late);
+        assert!((early.result().unwrap() - whole.result().unwrap()).abs() < 1e-9);
+        assert_eq!(early.min_time(), Some(120));
+        assert_eq!(early.max_time(), Some(60));
+        assert_eq!(early.first(), whole.first());
+        assert_eq!(early.last(), whole.last());
+        assert_eq!(early.count(), 5);
+
+        let mut empty = Accumulator::new(AggregateType::Max);
+        empty.merge(&whole);
+        assert_eq!(empty.result(), Some(9.0));
+    }
+
+    #[test]
+    fn test_accumulator_statistics() {
+        let values = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        let result = |agg: AggregateType| {
+            let mut acc = Accumulator::new(agg);
+            for (t, v) in values.iter().enumerate() {
+                acc.add_at(t as i64 * 60, *v);
+            }
+            acc.result().unwrap()
+        };
+        assert_eq!(result(AggregateType::StdDev), 2.0);
+        assert!((result(AggregateType::Rms) - 29f64.sqrt()).abs() < 1e-9);
+        assert_eq!(result(AggregateType::Median), 4.5);
+        assert_eq!(result(AggregateType::Percentile(0)), 2.0);
+        assert!((result(AggregateType::Percentile(90)) - 7.6).abs() < 1e-9);
+        assert_eq!(result(AggregateType::MinTime), 0.0);
+        assert_eq!(result(AggregateType::MaxTime), 420.0);
+
+        // Merged partial results give the same spread
+        let (mut a, mut b) = (
+            Accumulator::new(AggregateType::StdDev),
+            Accumulator::new(AggregateType::StdDev),
+        );
+        values[..3].iter().for_each(|v| a.add(*v));
+        values[3..].iter().for_each(|v| b.add(*v));
+        a.merge(&b);
+        assert!((a.result().unwrap() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_aggregate_counts_and_nulls() {
+        use crate::types::ObservationValue;
+
+        let packet = |rain: ObservationValue| WeatherPacket {
+            date_time: 0,
+            station: None,
+            interval: None,
+            observations: HashMap::from([("rain".to_string(), rain)]),
+        };
+        let opts = RollupOptions {
+            aggregates: HashMap::from([("rain".to_string(), AggregateType::Count)]),
+            ..Default::default()
+        };
+        let nulls = [
+            packet(ObservationValue::Null),
+            packet(ObservationValue::Null),
+        ];
+        assert_eq!(aggregate_packets_with(&nulls, &opts)["rain"].1, Some(0.0));
+        assert_eq!(
+            aggregate_packets(&nulls)["rain"],
+            (AggregateType::Sum, None)
+        );
+
+        let mixed = [
+            packet(ObservationValue::Null),
+            packet(ObservationValue::Float(0.2)),
+        ];
+        assert_eq!(aggregate_packets_with(&mixed, &opts)["rain"].1, Some(1.0));
+    }
+
+    #[test]
+    fn test_accumulator_vector() {
+        let mut dir = Accumulator::new(AggregateType::VecDir);
+        let mut avg = Accumulator::new(AggregateType::VecAvg);
+        for (speed, d) in [(2.0, 350.0), (2.0, 10.0), (0.0, 180.0)] {
+            dir.add_vector(speed, d);
+            avg.add_vector(speed, d);
+        }
+        let d = dir.result().unwrap();
+        assert!(d.min(360.0 - d) < 1e-9);
+        assert!((avg.result().unwrap() - 4.0 * 10f64.to_radians().cos() / 3.0).abs() < 1e-9);
+
+        // A stronger wind pulls the direction towards it
+        let mut dir = Accumulator::new(AggregateType::VecDir);
+        dir.add_vector(1.0, 0.0);
+        dir.add_vector(3.0, 90.0);
+        assert!((dir.result().unwrap() - 71.565).abs() < 1e-3);
+
+        let mut calm = Accumulator::new(AggregateType::VecDir);
+        calm.add_vector(0.0, 90.0);
+        assert_eq!(calm.result(), None);
+    }
+
+    #[test]
+    fn test_aggregate_wind() {
+        use crate::types::ObservationValue;
+
+        let packet = |speed: f64, dir: f64, gust: f64, gust_dir: f64| WeatherPacket {
+            date_time: 0,
+            station: None,
+            interval: None,
+            observations: [
+                ("windSpeed", speed),
+                ("windDir", dir),
+                ("windGust", gust),
+                ("windGustDir", gust_dir),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), ObservationValue::Float(v)))
+            .collect(),
+        };
+        let aggregates = aggregate_packets(&[
+            packet(4.0, 270.0, 9.0, 260.0),
+            packet(4.0, 280.0, 12.0, 290.0),
+            packet(1.0, 90.0, 5.0, 80.0),
+        ]);
+        let (agg, dir) = aggregates["windDir"];
+        assert_eq!(agg, AggregateType::VecDir);
+        assert!((dir.unwrap() - 275.7).abs() < 0.1);
+        assert_eq!(aggregates["windGust"].1, Some(12.0));
+        assert_eq!(aggregates["windGustDir"].1, Some(290.0));
+    }
+
+    #[test]
+    fn test_windrun() {
+        use crate::types::unit_systems;
+
+        let mph = windrun(10.0, 300, unit_systems::US).unwrap();
+        assert!((mph - 10.0 / 12.0).abs() < 1e-9);
+        let mps = windrun(2.0, 300, unit_systems::METRICWX).unwrap();
+        assert!((mps - 0.6).abs() < 1e-9);
+    }
+
     #[test]
     fn test_default_aggregate_types() {
         assert_eq!(default_aggregate_type("rain"), AggregateType::Sum);
         assert_eq!(default_aggregate_type("outTemp"), AggregateType::Avg);
         assert_eq!(default_aggregate_type("windGust"), AggregateType::Max);
-        assert_eq!(default_aggregate_type("windDir"), AggregateType::Last);
+        assert_eq!(default_aggregate_type("windDir"), AggregateType::VecDir);
     }
 }
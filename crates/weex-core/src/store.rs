@@ -0,0 +1,43 @@
+//! Small key/value store for state that must survive restarts
+//!
+//! Processors that carry state between packets (e.g. the last rain counter
+//! reading) persist it through a [`StateStore`]. The WeeWX database provides
+//! one backed by `archive_metadata`; [`MemoryStore`] is for tests and
+//! deployments without a database.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[async_trait::async_trait]
+pub trait StateStore: Send + Sync {
+    async fn load(&self, key: &str) -> Result<Option<String>>;
+
+    async fn save(&self, key: &str, value: &str) -> Result<()>;
+}
+
+/// In-process store; state is lost on restart
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    values: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait::async_trait]
+impl StateStore for MemoryStore {
+    async fn load(&self, key: &str) -> Result<Option<String>> {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(values.get(key).cloned())
+    }
+
+    async fn save(&self, key: &str, value: &str) -> Result<()> {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
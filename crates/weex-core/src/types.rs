@@ -29,12 +29,23 @@ pub struct WeatherPacket {
     pub observations: HashMap<String, ObservationValue>,
 }
 
+impl WeatherPacket {
+    /// Unit system from the packet's `usUnits` field, if it carries one
+    pub fn unit_system(&self) -> Option<i32> {
+        self.observations
+            .get("usUnits")
+            .and_then(|v| v.as_i64())
+            .map(|v| v as i32)
+    }
+}
+
 /// An observation value with optional null handling
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
 pub enum ObservationValue {
     Float(f64),
     Integer(i64),
+    Bool(bool),
     String(String),
     Null,
 }
@@ -80,6 +91,31 @@ pub struct ArchiveRecord {
     pub aggregates: HashMap<String, ObservationValue>,
 }
 
+/// A span of time `(start, stop]` in epoch seconds, as WeeWX's `TimeSpan`:
+/// it holds the archive records stamped after `start`, up to and including
+/// `stop`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TimeSpan {
+    pub start: Timestamp,
+    pub stop: Timestamp,
+}
+
+impl TimeSpan {
+    pub fn new(start: Timestamp, stop: Timestamp) -> Self {
+        Self { start, stop }
+    }
+
+    /// Whether a record stamped `date_time` falls in the span
+    pub fn contains(&self, date_time: Timestamp) -> bool {
+        self.start < date_time && date_time <= self.stop
+    }
+
+    /// Length in seconds
+    pub fn length(&self) -> i64 {
+        self.stop - self.start
+    }
+}
+
 /// Aggregation type for rollups
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -90,7 +126,54 @@ pub enum AggregateType {
     Avg,
     Last,
     First,
+    /// Number of non-null values
     Count,
+    /// Magnitude of the mean wind vector
+    VecAvg,
+    /// Direction of the mean wind vector (compass degrees)
+    VecDir,
+    /// Population standard deviation
+    StdDev,
+    /// Root mean square
+    Rms,
+    Median,
+    /// The p-th percentile (0-100), interpolated between closest ranks
+    Percentile(u8),
+    /// Timestamp of the minimum
+    MinTime,
+    /// Timestamp of the maximum
+    MaxTime,
+    /// Direction reported with the highest wind speed (e.g. gust direction)
+    GustDir,
+}
+
+impl std::str::FromStr for AggregateType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "sum" => Ok(Self::Sum),
+            "avg" => Ok(Self::Avg),
+            "last" => Ok(Self::Last),
+            "first" => Ok(Self::First),
+            "count" => Ok(Self::Count),
+            "vecavg" => Ok(Self::VecAvg),
+            "vecdir" => Ok(Self::VecDir),
+            "stddev" => Ok(Self::StdDev),
+            "rms" => Ok(Self::Rms),
+            "median" => Ok(Self::Median),
+            "mintime" => Ok(Self::MinTime),
+            "maxtime" => Ok(Self::MaxTime),
+            "gustdir" => Ok(Self::GustDir),
+            // "p95" is the 95th percentile
+            _ => match s.strip_prefix('p').map(str::parse::<u8>) {
+                Some(Ok(p)) if p <= 100 => Ok(Self::Percentile(p)),
+                _ => Err(format!("unknown aggregate type: {}", s)),
+            },
+        }
+    }
 }
 
 /// Unit system constants (must match Python WeeWX)
@@ -104,6 +187,15 @@ pub mod unit_systems {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_aggregate_type_from_str() {
+        assert_eq!("stddev".parse(), Ok(AggregateType::StdDev));
+        assert_eq!("gustdir".parse(), Ok(AggregateType::GustDir));
+        assert_eq!("p95".parse(), Ok(AggregateType::Percentile(95)));
+        assert!("p101".parse::<AggregateType>().is_err());
+        assert!("pressure".parse::<AggregateType>().is_err());
+    }
+
     #[test]
     fn test_observation_value_conversions() {
         let float_val = ObservationValue::Float(25.5);
@@ -118,6 +210,17 @@ mod tests {
         assert_eq!(null_val.as_f64(), None);
     }
 
+    #[test]
+    fn test_bool_observation_serde() {
+        let json = r#"{"dateTime":1,"rainAlarm":true,"outTemp":20}"#;
+        let packet: WeatherPacket = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            packet.observations.get("rainAlarm"),
+            Some(&ObservationValue::Bool(true))
+        );
+        assert_eq!(packet.observations["rainAlarm"].as_f64(), None);
+    }
+
     #[test]
     fn test_weather_packet_serde() {
         let json = r#"{"dateTime":1234567890,"outTemp":25.5,"interval":300}"#;
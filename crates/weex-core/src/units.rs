@@ -1,6 +1,12 @@
 //! Unit conversion utilities
 //!
-//! Maintains parity with Python WeeWX unit system and conversions
+//! Maintains parity with Python WeeWX unit system and conversions: every
+//! observation belongs to a unit group, each unit system (US, METRIC,
+//! METRICWX) picks one unit per group, and a value can be converted between
+//! any two units of the same group.
+
+use std::fmt;
+use std::str::FromStr;
 
 use crate::types::unit_systems;
 
@@ -13,6 +19,15 @@ pub enum UnitError {
     #[error("Unknown observation type: {0}")]
     UnknownObservationType(String),
 
+    #[error("Unknown unit group: {0}")]
+    UnknownUnitGroup(String),
+
+    #[error("Unknown unit: {0}")]
+    UnknownUnit(String),
+
+    #[error("Cannot convert {from} to {to}: different unit groups")]
+    IncompatibleUnits { from: Unit, to: Unit },
+
     #[error("Conversion not supported")]
     ConversionNotSupported,
 }
@@ -29,23 +44,317 @@ pub enum UnitGroup {
     Humidity,
     Radiation,
     Count,
+    Altitude,
+    Distance,
+    Uv,
 }
 
-/// Get unit group for an observation type
-pub fn get_unit_group(obs_type: &str) -> Option<UnitGroup> {
-    match obs_type {
-        "outTemp" | "inTemp" | "dewpoint" | "heatindex" | "windchill" => {
-            Some(UnitGroup::Temperature)
+impl UnitGroup {
+    pub const ALL: &'static [UnitGroup] = &[
+        UnitGroup::Temperature,
+        UnitGroup::Pressure,
+        UnitGroup::Rain,
+        UnitGroup::RainRate,
+        UnitGroup::Speed,
+        UnitGroup::Direction,
+        UnitGroup::Humidity,
+        UnitGroup::Radiation,
+        UnitGroup::Count,
+        UnitGroup::Altitude,
+        UnitGroup::Distance,
+        UnitGroup::Uv,
+    ];
+
+    /// WeeWX name of the group
+    pub fn name(self) -> &'static str {
+        match self {
+            UnitGroup::Temperature => "group_temperature",
+            UnitGroup::Pressure => "group_pressure",
+            UnitGroup::Rain => "group_rain",
+            UnitGroup::RainRate => "group_rainrate",
+            UnitGroup::Speed => "group_speed",
+            UnitGroup::Direction => "group_direction",
+            UnitGroup::Humidity => "group_percent",
+            UnitGroup::Radiation => "group_radiation",
+            UnitGroup::Count => "group_count",
+            UnitGroup::Altitude => "group_altitude",
+            UnitGroup::Distance => "group_distance",
+            UnitGroup::Uv => "group_uv",
+        }
+    }
+
+    /// The unit a unit system uses for this group (WeeWX `std_groups`)
+    pub fn unit_for(self, unit_system: i32) -> Result<Unit, UnitError> {
+        use UnitGroup::*;
+        let us = unit_system;
+        if !matches!(
+            us,
+            unit_systems::US | unit_systems::METRIC | unit_systems::METRICWX
+        ) {
+            return Err(UnitError::UnknownUnitSystem(us));
+        }
+        let us_units = us == unit_systems::US;
+        Ok(match self {
+            Temperature if us_units => Unit::DegreeF,
+            Temperature => Unit::DegreeC,
+            Pressure if us_units => Unit::InHg,
+            Pressure => Unit::Mbar,
+            Rain if us_units => Unit::Inch,
+            Rain if us == unit_systems::METRIC => Unit::Cm,
+            Rain => Unit::Mm,
+            RainRate if us_units => Unit::InchPerHour,
+            RainRate if us == unit_systems::METRIC => Unit::CmPerHour,
+            RainRate => Unit::MmPerHour,
+            Speed if us_units => Unit::MilePerHour,
+            Speed if us == unit_systems::METRIC => Unit::KmPerHour,
+            Speed => Unit::MeterPerSecond,
+            Direction => Unit::DegreeCompass,
+            Humidity => Unit::Percent,
+            Radiation => Unit::WattPerMeterSquared,
+            Count => Unit::Count,
+            Altitude if us_units => Unit::Foot,
+            Altitude => Unit::Meter,
+            Distance if us_units => Unit::Mile,
+            Distance => Unit::Km,
+            Uv => Unit::UvIndex,
+        })
+    }
+}
+
+impl FromStr for UnitGroup {
+    type Err = UnitError;
+
+    /// Parses WeeWX group names, with or without the `group_` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.strip_prefix("group_").unwrap_or(s);
+        UnitGroup::ALL
+            .iter()
+            .copied()
+            .find(|g| &g.name()["group_".len()..] == name)
+            .ok_or_else(|| UnitError::UnknownUnitGroup(s.to_string()))
+    }
+}
+
+/// A unit of measure, named as in WeeWX (`degree_F`, `mbar`, ...)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Unit {
+    DegreeF,
+    DegreeC,
+    DegreeK,
+    InHg,
+    Mbar,
+    HPa,
+    KPa,
+    MmHg,
+    Inch,
+    Cm,
+    Mm,
+    InchPerHour,
+    CmPerHour,
+    MmPerHour,
+    MilePerHour,
+    KmPerHour,
+    MeterPerSecond,
+    Knot,
+    DegreeCompass,
+    Percent,
+    WattPerMeterSquared,
+    Count,
+    Foot,
+    Meter,
+    Mile,
+    Km,
+    UvIndex,
+}
+
+impl Unit {
+    pub const ALL: &'static [Unit] = &[
+        Unit::DegreeF,
+        Unit::DegreeC,
+        Unit::DegreeK,
+        Unit::InHg,
+        Unit::Mbar,
+        Unit::HPa,
+        Unit::KPa,
+        Unit::MmHg,
+        Unit::Inch,
+        Unit::Cm,
+        Unit::Mm,
+        Unit::InchPerHour,
+        Unit::CmPerHour,
+        Unit::MmPerHour,
+        Unit::MilePerHour,
+        Unit::KmPerHour,
+        Unit::MeterPerSecond,
+        Unit::Knot,
+        Unit::DegreeCompass,
+        Unit::Percent,
+        Unit::WattPerMeterSquared,
+        Unit::Count,
+        Unit::Foot,
+        Unit::Meter,
+        Unit::Mile,
+        Unit::Km,
+        Unit::UvIndex,
+    ];
+
+    /// WeeWX name of the unit
+    pub fn name(self) -> &'static str {
+        match self {
+            Unit::DegreeF => "degree_F",
+            Unit::DegreeC => "degree_C",
+            Unit::DegreeK => "degree_K",
+            Unit::InHg => "inHg",
+            Unit::Mbar => "mbar",
+            Unit::HPa => "hPa",
+            Unit::KPa => "kPa",
+            Unit::MmHg => "mmHg",
+            Unit::Inch => "inch",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+            Unit::InchPerHour => "inch_per_hour",
+            Unit::CmPerHour => "cm_per_hour",
+            Unit::MmPerHour => "mm_per_hour",
+            Unit::MilePerHour => "mile_per_hour",
+            Unit::KmPerHour => "km_per_hour",
+            Unit::MeterPerSecond => "meter_per_second",
+            Unit::Knot => "knot",
+            Unit::DegreeCompass => "degree_compass",
+            Unit::Percent => "percent",
+            Unit::WattPerMeterSquared => "watt_per_meter_squared",
+            Unit::Count => "count",
+            Unit::Foot => "foot",
+            Unit::Meter => "meter",
+            Unit::Mile => "mile",
+            Unit::Km => "km",
+            Unit::UvIndex => "uv_index",
+        }
+    }
+
+    /// The group this unit measures
+    pub fn group(self) -> UnitGroup {
+        use Unit::*;
+        match self {
+            DegreeF | DegreeC | DegreeK => UnitGroup::Temperature,
+            InHg | Mbar | HPa | KPa | MmHg => UnitGroup::Pressure,
+            Inch | Cm | Mm => UnitGroup::Rain,
+            InchPerHour | CmPerHour | MmPerHour => UnitGroup::RainRate,
+            MilePerHour | KmPerHour | MeterPerSecond | Knot => UnitGroup::Speed,
+            DegreeCompass => UnitGroup::Direction,
+            Percent => UnitGroup::Humidity,
+            WattPerMeterSquared => UnitGroup::Radiation,
+            Count => UnitGroup::Count,
+            Foot | Meter => UnitGroup::Altitude,
+            Mile | Km => UnitGroup::Distance,
+            UvIndex => UnitGroup::Uv,
+        }
+    }
+
+    /// Convert into the group's base unit (degC, mbar, mm, mm/h, m/s, m, km)
+    fn to_base(self, v: f64) -> f64 {
+        use Unit::*;
+        match self {
+            DegreeF => (v - 32.0) * 5.0 / 9.0,
+            DegreeK => v - 273.15,
+            InHg => v * 33.8639,
+            KPa => v * 10.0,
+            MmHg => v * 1.333_224,
+            Inch | InchPerHour => v * 25.4,
+            Cm | CmPerHour => v * 10.0,
+            MilePerHour => v * 0.44704,
+            KmPerHour => v / 3.6,
+            Knot => v * 0.514_444,
+            Foot => v * 0.3048,
+            Mile => v * 1.609_344,
+            _ => v,
+        }
+    }
+
+    /// Inverse of [`Unit::to_base`]
+    fn base_to(self, v: f64) -> f64 {
+        use Unit::*;
+        match self {
+            DegreeF => v * 9.0 / 5.0 + 32.0,
+            DegreeK => v + 273.15,
+            InHg => v / 33.8639,
+            KPa => v / 10.0,
+            MmHg => v / 1.333_224,
+            Inch | InchPerHour => v / 25.4,
+            Cm | CmPerHour => v / 10.0,
+            MilePerHour => v / 0.44704,
+            KmPerHour => v * 3.6,
+            Knot => v / 0.514_444,
+            Foot => v / 0.3048,
+            Mile => v / 1.609_344,
+            _ => v,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Unit {
+    type Err = UnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Unit::ALL
+            .iter()
+            .copied()
+            .find(|u| u.name() == s)
+            .ok_or_else(|| UnitError::UnknownUnit(s.to_string()))
+    }
+}
+
+/// Convert a value between two units of the same group
+pub fn convert_unit(value: f64, from: Unit, to: Unit) -> Result<f64, UnitError> {
+    if from == to {
+        return Ok(value);
+    }
+    if from.group() != to.group() {
+        return Err(UnitError::IncompatibleUnits { from, to });
+    }
+    Ok(to.base_to(from.to_base(value)))
+}
+
+/// A value together with its unit and group (WeeWX `ValueTuple`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueTuple {
+    pub value: Option<f64>,
+    pub unit: Unit,
+    pub group: UnitGroup,
+}
+
+impl ValueTuple {
+    pub fn new(value: Option<f64>, unit: Unit) -> Self {
+        Self {
+            value,
+            unit,
+            group: unit.group(),
         }
-        "barometer" | "pressure" | "altimeter" => Some(UnitGroup::Pressure),
-        "rain" => Some(UnitGroup::Rain),
-        "rainRate" => Some(UnitGroup::RainRate),
-        "windSpeed" | "windGust" => Some(UnitGroup::Speed),
-        "windDir" | "windGustDir" => Some(UnitGroup::Direction),
-        "outHumidity" | "inHumidity" => Some(UnitGroup::Humidity),
-        "radiation" => Some(UnitGroup::Radiation),
-        _ => None,
     }
+
+    /// The same quantity expressed in `unit`
+    pub fn convert(&self, unit: Unit) -> Result<Self, UnitError> {
+        let value = self
+            .value
+            .map(|v| convert_unit(v, self.unit, unit))
+            .transpose()?;
+        Ok(Self::new(value, unit))
+    }
+
+    /// The same quantity in the unit `unit_system` uses for its group
+    pub fn to_unit_system(&self, unit_system: i32) -> Result<Self, UnitError> {
+        self.convert(self.group.unit_for(unit_system)?)
+    }
+}
+
+/// Get unit group for an observation type (see [`crate::obstypes`])
+pub fn get_unit_group(obs_type: &str) -> Option<UnitGroup> {
+    crate::obstypes::obs_registry().unit_group(obs_type)
 }
 
 /// Convert value between unit systems
@@ -58,36 +367,24 @@ pub fn convert(
     if from_unit == to_unit {
         return Ok(value);
     }
-
-    match (from_unit, to_unit, unit_group) {
-        // US to Metric temperature (F to C)
-        (unit_systems::US, unit_systems::METRIC, UnitGroup::Temperature) => {
-            Ok((value - 32.0) * 5.0 / 9.0)
-        }
-        // Metric to US temperature (C to F)
-        (unit_systems::METRIC, unit_systems::US, UnitGroup::Temperature) => {
-            Ok(value * 9.0 / 5.0 + 32.0)
-        }
-        // US to Metric pressure (inHg to mbar)
-        (unit_systems::US, unit_systems::METRIC, UnitGroup::Pressure) => Ok(value * 33.8639),
-        // Metric to US pressure (mbar to inHg)
-        (unit_systems::METRIC, unit_systems::US, UnitGroup::Pressure) => Ok(value / 33.8639),
-        // US to Metric rain (in to cm)
-        (unit_systems::US, unit_systems::METRIC, UnitGroup::Rain) => Ok(value * 2.54),
-        // Metric to US rain (cm to in)
-        (unit_systems::METRIC, unit_systems::US, UnitGroup::Rain) => Ok(value / 2.54),
-        // US to Metric speed (mph to kph)
-        (unit_systems::US, unit_systems::METRIC, UnitGroup::Speed) => Ok(value * 1.60934),
-        // Metric to US speed (kph to mph)
-        (unit_systems::METRIC, unit_systems::US, UnitGroup::Speed) => Ok(value / 1.60934),
-        _ => Err(UnitError::ConversionNotSupported),
-    }
+    convert_unit(
+        value,
+        unit_group.unit_for(from_unit)?,
+        unit_group.unit_for(to_unit)?,
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
     #[test]
     fn test_temperature_conversion() {
         // F to C: 32F = 0C
@@ -140,4 +437,78 @@ mod tests {
         assert_eq!(get_unit_group("windSpeed"), Some(UnitGroup::Speed));
         assert_eq!(get_unit_group("unknown"), None);
     }
+
+    #[test]
+    fn converts_between_all_unit_systems() {
+        use unit_systems::{METRIC, METRICWX, US};
+
+        assert_close(
+            convert(10.0, METRICWX, US, UnitGroup::Speed).unwrap(),
+            22.369,
+        );
+        assert_close(
+            convert(36.0, METRIC, METRICWX, UnitGroup::Speed).unwrap(),
+            10.0,
+        );
+        assert_close(convert(1.0, US, METRICWX, UnitGroup::Rain).unwrap(), 25.4);
+        assert_close(
+            convert(2.54, METRIC, METRICWX, UnitGroup::RainRate).unwrap(),
+            25.4,
+        );
+        assert_close(
+            convert(1013.25, METRICWX, US, UnitGroup::Pressure).unwrap(),
+            29.921,
+        );
+        assert_close(
+            convert(1000.0, US, METRIC, UnitGroup::Altitude).unwrap(),
+            304.8,
+        );
+        assert_eq!(
+            convert(800.0, US, METRICWX, UnitGroup::Radiation).unwrap(),
+            800.0
+        );
+        assert!(matches!(
+            convert(1.0, 2, US, UnitGroup::Rain),
+            Err(UnitError::UnknownUnitSystem(2))
+        ));
+    }
+
+    #[test]
+    fn converts_between_any_units_in_a_group() {
+        assert_close(convert_unit(1.0, Unit::KPa, Unit::MmHg).unwrap(), 7.5006);
+        assert_close(
+            convert_unit(10.0, Unit::Knot, Unit::KmPerHour).unwrap(),
+            18.52,
+        );
+        assert_close(
+            convert_unit(0.0, Unit::DegreeC, Unit::DegreeK).unwrap(),
+            273.15,
+        );
+        assert_close(convert_unit(1.0, Unit::Mile, Unit::Km).unwrap(), 1.609);
+        assert!(matches!(
+            convert_unit(1.0, Unit::Mm, Unit::MmPerHour),
+            Err(UnitError::IncompatibleUnits { .. })
+        ));
+    }
+
+    #[test]
+    fn value_tuple_converts_and_keeps_group() {
+        let vt = ValueTuple::new(Some(68.0), "degree_F".parse().unwrap());
+        assert_eq!(vt.group, UnitGroup::Temperature);
+
+        let metric = vt.to_unit_system(unit_systems::METRICWX).unwrap();
+        assert_eq!(metric.unit, Unit::DegreeC);
+        assert_close(metric.value.unwrap(), 20.0);
+
+        let missing = ValueTuple::new(None, Unit::InHg)
+            .convert(Unit::HPa)
+            .unwrap();
+        assert_eq!(missing, ValueTuple::new(None, Unit::HPa));
+        assert!("furlong".parse::<Unit>().is_err());
+        assert_eq!(
+            "group_percent".parse::<UnitGroup>().unwrap(),
+            UnitGroup::Humidity
+        );
+        assert_eq!("speed".parse::<UnitGroup>().unwrap(), UnitGroup::Speed);
+    }
 }
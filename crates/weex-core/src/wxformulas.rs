@@ -0,0 +1,255 @@
+//! Weather formulas for derived observations
+//!
+//! Ports of the formulas in WeeWX's `weewx.wxformulas` and `weewx.uwxutils`,
+//! in both metric and US flavours where WeeWX offers them. Functions return
+//! `None` where the formula is undefined for the inputs (e.g. zero humidity).
+
+/// Altimeter setting algorithm (WeeWX `altimeter_algorithm`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AltimeterAlgorithm {
+    /// ASOS, as used by the US Automated Surface Observing System (`aaASOS`)
+    #[default]
+    Asos,
+    /// NOAA formula with the 0.3 hPa instrument correction (`aaNOAA`)
+    Noaa,
+}
+
+impl std::str::FromStr for AltimeterAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches("aa").to_ascii_lowercase().as_str() {
+            "asos" => Ok(Self::Asos),
+            "noaa" => Ok(Self::Noaa),
+            _ => Err(format!("unknown altimeter algorithm: {}", s)),
+        }
+    }
+}
+
+/// Mean radius of the earth used for geopotential altitude (meters)
+const EARTH_RADIUS_M: f64 = 6_356_766.0;
+
+fn c_to_f(c: f64) -> f64 {
+    c * 9.0 / 5.0 + 32.0
+}
+
+fn f_to_c(f: f64) -> f64 {
+    (f - 32.0) * 5.0 / 9.0
+}
+
+/// Dewpoint in degC from temperature (degC) and relative humidity (%)
+pub fn dewpoint_c(t_c: f64, rh: f64) -> Option<f64> {
+    if rh <= 0.0 {
+        return None;
+    }
+    let g = 17.27 * t_c / (237.7 + t_c) + (rh / 100.0).ln();
+    Some(237.7 * g / (17.27 - g))
+}
+
+/// Dewpoint in degF from temperature (degF) and relative humidity (%)
+pub fn dewpoint_f(t_f: f64, rh: f64) -> Option<f64> {
+    dewpoint_c(f_to_c(t_f), rh).map(c_to_f)
+}
+
+/// Wind chill in degF from temperature (degF) and wind speed (mph).
+/// Defined only below 50 degF and above 3 mph; otherwise the temperature.
+pub fn windchill_f(t_f: f64, v_mph: f64) -> f64 {
+    if t_f >= 50.0 || v_mph <= 3.0 {
+        return t_f;
+    }
+    let v = v_mph.powf(0.16);
+    35.74 + 0.6215 * t_f + (-35.75 + 0.4275 * t_f) * v
+}
+
+/// Wind chill in degC from temperature (degC) and wind speed (km/h)
+pub fn windchill_c(t_c: f64, v_kph: f64) -> f64 {
+    f_to_c(windchill_f(c_to_f(t_c), v_kph * 0.621371192))
+}
+
+/// Heat index in degF from temperature (degF) and relative humidity (%),
+/// using the NWS algorithm (Rothfusz regression with adjustments)
+pub fn heatindex_f(t_f: f64, rh: f64) -> f64 {
+    if t_f <= 40.0 {
+        return t_f;
+    }
+    // Steadman's simple formula first; the regression only applies above 80
+    let simple = 0.5 * (t_f + 61.0 + (t_f - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t_f) / 2.0 < 80.0 {
+        return simple;
+    }
+    let mut hi = -42.379 + 2.04901523 * t_f + 10.14333127 * rh
+        - 0.22475541 * t_f * rh
+        - 6.83783e-3 * t_f * t_f
+        - 5.481717e-2 * rh * rh
+        + 1.22874e-3 * t_f * t_f * rh
+        + 8.5282e-4 * t_f * rh * rh
+        - 1.99e-6 * t_f * t_f * rh * rh;
+    if rh < 13.0 && t_f > 80.0 && t_f < 112.0 {
+        hi -= ((13.0 - rh) / 4.0) * ((17.0 - (t_f - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..87.0).contains(&t_f) {
+        hi += ((rh - 85.0) / 10.0) * ((87.0 - t_f) / 5.0);
+    }
+    hi
+}
+
+/// Heat index in degC from temperature (degC) and relative humidity (%)
+pub fn heatindex_c(t_c: f64, rh: f64) -> f64 {
+    f_to_c(heatindex_f(c_to_f(t_c), rh))
+}
+
+/// Humidex in degC from temperature (degC) and relative humidity (%).
+/// Never less than the temperature.
+pub fn humidex_c(t_c: f64, rh: f64) -> Option<f64> {
+    let dp = dewpoint_c(t_c, rh)?;
+    let e = 6.11 * (5417.753 * (1.0 / 273.16 - 1.0 / (dp + 273.16))).exp();
+    Some((t_c + 0.5555 * (e - 10.0)).max(t_c))
+}
+
+/// Humidex in degF from temperature (degF) and relative humidity (%)
+pub fn humidex_f(t_f: f64, rh: f64) -> Option<f64> {
+    humidex_c(f_to_c(t_f), rh).map(c_to_f)
+}
+
+/// Apparent temperature (Australian BoM) in degC from temperature (degC),
+/// relative humidity (%) and wind speed (m/s)
+pub fn apptemp_c(t_c: f64, rh: f64, ws_mps: f64) -> f64 {
+    let e = (rh / 100.0) * 6.105 * (17.27 * t_c / (237.7 + t_c)).exp();
+    t_c + 0.33 * e - 0.70 * ws_mps - 4.0
+}
+
+/// Apparent temperature in degF from temperature (degF), relative humidity
+/// (%) and wind speed (mph)
+pub fn apptemp_f(t_f: f64, rh: f64, ws_mph: f64) -> f64 {
+    c_to_f(apptemp_c(f_to_c(t_f), rh, ws_mph * 0.44704))
+}
+
+/// Cloud base above sea level in meters from temperature (degC), relative
+/// humidity (%) and station altitude (meters)
+pub fn cloudbase_m(t_c: f64, rh: f64, altitude_m: f64) -> Option<f64> {
+    let dp = dewpoint_c(t_c, rh)?;
+    // 2.5 degC spread per 1000 ft of lift
+    Some(altitude_m + (t_c - dp) * 1000.0 / 2.5 * 0.3048)
+}
+
+/// Cloud base above sea level in feet from temperature (degF), relative
+/// humidity (%) and station altitude (feet)
+pub fn cloudbase_ft(t_f: f64, rh: f64, altitude_ft: f64) -> Option<f64> {
+    let dp = dewpoint_f(t_f, rh)?;
+    Some(altitude_ft + (t_f - dp) * 1000.0 / 4.4)
+}
+
+/// Altimeter setting in hPa from station pressure (hPa) and altitude (meters)
+pub fn altimeter_hpa(p_hpa: f64, altitude_m: f64, algorithm: AltimeterAlgorithm) -> f64 {
+    match algorithm {
+        AltimeterAlgorithm::Asos => {
+            let k1 = 0.0065 * 287.05 / 9.80665;
+            let k2 = 8.41728638e-5;
+            (p_hpa.powf(k1) + k2 * altitude_m).powf(1.0 / k1)
+        }
+        AltimeterAlgorithm::Noaa => {
+            let n = 0.190284;
+            ((p_hpa - 0.3).powf(n) + 8.4184960528e-5 * altitude_m).powf(1.0 / n)
+        }
+    }
+}
+
+/// Sea-level pressure (barometer) in hPa from station pressure (hPa),
+/// altitude (meters), the current temperature and the temperature 12 hours
+/// ago (degC), using the Manual of Barometry reduction (`paManBar`)
+pub fn sealevel_pressure_hpa(p_hpa: f64, altitude_m: f64, t_c: f64, t12h_c: f64) -> f64 {
+    let geop = EARTH_RADIUS_M * altitude_m / (EARTH_RADIUS_M + altitude_m);
+    let mean_f = (c_to_f(t_c) + c_to_f(t12h_c)) / 2.0;
+    p_hpa * (geop * 6.1454e-2 / (mean_f + 459.7 + geop * 0.0117 / 2.0)).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tol: f64) {
+        assert!(
+            (actual - expected).abs() < tol,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    // Reference values are the doctest outputs in weewx/wxformulas.py
+
+    #[test]
+    fn dewpoint_matches_weewx() {
+        assert_close(dewpoint_f(68.0, 50.0).unwrap(), 48.7, 0.05);
+        assert_close(dewpoint_f(32.0, 50.0).unwrap(), 15.5, 0.05);
+        assert_close(dewpoint_f(-10.0, 50.0).unwrap(), -23.5, 0.05);
+        assert!(dewpoint_c(20.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn windchill_matches_weewx() {
+        assert_close(windchill_f(45.0, 20.0), 37.0, 0.05);
+        assert_close(windchill_f(-5.0, 20.0), -28.6, 0.05);
+        assert_eq!(windchill_f(55.0, 20.0), 55.0);
+        assert_eq!(windchill_f(45.0, 2.0), 45.0);
+        assert_close(windchill_c(0.0, 20.0), -5.2, 0.05);
+    }
+
+    #[test]
+    fn heatindex_matches_weewx() {
+        assert_close(heatindex_f(75.0, 50.0), 74.5, 0.06);
+        assert_close(heatindex_f(80.0, 50.0), 80.8, 0.05);
+        assert_close(heatindex_f(80.0, 95.0), 87.8, 0.05);
+        assert_close(heatindex_f(90.0, 50.0), 94.6, 0.05);
+        assert_close(heatindex_f(90.0, 95.0), 126.6, 0.05);
+        assert_eq!(heatindex_f(30.0, 50.0), 30.0);
+    }
+
+    #[test]
+    fn humidex_matches_weewx() {
+        assert_close(humidex_c(30.0, 80.0).unwrap(), 43.66, 0.005);
+        assert_close(humidex_c(30.0, 20.0).unwrap(), 30.00, 0.005);
+    }
+
+    #[test]
+    fn apptemp_matches_weewx() {
+        assert_close(apptemp_c(30.0, 80.0, 5.0), 33.7, 0.05);
+        assert_close(apptemp_f(80.0, 80.0, 5.0), 86.6, 0.06);
+    }
+
+    #[test]
+    fn cloudbase_matches_weewx() {
+        assert_close(cloudbase_m(20.0, 50.0, 100.0).unwrap(), 1410.1, 0.05);
+    }
+
+    #[test]
+    fn altimeter_matches_weewx() {
+        assert_close(
+            altimeter_hpa(948.08, 1000.0, AltimeterAlgorithm::Asos),
+            1067.6,
+            0.05,
+        );
+        assert_close(
+            altimeter_hpa(948.08, 1000.0, AltimeterAlgorithm::Noaa),
+            1067.2,
+            0.05,
+        );
+        assert_close(
+            altimeter_hpa(948.08, 0.0, AltimeterAlgorithm::Asos),
+            948.08,
+            1e-6,
+        );
+        assert_eq!("aaNOAA".parse(), Ok(AltimeterAlgorithm::Noaa));
+    }
+
+    #[test]
+    fn sealevel_pressure_matches_weewx() {
+        assert_close(
+            sealevel_pressure_hpa(948.08, 1000.0, 20.0, 20.0),
+            1063.8,
+            0.05,
+        );
+        assert_close(
+            sealevel_pressure_hpa(1000.0, 100.0, 15.0, 15.0),
+            1011.9,
+            0.05,
+        );
+    }
+}
@@ -1,22 +1,62 @@
 //! Daemon configuration from environment variables
 
 use anyhow::{Context, Result};
+use chrono_tz::Tz;
 use std::env;
+use weex_archive::FsyncPolicy;
+use weex_db::ConflictPolicy;
 
 #[derive(Debug, Clone)]
 pub struct DaemonConfig {
-    /// MySQL database connection URL
+    /// Database connection URL (`mysql://`, `sqlite://` or `postgres://`)
     pub database_url: String,
 
     /// Archive interval in seconds (default: 300 = 5 minutes)
     pub archive_interval: i32,
 
+    /// Time-weight archive averages by packet timestamps (default: false)
+    pub time_weighted: bool,
+
+    /// Seconds past an interval's end before it is archived (default: 15)
+    pub archive_delay: i64,
+
+    /// Archive empty intervals as gap markers instead of skipping them
+    /// (default: false)
+    pub gap_markers: bool,
+
+    /// Seconds an archived interval still accepts late packets (default: 300)
+    pub late_grace: i64,
+
+    /// Rewrite archived records with late packets instead of dropping them
+    /// (default: false)
+    pub late_upsert: bool,
+
+    /// What to do with an interval the archive already has: skip, replace
+    /// or merge (default: skip)
+    pub conflict_policy: ConflictPolicy,
+
+    /// Write-ahead log of packets not yet archived (default: none)
+    pub wal_path: Option<String>,
+
+    /// WAL fsync policy: always, never or e.g. 5s (default: always)
+    pub wal_fsync: FsyncPolicy,
+
     /// Poll interval for driver in seconds (default: 10)
     pub poll_interval: u64,
 
     /// Unit system (1=US, 16=Metric, 17=MetricWX)
     pub unit_system: i32,
 
+    /// Capacity of each queue between pipeline stages (default: 256)
+    pub pipeline_capacity: usize,
+
+    /// Station altitude in meters (default: 0)
+    pub altitude: f64,
+
+    /// Station timezone, whose local days the daily summaries cover
+    /// (default: UTC)
+    pub timezone: Tz,
+
     /// Station driver type
     #[allow(dead_code)]
     pub driver: String,
@@ -33,6 +73,43 @@ impl DaemonConfig {
             .parse()
             .context("Invalid ARCHIVE_INTERVAL")?;
 
+        let time_weighted = env::var("ARCHIVE_TIME_WEIGHTED")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .context("Invalid ARCHIVE_TIME_WEIGHTED")?;
+
+        let archive_delay = env::var("ARCHIVE_DELAY")
+            .unwrap_or_else(|_| "15".to_string())
+            .parse()
+            .context("Invalid ARCHIVE_DELAY")?;
+
+        let gap_markers = env::var("ARCHIVE_GAP_MARKERS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .context("Invalid ARCHIVE_GAP_MARKERS")?;
+
+        let late_grace = env::var("ARCHIVE_LATE_GRACE")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()
+            .context("Invalid ARCHIVE_LATE_GRACE")?;
+
+        let late_upsert = env::var("ARCHIVE_LATE_UPSERT")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()
+            .context("Invalid ARCHIVE_LATE_UPSERT")?;
+
+        let conflict_policy = env::var("ARCHIVE_CONFLICT_POLICY")
+            .unwrap_or_else(|_| "skip".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid ARCHIVE_CONFLICT_POLICY: {}", e))?;
+
+        let wal_path = env::var("ARCHIVE_WAL_PATH").ok();
+
+        let wal_fsync = env::var("ARCHIVE_WAL_FSYNC")
+            .unwrap_or_else(|_| "always".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid ARCHIVE_WAL_FSYNC: {}", e))?;
+
         let poll_interval = env::var("POLL_INTERVAL")
             .unwrap_or_else(|_| "10".to_string())
             .parse()
@@ -43,13 +120,39 @@ impl DaemonConfig {
             .parse()
             .context("Invalid UNIT_SYSTEM")?;
 
+        let pipeline_capacity = env::var("PIPELINE_CAPACITY")
+            .unwrap_or_else(|_| "256".to_string())
+            .parse()
+            .context("Invalid PIPELINE_CAPACITY")?;
+
+        let altitude = env::var("STATION_ALTITUDE")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()
+            .context("Invalid STATION_ALTITUDE")?;
+
+        let timezone = env::var("STATION_TIMEZONE")
+            .unwrap_or_else(|_| "UTC".to_string())
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid STATION_TIMEZONE: {}", e))?;
+
         let driver = env::var("STATION_DRIVER").unwrap_or_else(|_| "simulator".to_string());
 
         Ok(Self {
             database_url,
             archive_interval,
+            time_weighted,
+            archive_delay,
+            gap_markers,
+            late_grace,
+            late_upsert,
+            conflict_policy,
+            wal_path,
+            wal_fsync,
             poll_interval,
             unit_system,
+            pipeline_capacity,
+            altitude,
+            timezone,
             driver,
         })
     }
@@ -67,8 +170,19 @@ mod tests {
         let config = DaemonConfig::from_env().unwrap();
 
         assert_eq!(config.archive_interval, 300);
+        assert!(!config.time_weighted);
+        assert_eq!(config.archive_delay, 15);
+        assert!(!config.gap_markers);
+        assert_eq!(config.late_grace, 300);
+        assert!(!config.late_upsert);
+        assert_eq!(config.conflict_policy, ConflictPolicy::Skip);
+        assert_eq!(config.wal_path, None);
+        assert_eq!(config.wal_fsync, FsyncPolicy::Always);
         assert_eq!(config.poll_interval, 10);
         assert_eq!(config.unit_system, 16);
+        assert_eq!(config.altitude, 0.0);
+        assert_eq!(config.timezone, Tz::UTC);
+        assert_eq!(config.pipeline_capacity, 256);
         assert_eq!(config.driver, "simulator");
 
         env::remove_var("DATABASE_URL");
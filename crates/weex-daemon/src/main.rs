@@ -1,24 +1,25 @@
-//! WeeWX Daemon - Main scheduler and archive writer
+//! WeeWX Daemon - Pipeline runner and archive writer
 //!
-//! This binary coordinates:
-//! - Weather station data collection (via drivers)
-//! - Interval aggregation
-//! - Archive record writing to MySQL
+//! This binary runs a pipeline of:
+//! - Weather station data collection (via drivers) as the source
+//! - Quality control (range, spike, rate and flatline checks) as a processor
+//! - Derived observations (dewpoint, wind chill, barometer, ...) as a processor
+//! - Interval aggregation, archive record and daily summary writing to the WeeWX database
+//!   as the sink
 
 mod config;
-mod scheduler;
 
 use anyhow::{Context, Result};
-use tracing::{error, info};
+use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-use weex_archive::IntervalAggregator;
+use weex_archive::{ArchiveSink, IntervalAggregator, Wal};
+use weex_core::{Pipeline, QcOptions, QualityControl, StdWxCalculate, WxCalculateOptions};
 use weex_db::DbClient;
 use weex_ingest::simulator::SimulatorDriver;
-use weex_ingest::StationDriver;
+use weex_ingest::{DriverSource, StationDriver};
 
 use crate::config::DaemonConfig;
-use crate::scheduler::Scheduler;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -34,10 +35,17 @@ async fn main() -> Result<()> {
     let config = DaemonConfig::from_env()?;
     info!("Loaded configuration: {:?}", config);
 
-    // Initialize database connection
-    let db_client = DbClient::new(&config.database_url)
-        .await
-        .context("Failed to connect to database")?;
+    // `weexd init` creates the WeeWX schema in an empty database and exits
+    let init = std::env::args().nth(1).as_deref() == Some("init");
+
+    // Initialize database connection; `init` may create a SQLite file
+    let db_client = if init {
+        DbClient::create(&config.database_url).await
+    } else {
+        DbClient::new(&config.database_url).await
+    }
+    .context("Failed to connect to database")?
+    .with_timezone(config.timezone);
 
     info!("Connected to database");
 
@@ -45,47 +53,140 @@ async fn main() -> Result<()> {
     db_client.ping().await.context("Database ping failed")?;
     info!("Database connection verified");
 
+    if init {
+        let created = db_client
+            .init_schema(config.unit_system)
+            .await
+            .context("Failed to create database schema")?;
+        if !created {
+            db_client.check_schema(config.unit_system).await?;
+        }
+        info!(
+            "Database schema {}",
+            if created {
+                "created"
+            } else {
+                "already present"
+            }
+        );
+        return Ok(());
+    }
+
+    // Refuse to write to a missing schema or one in another unit system
+    db_client
+        .check_schema(config.unit_system)
+        .await
+        .context("Database schema check failed")?;
+
     // Initialize station driver (simulator for now)
     let mut driver = Box::new(SimulatorDriver::new(config.poll_interval)) as Box<dyn StationDriver>;
     driver.start().await.context("Failed to start driver")?;
     info!("Station driver started: {}", driver.name());
 
     // Create aggregator
-    let aggregator = IntervalAggregator::new(
+    let mut aggregator = IntervalAggregator::new(
         config.archive_interval,
         config.unit_system,
         db_client.clone(),
-    );
+    )
+    .time_weighted(config.time_weighted)
+    .archive_delay(config.archive_delay)
+    .gap_markers(config.gap_markers)
+    .late_grace(config.late_grace)
+    .late_upsert(config.late_upsert)
+    .conflict_policy(config.conflict_policy)
+    .timezone(config.timezone);
+
+    // Intervals the archive already has are not written again
+    if let Some(latest) = aggregator
+        .resume()
+        .await
+        .context("Failed to read the latest archive record")?
+    {
+        info!("Latest archive record: {}", latest);
+    }
 
-    // Create and run scheduler
-    let mut scheduler = Scheduler::new(driver, aggregator);
+    // Restore the open intervals a crashed run left in the WAL
+    if let Some(path) = config.wal_path.as_ref() {
+        let wal = Wal::open(path, config.wal_fsync).context("Failed to open WAL")?;
+        aggregator = aggregator.wal(wal);
+        let restored = aggregator
+            .replay_wal()
+            .await
+            .context("Failed to replay WAL")?;
+        info!("WAL {}: {} packets restored", path, restored);
+    }
 
-    // Setup signal handler for graceful shutdown
-    let shutdown = setup_shutdown_handler();
+    info!("Archive interval: {}s", aggregator.interval());
+    info!("Unit system: {}", aggregator.unit_system());
+
+    let qc = QcOptions {
+        unit_system: config.unit_system,
+        ..Default::default()
+    };
+    let calculate = WxCalculateOptions {
+        altitude_m: config.altitude,
+        unit_system: config.unit_system,
+        ..Default::default()
+    };
+
+    // Driver -> QC -> derived observations -> archive, connected by bounded queues
+    let pipeline = Pipeline::builder()
+        .capacity(config.pipeline_capacity)
+        .source(driver.name().to_string(), DriverSource::new(driver))
+        .processor("qc", QualityControl::new(qc))
+        .processor("calculate", StdWxCalculate::new(calculate))
+        .sink("archive", ArchiveSink::new(aggregator))
+        .spawn();
 
     info!("Daemon running - press Ctrl+C to stop");
 
     // Run until shutdown signal
-    tokio::select! {
-        result = scheduler.run() => {
-            if let Err(e) = result {
-                error!("Scheduler error: {}", e);
-                return Err(e);
-            }
-        }
-        _ = shutdown => {
-            info!("Shutdown signal received");
-            scheduler.stop().await?;
-        }
+    setup_shutdown_handler().await;
+    info!("Shutdown signal received");
+
+    // Stops the driver, then archives the buffered interval
+    let stats = pipeline.stats();
+    pipeline.shutdown().await;
+    for stage in stats {
+        info!(
+            "Stage {} ({}): received={} emitted={} errors={}",
+            stage.name(),
+            stage.kind().as_str(),
+            stage.received(),
+            stage.emitted(),
+            stage.errors()
+        );
     }
 
+    // Flushing happened in shutdown(); release the connection pool cleanly
+    db_client.close().await;
+
     info!("WeeWX Daemon stopped");
     Ok(())
 }
 
-/// Setup graceful shutdown handler
+/// Setup graceful shutdown handler: Ctrl-C, or SIGTERM from a service
+/// manager or container runtime
 async fn setup_shutdown_handler() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to setup signal handler");
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to setup signal handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to setup signal handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
@@ -0,0 +1,432 @@
+//! Aggregates over time spans, like WeeWX's xtypes
+//!
+//! "Max outTemp this month" is answered from the daily summaries for the
+//! whole local days in the span and from the archive for the partial days at
+//! either end, the way WeeWX combines its `DailySummaries` and
+//! `ArchiveTable` types. Aggregates the summaries cannot give (first, last,
+//! standard deviation, percentiles) read the archive for the whole span.
+
+use crate::schema::{tables, DailySummaryRow};
+use crate::{ArchiveQuery, DbClient, DbError, DbResult};
+use chrono::{NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use futures_util::TryStreamExt;
+use tracing::instrument;
+use weex_core::{Accumulator, AggregateType, ArchiveRecord, TimeSpan, WIND_VECTORS};
+
+/// Archive columns folded into the `wind` summary
+const WIND_COLUMNS: [&str; 4] = ["windSpeed", "windDir", "windGust", "windGustDir"];
+
+/// Start (epoch seconds) of the local day an archive record stamped
+/// `date_time` belongs to
+pub fn day_start(date_time: i64, tz: Tz) -> i64 {
+    let ts = date_time - 1;
+    let Some(local) = tz.timestamp_opt(ts, 0).single() else {
+        return ts - ts.rem_euclid(86_400);
+    };
+    let midnight = local.date_naive().and_time(NaiveTime::MIN);
+    match tz.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.timestamp(),
+        // Midnight skipped by a DST change; the day starts at the first
+        // valid local time
+        None => {
+            ts - local
+                .time()
+                .signed_duration_since(NaiveTime::MIN)
+                .num_seconds()
+        }
+    }
+}
+
+/// Start of the local day after the one starting at `day`
+fn next_day(day: i64, tz: Tz) -> i64 {
+    // Some time into the next day, whatever DST did to this one
+    day_start(day + 26 * 3600, tz)
+}
+
+/// The first local midnight at or after `date_time`
+fn midnight_from(date_time: i64, tz: Tz) -> i64 {
+    let day = day_start(date_time + 1, tz);
+    if day == date_time {
+        day
+    } else {
+        next_day(day, tz)
+    }
+}
+
+/// Whether the daily summaries (with the archive for partial days) can give
+/// `aggregate`. Only `wind` keeps the vector sums and squares.
+fn from_summaries(obs: &str, aggregate: AggregateType) -> bool {
+    use AggregateType::*;
+    match aggregate {
+        Min | Max | MinTime | MaxTime | Sum | Count | Avg => true,
+        Rms | VecAvg | VecDir | GustDir => obs == tables::WIND_SUMMARY,
+        _ => false,
+    }
+}
+
+/// `aggregate` of a (possibly merged) summary row
+fn summary_value(row: &DailySummaryRow, aggregate: AggregateType) -> Option<f64> {
+    let per_second = |sum: Option<f64>, time: Option<i64>| match (sum, time) {
+        (Some(sum), Some(time)) if time > 0 => Some(sum / time as f64),
+        _ => None,
+    };
+    match aggregate {
+        AggregateType::Min => row.min,
+        AggregateType::Max => row.max,
+        AggregateType::MinTime => row.mintime.map(|t| t as f64),
+        AggregateType::MaxTime => row.maxtime.map(|t| t as f64),
+        AggregateType::Sum => row.sum,
+        AggregateType::Count => Some(row.count.unwrap_or(0) as f64),
+        AggregateType::Avg => per_second(row.wsum, row.sumtime),
+        AggregateType::Rms => per_second(row.wsquaresum, row.sumtime).map(f64::sqrt),
+        AggregateType::VecAvg => {
+            let (x, y) = (row.xsum?, row.ysum?);
+            per_second(Some(x.hypot(y)), row.dirsumtime)
+        }
+        AggregateType::VecDir => {
+            // All-calm spans have no direction
+            let (x, y) = (row.xsum?, row.ysum?);
+            if x == 0.0 && y == 0.0 {
+                return None;
+            }
+            Some(x.atan2(y).to_degrees().rem_euclid(360.0))
+        }
+        AggregateType::GustDir => row.max_dir,
+        _ => None,
+    }
+}
+
+impl DbClient {
+    /// `aggregate` of `obs` over `span`, e.g. the max `outTemp` this month or
+    /// the sum of `rain` this year. `wind` aggregates the combined wind
+    /// summary (vector average and direction, gust direction). Whole local
+    /// days (of [`timezone`](Self::timezone)) come from the daily summaries,
+    /// so their tables must be up to date.
+    #[instrument(skip(self))]
+    pub async fn get_aggregate(
+        &self,
+        obs: &str,
+        aggregate: AggregateType,
+        span: TimeSpan,
+    ) -> DbResult<Option<f64>> {
+        if span.stop <= span.start {
+            return Ok(if aggregate == AggregateType::Count {
+                Some(0.0)
+            } else {
+                None
+            });
+        }
+        if !from_summaries(obs, aggregate) {
+            return self.accumulate_archive(obs, aggregate, span).await;
+        }
+
+        let tz = self.timezone();
+        let days = (midnight_from(span.start, tz), day_start(span.stop + 1, tz));
+        let mut row = DailySummaryRow::default();
+        if days.0 >= days.1 {
+            // Not one whole day
+            row = self.summarize_archive(obs, span).await?;
+        } else {
+            if span.start < days.0 {
+                row.merge(
+                    &self
+                        .summarize_archive(obs, TimeSpan::new(span.start, days.0))
+                        .await?,
+                );
+            }
+            let table = self.summary_table(obs).await?;
+            for day in self.get_daily_summary_range(&table, days.0, days.1).await? {
+                row.merge(&day);
+            }
+            if days.1 < span.stop {
+                row.merge(
+                    &self
+                        .summarize_archive(obs, TimeSpan::new(days.1, span.stop))
+                        .await?,
+                );
+            }
+        }
+        Ok(summary_value(&row, aggregate))
+    }
+
+    /// [`get_aggregate`](Self::get_aggregate) over each `step` seconds of
+    /// `span` (the last step may be shorter). Steps of whole days from a
+    /// local midnight follow local days across DST changes.
+    #[instrument(skip(self))]
+    pub async fn get_series(
+        &self,
+        obs: &str,
+        aggregate: AggregateType,
+        span: TimeSpan,
+        step: i64,
+    ) -> DbResult<Vec<(TimeSpan, Option<f64>)>> {
+        if step <= 0 {
+            return Err(DbError::ConfigError(format!(
+                "series step must be positive, not {}",
+                step
+            )));
+        }
+        let tz = self.timezone();
+        let mut series = Vec::new();
+        let mut start = span.start;
+        while start < span.stop {
+            let stop = if step % 86_400 == 0 && midnight_from(start, tz) == start {
+                (0..step / 86_400).fold(start, |day, _| next_day(day, tz))
+            } else {
+                start.saturating_add(step)
+            };
+            let step_span = TimeSpan::new(start, stop.min(span.stop));
+            let value = self.get_aggregate(obs, aggregate, step_span).await?;
+            series.push((step_span, value));
+            start = step_span.stop;
+        }
+        Ok(series)
+    }
+
+    /// Name of the daily summary of `obs`: its archive column, or `wind`
+    async fn summary_table(&self, obs: &str) -> DbResult<String> {
+        if obs == tables::WIND_SUMMARY {
+            return Ok(obs.to_string());
+        }
+        let columns = self.archive_columns().await?;
+        match columns.get(obs) {
+            Some(column) => Ok(column.to_string()),
+            None => Err(DbError::SchemaError(format!(
+                "`{}` table has no `{}` column",
+                tables::ARCHIVE,
+                obs
+            ))),
+        }
+    }
+
+    /// The archive records of `span`, with just the columns `obs` needs
+    async fn archive_span(&self, obs: &str, span: TimeSpan) -> DbResult<ArchiveQuery> {
+        let query = ArchiveQuery::new(span.start.saturating_add(1), span.stop);
+        if obs == tables::WIND_SUMMARY {
+            let columns = self.archive_columns().await?;
+            let wind = WIND_COLUMNS.iter().filter(|c| columns.get(c).is_some());
+            return Ok(query.columns(wind.copied()));
+        }
+        let speed = WIND_VECTORS.iter().find(|(dir, _)| *dir == obs);
+        Ok(query.columns(
+            [Some(obs), speed.map(|(_, speed)| *speed)]
+                .into_iter()
+                .flatten(),
+        ))
+    }
+
+    /// Fold the archive records of `span` into a summary row, as the daily
+    /// summaries are built
+    async fn summarize_archive(&self, obs: &str, span: TimeSpan) -> DbResult<DailySummaryRow> {
+        let query = self.archive_span(obs, span).await?;
+        let column = self.summary_table(obs).await?;
+        let mut row = DailySummaryRow::default();
+        let mut records = std::pin::pin!(self.stream_archive(query));
+        while let Some(record) = records.try_next().await? {
+            let weight = i64::from(record.interval);
+            if obs == tables::WIND_SUMMARY {
+                row.add_wind(&record, weight);
+            } else if let Some(value) = field(&record, &column) {
+                row.add_value(value, record.date_time, weight);
+            }
+        }
+        Ok(row)
+    }
+
+    /// Aggregate the archive records of `span` one by one
+    async fn accumulate_archive(
+        &self,
+        obs: &str,
+        aggregate: AggregateType,
+        span: TimeSpan,
+    ) -> DbResult<Option<f64>> {
+        // Directions are weighted by their speed in vector aggregates (the
+        // summaries give those of `wind`, which otherwise stands for its speed)
+        let vector = matches!(
+            aggregate,
+            AggregateType::VecAvg | AggregateType::VecDir | AggregateType::GustDir
+        );
+        let (column, speed) = if obs == tables::WIND_SUMMARY {
+            ("windSpeed", None)
+        } else if vector {
+            let speed = WIND_VECTORS.iter().find(|(dir, _)| *dir == obs);
+            let Some((_, speed)) = speed else {
+                return Ok(None);
+            };
+            (obs, Some(*speed))
+        } else {
+            (obs, None)
+        };
+
+        let query = self.archive_span(obs, span).await?;
+        let column = self.summary_table(column).await?;
+        let mut acc = Accumulator::new(aggregate);
+        let mut records = std::pin::pin!(self.stream_archive(query));
+        while let Some(record) = records.try_next().await? {
+            let Some(value) = field(&record, &column) else {
+                continue;
+            };
+            match speed {
+                Some(speed) => {
+                    if let Some(speed) = field(&record, speed) {
+                        acc.add_vector(speed, value);
+                    }
+                }
+                None => acc.add_at(record.date_time, value),
+            }
+        }
+        Ok(acc.result())
+    }
+}
+
+/// A numeric field of an archive record
+fn field(record: &ArchiveRecord, name: &str) -> Option<f64> {
+    record.aggregates.get(name).and_then(|v| v.as_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weex_core::ObservationValue::Float;
+
+    /// Three UTC days of hourly records, with their daily summaries
+    async fn sqlite() -> DbClient {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL, \
+             windSpeed REAL, windDir REAL)",
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+        db.create_daily_summary("outTemp").await.unwrap();
+        db.create_daily_summary("wind").await.unwrap();
+
+        let records: Vec<_> = (1..=72)
+            .map(|h| ArchiveRecord {
+                date_time: h * 3600,
+                interval: 3600,
+                us_units: 16,
+                aggregates: [
+                    ("outTemp".to_string(), Float((h % 24) as f64)),
+                    ("windSpeed".to_string(), Float(2.0)),
+                    (
+                        "windDir".to_string(),
+                        Float(if h <= 24 { 90.0 } else { 0.0 }),
+                    ),
+                ]
+                .into(),
+            })
+            .collect();
+        db.insert_archive_batch(&records, Default::default())
+            .await
+            .unwrap();
+        for day in [0, 86_400, 172_800] {
+            let mut temp = DailySummaryRow {
+                date_time: day,
+                ..Default::default()
+            };
+            let mut wind = temp.clone();
+            for record in records
+                .iter()
+                .filter(|r| day_start(r.date_time, Tz::UTC) == day)
+            {
+                temp.add_value(field(record, "outTemp").unwrap(), record.date_time, 3600);
+                wind.add_wind(record, 3600);
+            }
+            db.put_daily_summary("outTemp", &temp).await.unwrap();
+            db.put_daily_summary("wind", &wind).await.unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_day_boundaries() {
+        let tz: Tz = "America/Chicago".parse().unwrap();
+        // 2024-03-10 (23 hours) begins at 06:00 UTC
+        let day = 1_710_050_400;
+        assert_eq!(next_day(day, tz), day + 23 * 3600);
+        assert_eq!(midnight_from(day, tz), day);
+        assert_eq!(midnight_from(day + 1, tz), day + 23 * 3600);
+        assert_eq!(midnight_from(0, Tz::UTC), 0);
+    }
+
+    #[tokio::test]
+    async fn test_aggregates_match_the_archive() {
+        let db = sqlite().await;
+        // Noon of day one to 6am of day three: partial days around one whole
+        let span = TimeSpan::new(12 * 3600, 54 * 3600);
+        for aggregate in [
+            AggregateType::Min,
+            AggregateType::Max,
+            AggregateType::MaxTime,
+            AggregateType::Sum,
+            AggregateType::Count,
+            AggregateType::Avg,
+        ] {
+            let expected = db.accumulate_archive("outTemp", aggregate, span).await;
+            assert_eq!(
+                db.get_aggregate("outTemp", aggregate, span).await.unwrap(),
+                expected.unwrap(),
+                "{:?}",
+                aggregate
+            );
+        }
+        // Of the maxima on both days, the first is kept
+        assert_eq!(
+            db.get_aggregate("outTemp", AggregateType::MaxTime, span)
+                .await
+                .unwrap(),
+            Some(23.0 * 3600.0)
+        );
+        assert_eq!(
+            db.get_aggregate("outTemp", AggregateType::Median, span)
+                .await
+                .unwrap(),
+            Some(13.0)
+        );
+
+        // Half east, half north, weighted equally
+        let all = TimeSpan::new(0, 48 * 3600);
+        let dir = db
+            .get_aggregate("wind", AggregateType::VecDir, all)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((dir - 45.0).abs() < 1e-9);
+        let speed = db
+            .get_aggregate("wind", AggregateType::VecAvg, all)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!((speed - 2.0_f64.sqrt()).abs() < 1e-9);
+        assert!(db
+            .get_aggregate("soilMoist1", AggregateType::Max, all)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_series() {
+        let db = sqlite().await;
+        let series = db
+            .get_series(
+                "outTemp",
+                AggregateType::Max,
+                TimeSpan::new(0, 60 * 3600),
+                86_400,
+            )
+            .await
+            .unwrap();
+        let spans: Vec<_> = series.iter().map(|(span, _)| span.stop).collect();
+        assert_eq!(spans, vec![86_400, 172_800, 216_000]);
+        assert_eq!(series[0].1, Some(23.0));
+        assert_eq!(series[2].1, Some(12.0));
+        assert!(db
+            .get_series("outTemp", AggregateType::Max, TimeSpan::new(0, 1), 0)
+            .await
+            .is_err());
+    }
+}
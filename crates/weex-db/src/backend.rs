@@ -0,0 +1,325 @@
+//! Database backends and their SQL dialects
+//!
+//! Queries are written once, with `?` placeholders and identifiers passed
+//! through [`Backend::quote`], and adapted to the backend here. The backend
+//! is chosen by the URL scheme.
+
+use crate::{DbError, DbResult};
+use std::borrow::Cow;
+use std::str::FromStr;
+
+/// What an archive write does when a record with the same timestamp exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing record
+    #[default]
+    Skip,
+    /// Replace the existing record entirely
+    Replace,
+    /// Overwrite the existing record's columns with the new non-null values
+    Merge,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "skip" => Ok(Self::Skip),
+            "replace" => Ok(Self::Replace),
+            "merge" => Ok(Self::Merge),
+            _ => Err(format!(
+                "invalid conflict policy '{}': expected skip, replace or merge",
+                s
+            )),
+        }
+    }
+}
+
+/// Database engine behind a [`DbClient`](crate::DbClient)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// MySQL or MariaDB (`mysql://`, `mariadb://`)
+    MySql,
+    /// SQLite, as Python WeeWX's default `weewx.sdb` (`sqlite://`)
+    Sqlite,
+    /// PostgreSQL (`postgres://`, `postgresql://`)
+    Postgres,
+}
+
+impl Backend {
+    /// Backend for a database URL, by its scheme
+    pub fn from_url(url: &str) -> DbResult<Self> {
+        let scheme = url.split(':').next().unwrap_or_default();
+        match scheme.to_ascii_lowercase().as_str() {
+            "mysql" | "mariadb" => Ok(Self::MySql),
+            "sqlite" => Ok(Self::Sqlite),
+            "postgres" | "postgresql" => Ok(Self::Postgres),
+            _ => Err(DbError::ConfigError(format!(
+                "unsupported database URL scheme '{}': expected mysql, sqlite or postgres",
+                scheme
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MySql => "mysql",
+            Self::Sqlite => "sqlite",
+            Self::Postgres => "postgres",
+        }
+    }
+
+    /// Quote an identifier. PostgreSQL folds unquoted names to lower case,
+    /// so WeeWX's mixed-case columns (`dateTime`) are always quoted.
+    pub fn quote(&self, name: &str) -> String {
+        match self {
+            Self::MySql => format!("`{}`", name.replace('`', "``")),
+            Self::Sqlite | Self::Postgres => format!("\"{}\"", name.replace('"', "\"\"")),
+        }
+    }
+
+    /// Column type of WeeWX's `REAL` columns: a double everywhere, which
+    /// PostgreSQL spells differently
+    pub fn real_type(&self) -> &'static str {
+        match self {
+            Self::MySql | Self::Sqlite => "REAL",
+            Self::Postgres => "DOUBLE PRECISION",
+        }
+    }
+
+    /// Query for whether a table exists, binding its name; returns a row if
+    /// it does
+    pub fn table_exists_sql(&self) -> Cow<'static, str> {
+        self.sql(match self {
+            Self::MySql => {
+                "SELECT table_name FROM information_schema.tables \
+                 WHERE table_schema = DATABASE() AND table_name = ?"
+            }
+            Self::Sqlite => "SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?",
+            Self::Postgres => {
+                "SELECT table_name FROM information_schema.tables \
+                 WHERE table_schema = current_schema() AND table_name = ?"
+            }
+        })
+    }
+
+    /// Adapt a query written with `?` placeholders; PostgreSQL numbers them
+    /// (`$1`, `$2`, ...). Quoted strings and identifiers are left alone.
+    pub fn sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        if *self != Self::Postgres || !sql.contains('?') {
+            return Cow::Borrowed(sql);
+        }
+        let mut out = String::with_capacity(sql.len() + 8);
+        let mut quote = None;
+        let mut n = 0;
+        for c in sql.chars() {
+            match (quote, c) {
+                (None, '\'' | '"' | '`') => quote = Some(c),
+                (Some(q), _) if c == q => quote = None,
+                (None, '?') => {
+                    n += 1;
+                    out.push('$');
+                    out.push_str(&n.to_string());
+                    continue;
+                }
+                _ => {}
+            }
+            out.push(c);
+        }
+        Cow::Owned(out)
+    }
+
+    /// `INSERT` of `columns` into `table` that replaces any row with the same
+    /// `key`. Columns of an existing row that are not in `columns` are reset
+    /// to their defaults, as MySQL's and SQLite's `REPLACE` do; `others`
+    /// lists them for PostgreSQL, which can only update named columns.
+    pub fn replace_sql(&self, table: &str, key: &str, columns: &[&str], others: &[&str]) -> String {
+        self.insert_sql(
+            table,
+            columns,
+            1,
+            Some((key, ConflictPolicy::Replace)),
+            others,
+        )
+    }
+
+    /// Multi-row `INSERT` of `rows` rows of `columns` into `table`. With a
+    /// conflict policy, rows whose `key` exists are skipped, replaced or
+    /// merged (non-null values overwrite) rather than failing; `others` are
+    /// the table's remaining columns, which a replace resets.
+    pub fn insert_sql(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: usize,
+        conflict: Option<(&str, ConflictPolicy)>,
+        others: &[&str],
+    ) -> String {
+        let names: Vec<_> = columns.iter().map(|c| self.quote(c)).collect();
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+        let values = vec![row.as_str(); rows].join(", ");
+        let Some((key, policy)) = conflict else {
+            return self
+                .sql(&format!(
+                    "INSERT INTO {} ({}) VALUES {}",
+                    table,
+                    names.join(", "),
+                    values
+                ))
+                .into_owned();
+        };
+
+        let updated: Vec<_> = match policy {
+            ConflictPolicy::Skip => Vec::new(),
+            ConflictPolicy::Replace => columns.iter().chain(others).collect(),
+            ConflictPolicy::Merge => columns.iter().collect(),
+        };
+        let updated: Vec<_> = updated
+            .into_iter()
+            .filter(|c| **c != key)
+            .map(|c| self.quote(c))
+            .collect();
+        let sql = match (self, policy) {
+            (Self::MySql, ConflictPolicy::Skip) => {
+                format!(
+                    "INSERT IGNORE INTO {} ({}) VALUES {}",
+                    table,
+                    names.join(", "),
+                    values
+                )
+            }
+            (Self::MySql | Self::Sqlite, ConflictPolicy::Replace) => {
+                format!(
+                    "REPLACE INTO {} ({}) VALUES {}",
+                    table,
+                    names.join(", "),
+                    values
+                )
+            }
+            (Self::MySql, ConflictPolicy::Merge) => {
+                let set: Vec<_> = updated
+                    .iter()
+                    .map(|c| format!("{0} = COALESCE(VALUES({0}), {0})", c))
+                    .collect();
+                let set = if set.is_empty() {
+                    // Nothing to merge; a no-op update keeps the row
+                    format!("{0} = {0}", self.quote(key))
+                } else {
+                    set.join(", ")
+                };
+                format!(
+                    "INSERT INTO {} ({}) VALUES {} ON DUPLICATE KEY UPDATE {}",
+                    table,
+                    names.join(", "),
+                    values,
+                    set
+                )
+            }
+            (Self::Sqlite | Self::Postgres, _) => {
+                let set: Vec<_> = updated
+                    .iter()
+                    .map(|c| match policy {
+                        ConflictPolicy::Merge => {
+                            format!("{0} = COALESCE(EXCLUDED.{0}, {1}.{0})", c, table)
+                        }
+                        _ => format!("{0} = EXCLUDED.{0}", c),
+                    })
+                    .collect();
+                let action = if set.is_empty() {
+                    "NOTHING".to_string()
+                } else {
+                    format!("UPDATE SET {}", set.join(", "))
+                };
+                format!(
+                    "INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) DO {}",
+                    table,
+                    names.join(", "),
+                    values,
+                    self.quote(key),
+                    action
+                )
+            }
+        };
+        self.sql(&sql).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_from_url() {
+        assert_eq!(
+            Backend::from_url("mysql://weewx@localhost/weewx").unwrap(),
+            Backend::MySql
+        );
+        assert_eq!(
+            Backend::from_url("sqlite:///var/lib/weewx/weewx.sdb").unwrap(),
+            Backend::Sqlite
+        );
+        assert_eq!(
+            Backend::from_url("postgresql://localhost/weewx").unwrap(),
+            Backend::Postgres
+        );
+        assert!(Backend::from_url("oracle://localhost").is_err());
+    }
+
+    #[test]
+    fn test_dialects() {
+        assert_eq!(Backend::MySql.quote("interval"), "`interval`");
+        assert_eq!(Backend::MySql.quote("a`b"), "`a``b`");
+        assert_eq!(Backend::Postgres.quote("dateTime"), "\"dateTime\"");
+
+        let sql = "SELECT * FROM t WHERE a = ? AND b = '?' AND c = ?";
+        assert_eq!(Backend::Sqlite.sql(sql), sql);
+        assert_eq!(
+            Backend::Postgres.sql(sql),
+            "SELECT * FROM t WHERE a = $1 AND b = '?' AND c = $2"
+        );
+
+        assert_eq!(
+            Backend::Sqlite.replace_sql("m", "name", &["name", "value"], &[]),
+            "REPLACE INTO m (\"name\", \"value\") VALUES (?, ?)"
+        );
+        assert_eq!(
+            Backend::Postgres.replace_sql("m", "name", &["name", "value"], &["extra"]),
+            "INSERT INTO m (\"name\", \"value\") VALUES ($1, $2) ON CONFLICT (\"name\") \
+             DO UPDATE SET \"value\" = EXCLUDED.\"value\", \"extra\" = EXCLUDED.\"extra\""
+        );
+
+        assert_eq!(
+            Backend::MySql.insert_sql("t", &["k", "v"], 2, Some(("k", ConflictPolicy::Skip)), &[]),
+            "INSERT IGNORE INTO t (`k`, `v`) VALUES (?, ?), (?, ?)"
+        );
+        assert_eq!(
+            Backend::MySql.insert_sql("t", &["k", "v"], 1, Some(("k", ConflictPolicy::Merge)), &[]),
+            "INSERT INTO t (`k`, `v`) VALUES (?, ?) ON DUPLICATE KEY UPDATE \
+             `v` = COALESCE(VALUES(`v`), `v`)"
+        );
+        assert_eq!(
+            Backend::Sqlite.insert_sql("t", &["k", "v"], 1, Some(("k", ConflictPolicy::Skip)), &[]),
+            "INSERT INTO t (\"k\", \"v\") VALUES (?, ?) ON CONFLICT (\"k\") DO NOTHING"
+        );
+        assert_eq!(
+            Backend::Postgres.insert_sql(
+                "t",
+                &["k", "v"],
+                2,
+                Some(("k", ConflictPolicy::Merge)),
+                &[]
+            ),
+            "INSERT INTO t (\"k\", \"v\") VALUES ($1, $2), ($3, $4) ON CONFLICT (\"k\") \
+             DO UPDATE SET \"v\" = COALESCE(EXCLUDED.\"v\", t.\"v\")"
+        );
+    }
+
+    #[test]
+    fn test_conflict_policy_from_str() {
+        assert_eq!("skip".parse(), Ok(ConflictPolicy::Skip));
+        assert_eq!(" Merge".parse(), Ok(ConflictPolicy::Merge));
+        assert_eq!("replace".parse(), Ok(ConflictPolicy::Replace));
+        assert!("upsert".parse::<ConflictPolicy>().is_err());
+    }
+}
@@ -1,43 +1,107 @@
 //! Database client and connection management
 
-use crate::DbResult;
-use sqlx::mysql::{MySqlConnectOptions, MySqlPool, MySqlPoolOptions};
+use crate::{ArchiveColumns, Backend, DbResult};
+use chrono_tz::Tz;
+use sqlx::any::{AnyConnectOptions, AnyPoolOptions};
+use sqlx::mysql::MySqlConnectOptions;
+use sqlx::{AnyPool, ConnectOptions};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
 /// Database client wrapping sqlx connection pool
 #[derive(Clone)]
 pub struct DbClient {
-    pool: MySqlPool,
+    pool: AnyPool,
+    backend: Backend,
+    /// Station timezone, whose local days the daily summaries cover
+    timezone: Tz,
+    /// `archive` table columns, read on first use
+    archive_columns: Arc<RwLock<Option<Arc<ArchiveColumns>>>>,
 }
 
 impl DbClient {
-    /// Create a new database client from connection string
+    /// Create a new database client from connection string. The backend
+    /// follows the URL scheme: `mysql://`, `sqlite://` or `postgres://`.
     pub async fn new(database_url: &str) -> DbResult<Self> {
-        let pool = MySqlPoolOptions::new()
-            .max_connections(10)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect(database_url)
-            .await?;
+        let backend = Backend::from_url(database_url)?;
+        let opts: AnyConnectOptions = database_url.parse()?;
+        Self::connect(backend, opts, database_url.contains(":memory:")).await
+    }
 
-        Ok(Self { pool })
+    /// Like [`new`](Self::new), but a SQLite database file that does not
+    /// exist yet is created (`mode=rwc`), for setting up a fresh station
+    pub async fn create(database_url: &str) -> DbResult<Self> {
+        let backend = Backend::from_url(database_url)?;
+        let in_memory = database_url.contains(":memory:");
+        if backend != Backend::Sqlite || in_memory || database_url.contains("mode=") {
+            return Self::new(database_url).await;
+        }
+        let separator = if database_url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}mode=rwc", database_url, separator);
+        Self::connect(backend, url.parse()?, false).await
     }
 
-    /// Create a new database client with custom options
+    /// Create a new MySQL database client with custom options
     pub async fn with_options(opts: MySqlConnectOptions) -> DbResult<Self> {
-        let pool = MySqlPoolOptions::new()
+        let opts = AnyConnectOptions::from_url(&opts.to_url_lossy())?;
+        Self::connect(Backend::MySql, opts, false).await
+    }
+
+    async fn connect(backend: Backend, opts: AnyConnectOptions, in_memory: bool) -> DbResult<Self> {
+        sqlx::any::install_default_drivers();
+        let mut pool = AnyPoolOptions::new()
             .max_connections(10)
-            .acquire_timeout(Duration::from_secs(30))
-            .connect_with(opts)
-            .await?;
+            .acquire_timeout(Duration::from_secs(30));
+        if in_memory {
+            // Each connection to `sqlite::memory:` is a database of its own,
+            // gone when the connection closes
+            pool = pool
+                .max_connections(1)
+                .idle_timeout(None)
+                .max_lifetime(None);
+        }
+        let pool = pool.connect_with(opts).await?;
+
+        Ok(Self {
+            pool,
+            backend,
+            timezone: Tz::UTC,
+            archive_columns: Arc::default(),
+        })
+    }
 
-        Ok(Self { pool })
+    pub(crate) fn cached_archive_columns(&self) -> Option<Arc<ArchiveColumns>> {
+        self.archive_columns.read().ok().and_then(|c| c.clone())
     }
 
-    /// Get reference to underlying pool for direct queries
-    pub fn pool(&self) -> &MySqlPool {
+    pub(crate) fn cache_archive_columns(&self, columns: Arc<ArchiveColumns>) {
+        if let Ok(mut cached) = self.archive_columns.write() {
+            *cached = Some(columns);
+        }
+    }
+
+    /// Get reference to underlying pool for direct queries. SQL must suit
+    /// the [`backend`](Self::backend).
+    pub fn pool(&self) -> &AnyPool {
         &self.pool
     }
 
+    /// The database engine connected to
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Station timezone, whose local days the daily summaries cover
+    /// (default UTC); time-span aggregates use it to find whole days
+    pub fn with_timezone(mut self, tz: Tz) -> Self {
+        self.timezone = tz;
+        self
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone
+    }
+
     /// Test the database connection
     pub async fn ping(&self) -> DbResult<()> {
         sqlx::query("SELECT 1").execute(&self.pool).await?;
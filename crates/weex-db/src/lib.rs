@@ -1,14 +1,29 @@
-//! Database access layer for WeeWX MySQL schema
+//! Database access layer for WeeWX databases
 //!
-//! Uses existing schema from Python WeeWX - NO migrations.
-//! Assumes schema is already created and matches production layout.
-
+//! MySQL/MariaDB, SQLite and PostgreSQL are supported, chosen by the database
+//! URL's scheme (see [`Backend`]).
+//! Uses existing schema from Python WeeWX - NO migrations. The schema is
+//! checked at startup ([`DbClient::check_schema`]); an empty database can be
+//! given one with [`DbClient::init_schema`].
+//! Archive records are written to whatever columns the `archive` table has
+//! (e.g. WeeWX's `wview_extended` schema), read once at startup. Large
+//! ranges are read in pages or as a stream (see [`ArchiveQuery`]), and
+//! aggregated over time spans with [`DbClient::get_aggregate`].
+
+pub mod aggregate;
+pub mod backend;
 pub mod client;
 pub mod queries;
+pub mod range;
 pub mod schema;
+pub mod setup;
 
+pub use backend::{Backend, ConflictPolicy};
 pub use client::*;
+pub use queries::ArchiveWrite;
+pub use range::ArchiveQuery;
 pub use schema::*;
+pub use setup::SchemaInfo;
 
 use thiserror::Error;
 
@@ -25,6 +40,12 @@ pub enum DbError {
 
     #[error("Constraint violation: {0}")]
     ConstraintViolation(String),
+
+    #[error("Schema error: {0}")]
+    SchemaError(String),
+
+    #[error("Unit system mismatch: database uses {database}, configured {configured}")]
+    UnitSystemMismatch { database: i32, configured: i32 },
 }
 
 pub type DbResult<T> = Result<T, DbError>;
@@ -1,58 +1,253 @@
 //! Database query operations for WeeWX tables
+//!
+//! Queries are written with `?` placeholders and adapted to the connected
+//! [`Backend`](crate::Backend).
 
-use crate::schema::ArchiveRow;
-use crate::{DbClient, DbResult};
-use sqlx::Row;
-use tracing::{debug, instrument};
+use crate::schema::{tables, ArchiveColumns, ArchiveRow, DailySummaryRow};
+use crate::{ConflictPolicy, DbClient, DbError, DbResult};
+use sqlx::any::AnyRow;
+use sqlx::{Column, Executor, Row};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tracing::{debug, info, instrument};
+use weex_core::{ArchiveRecord, ObservationValue};
+
+/// Columns of every daily summary table
+const DAILY_SUMMARY_COLUMNS: [&str; 9] = [
+    "dateTime", "min", "mintime", "max", "maxtime", "sum", "count", "wsum", "sumtime",
+];
+
+/// Extra columns of the wind daily summary table
+const WIND_SUMMARY_COLUMNS: [&str; 6] = [
+    "max_dir",
+    "xsum",
+    "ysum",
+    "dirsumtime",
+    "squaresum",
+    "wsquaresum",
+];
+
+/// Bind parameters per statement of a batch insert, under the lowest limit
+/// of the backends (SQLite's 32766)
+const MAX_BIND_PARAMS: usize = 30_000;
+
+/// Outcome of an archive write
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveWrite {
+    /// Rows the database reports as affected. Records skipped under
+    /// [`ConflictPolicy::Skip`] are not counted; how an update is counted
+    /// varies by backend (MySQL counts 2).
+    pub written: u64,
+    /// Fields without an archive column, which were dropped
+    pub dropped: Vec<String>,
+}
 
 impl DbClient {
+    /// Quote an identifier for the backend
+    fn q(&self, name: &str) -> String {
+        self.backend().quote(name)
+    }
+
+    /// Adapt a query's placeholders to the backend
+    fn sql<'a>(&self, sql: &'a str) -> Cow<'a, str> {
+        self.backend().sql(sql)
+    }
+
     /// Insert a single archive record
     #[instrument(skip(self, record))]
     pub async fn insert_archive(&self, record: &ArchiveRow) -> DbResult<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO archive (
-                dateTime, usUnits, interval,
-                outTemp, inTemp, extraTemp1,
-                outHumidity, inHumidity,
-                barometer, pressure, altimeter,
-                windSpeed, windDir, windGust, windGustDir,
-                rain, rainRate,
-                dewpoint, windchill, heatindex,
-                radiation, UV, rxCheckPercent
-            ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-            "#,
-        )
-        .bind(record.date_time)
-        .bind(record.us_units)
-        .bind(record.interval)
-        .bind(record.out_temp)
-        .bind(record.in_temp)
-        .bind(record.extra_temp1)
-        .bind(record.out_humidity)
-        .bind(record.in_humidity)
-        .bind(record.barometer)
-        .bind(record.pressure)
-        .bind(record.altimeter)
-        .bind(record.wind_speed)
-        .bind(record.wind_dir)
-        .bind(record.wind_gust)
-        .bind(record.wind_gust_dir)
-        .bind(record.rain)
-        .bind(record.rain_rate)
-        .bind(record.dewpoint)
-        .bind(record.windchill)
-        .bind(record.heatindex)
-        .bind(record.radiation)
-        .bind(record.uv)
-        .bind(record.rx_check_percent)
-        .execute(self.pool())
-        .await?;
-
+        self.write_archive_records(None, &[record.into()]).await?;
         debug!("Inserted archive record for timestamp {}", record.date_time);
         Ok(())
     }
 
+    /// Insert an archive record, replacing any record with the same
+    /// timestamp (e.g. to roll late packets into it)
+    #[instrument(skip(self, record))]
+    pub async fn replace_archive(&self, record: &ArchiveRow) -> DbResult<()> {
+        self.write_archive_records(Some(ConflictPolicy::Replace), &[record.into()])
+            .await?;
+        debug!("Replaced archive record for timestamp {}", record.date_time);
+        Ok(())
+    }
+
+    /// Read the `archive` table's columns from the database (and cache them)
+    #[instrument(skip(self))]
+    pub async fn load_archive_columns(&self) -> DbResult<Arc<ArchiveColumns>> {
+        let describe = self
+            .pool()
+            .describe(&format!("SELECT * FROM {}", tables::ARCHIVE))
+            .await?;
+        let names = describe
+            .columns()
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect();
+        let columns = Arc::new(ArchiveColumns::new(names));
+        info!(
+            "archive table has {} observation columns",
+            columns.observations().count()
+        );
+        self.cache_archive_columns(Arc::clone(&columns));
+        Ok(columns)
+    }
+
+    /// The `archive` table's columns, read once per client
+    pub async fn archive_columns(&self) -> DbResult<Arc<ArchiveColumns>> {
+        match self.cached_archive_columns() {
+            Some(columns) => Ok(columns),
+            None => self.load_archive_columns().await,
+        }
+    }
+
+    /// Insert an archive record into whichever of its fields the table has
+    /// columns for. Returns the fields without a column, which are dropped.
+    #[instrument(skip(self, record))]
+    pub async fn insert_archive_record(&self, record: &ArchiveRecord) -> DbResult<Vec<String>> {
+        let write = self
+            .write_archive_records(None, std::slice::from_ref(record))
+            .await?;
+        debug!("Inserted archive record for timestamp {}", record.date_time);
+        Ok(write.dropped)
+    }
+
+    /// Like [`insert_archive_record`](Self::insert_archive_record), replacing
+    /// any record with the same timestamp
+    #[instrument(skip(self, record))]
+    pub async fn replace_archive_record(&self, record: &ArchiveRecord) -> DbResult<Vec<String>> {
+        let write = self.upsert_archive(record, ConflictPolicy::Replace).await?;
+        debug!("Replaced archive record for timestamp {}", record.date_time);
+        Ok(write.dropped)
+    }
+
+    /// Insert an archive record, resolving a record with the same timestamp
+    /// by `policy` instead of failing. Under [`ConflictPolicy::Skip`],
+    /// `written` is 0 if the archive already had the record.
+    #[instrument(skip(self, record))]
+    pub async fn upsert_archive(
+        &self,
+        record: &ArchiveRecord,
+        policy: ConflictPolicy,
+    ) -> DbResult<ArchiveWrite> {
+        let write = self
+            .write_archive_records(Some(policy), std::slice::from_ref(record))
+            .await?;
+        debug!(
+            "Upserted archive record for timestamp {} ({:?}, {} rows)",
+            record.date_time, policy, write.written
+        );
+        Ok(write)
+    }
+
+    /// Insert many archive records (e.g. a backfill) with multi-row
+    /// `INSERT`s in one transaction, resolving existing timestamps by
+    /// `policy`. Records missing a column another one has bind it as NULL;
+    /// a timestamp repeated in `records` is written once, from its last
+    /// record.
+    #[instrument(skip(self, records), fields(records = records.len()))]
+    pub async fn insert_archive_batch(
+        &self,
+        records: &[ArchiveRecord],
+        policy: ConflictPolicy,
+    ) -> DbResult<ArchiveWrite> {
+        let write = self.write_archive_records(Some(policy), records).await?;
+        info!(
+            "Inserted batch of {} archive records ({:?}, {} rows)",
+            records.len(),
+            policy,
+            write.written
+        );
+        Ok(write)
+    }
+
+    async fn write_archive_records(
+        &self,
+        conflict: Option<ConflictPolicy>,
+        records: &[ArchiveRecord],
+    ) -> DbResult<ArchiveWrite> {
+        let mut write = ArchiveWrite::default();
+        let records: BTreeMap<i64, &ArchiveRecord> =
+            records.iter().map(|r| (r.date_time, r)).collect();
+        if records.is_empty() {
+            return Ok(write);
+        }
+
+        // One column list for the batch: the union of the records' fields
+        let columns = self.archive_columns().await?;
+        let mut observations = BTreeSet::new();
+        let mut dropped = BTreeSet::new();
+        for name in records.values().flat_map(|r| r.aggregates.keys()) {
+            match columns.get(name) {
+                Some(column) if !ArchiveColumns::KEY_COLUMNS.contains(&column) => {
+                    observations.insert(column);
+                }
+                Some(_) => {}
+                None => {
+                    dropped.insert(name.clone());
+                }
+            }
+        }
+        write.dropped = dropped.into_iter().collect();
+        let mut names: Vec<&str> = ArchiveColumns::KEY_COLUMNS.to_vec();
+        names.extend(&observations);
+        let others: Vec<&str> = columns
+            .observations()
+            .filter(|c| !names.contains(c))
+            .collect();
+        let conflict = conflict.map(|policy| ("dateTime", policy));
+        let chunk_rows = (MAX_BIND_PARAMS / names.len()).max(1);
+        let records: Vec<_> = records.into_values().collect();
+
+        let mut tx = self.pool().begin().await?;
+        for chunk in records.chunks(chunk_rows) {
+            let sql =
+                self.backend()
+                    .insert_sql(tables::ARCHIVE, &names, chunk.len(), conflict, &others);
+            let mut query = sqlx::query(&sql);
+            for record in chunk {
+                // The record's value of each column, by the field mapped to it
+                let values: BTreeMap<&str, &ObservationValue> = record
+                    .aggregates
+                    .iter()
+                    .filter_map(|(name, value)| Some((columns.get(name)?, value)))
+                    .collect();
+                query = query
+                    .bind(record.date_time)
+                    .bind(record.us_units)
+                    .bind(record.interval);
+                for column in &observations {
+                    query = match values.get(column) {
+                        Some(ObservationValue::Float(v)) => query.bind(*v),
+                        Some(ObservationValue::Integer(v)) => query.bind(*v),
+                        Some(ObservationValue::Bool(v)) => query.bind(*v),
+                        Some(ObservationValue::String(v)) => query.bind(v.as_str()),
+                        Some(ObservationValue::Null) | None => query.bind(None::<f64>),
+                    };
+                }
+            }
+            write.written += query.execute(&mut *tx).await?.rows_affected();
+        }
+        tx.commit().await?;
+        Ok(write)
+    }
+
+    /// Get archive records within a time range, with every column the table
+    /// has
+    #[instrument(skip(self))]
+    pub async fn get_archive_records(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> DbResult<Vec<ArchiveRecord>> {
+        let rows = sqlx::query(&self.archive_range_sql())
+            .bind(start_time)
+            .bind(end_time)
+            .fetch_all(self.pool())
+            .await?;
+        rows.iter().map(record_from_row).collect()
+    }
+
     /// Get archive records within a time range
     #[instrument(skip(self))]
     pub async fn get_archive_range(
@@ -60,17 +255,11 @@ impl DbClient {
         start_time: i64,
         end_time: i64,
     ) -> DbResult<Vec<ArchiveRow>> {
-        let records = sqlx::query_as::<_, ArchiveRow>(
-            r#"
-            SELECT * FROM archive
-            WHERE dateTime >= ? AND dateTime <= ?
-            ORDER BY dateTime ASC
-            "#,
-        )
-        .bind(start_time)
-        .bind(end_time)
-        .fetch_all(self.pool())
-        .await?;
+        let records = sqlx::query_as::<_, ArchiveRow>(&self.archive_range_sql())
+            .bind(start_time)
+            .bind(end_time)
+            .fetch_all(self.pool())
+            .await?;
 
         debug!(
             "Retrieved {} archive records between {} and {}",
@@ -81,16 +270,24 @@ impl DbClient {
         Ok(records)
     }
 
+    fn archive_range_sql(&self) -> String {
+        let date_time = self.q("dateTime");
+        self.sql(&format!(
+            "SELECT * FROM {} WHERE {1} >= ? AND {1} <= ? ORDER BY {1} ASC",
+            tables::ARCHIVE,
+            date_time
+        ))
+        .into_owned()
+    }
+
     /// Get the most recent archive record
     #[instrument(skip(self))]
     pub async fn get_latest_archive(&self) -> DbResult<Option<ArchiveRow>> {
-        let record = sqlx::query_as::<_, ArchiveRow>(
-            r#"
-            SELECT * FROM archive
-            ORDER BY dateTime DESC
-            LIMIT 1
-            "#,
-        )
+        let record = sqlx::query_as::<_, ArchiveRow>(&format!(
+            "SELECT * FROM {} ORDER BY {} DESC LIMIT 1",
+            tables::ARCHIVE,
+            self.q("dateTime")
+        ))
         .fetch_optional(self.pool())
         .await?;
 
@@ -100,11 +297,10 @@ impl DbClient {
     /// Get metadata value by name
     #[instrument(skip(self))]
     pub async fn get_metadata(&self, name: &str) -> DbResult<Option<String>> {
-        let row = sqlx::query(
-            r#"
-            SELECT value FROM archive_metadata WHERE name = ?
-            "#,
-        )
+        let row = sqlx::query(&self.sql(&format!(
+            "SELECT value FROM {} WHERE name = ?",
+            tables::METADATA
+        )))
         .bind(name)
         .fetch_optional(self.pool())
         .await?;
@@ -115,28 +311,164 @@ impl DbClient {
     /// Set metadata value
     #[instrument(skip(self))]
     pub async fn set_metadata(&self, name: &str, value: &str) -> DbResult<()> {
-        sqlx::query(
-            r#"
-            INSERT INTO archive_metadata (name, value)
-            VALUES (?, ?)
-            ON DUPLICATE KEY UPDATE value = VALUES(value)
-            "#,
-        )
-        .bind(name)
-        .bind(value)
+        let sql = self
+            .backend()
+            .replace_sql(tables::METADATA, "name", &["name", "value"], &[]);
+        sqlx::query(&sql)
+            .bind(name)
+            .bind(value)
+            .execute(self.pool())
+            .await?;
+
+        debug!("Set metadata: {} = {}", name, value);
+        Ok(())
+    }
+
+    /// Create the daily summary table for `obs` if it does not exist
+    #[instrument(skip(self))]
+    pub async fn create_daily_summary(&self, obs: &str) -> DbResult<()> {
+        let table = self.q(&daily_summary_table(obs)?);
+        let real = self.backend().real_type();
+        let wind_columns = if obs == tables::WIND_SUMMARY {
+            format!(
+                ", max_dir {0}, xsum {0}, ysum {0}, dirsumtime INTEGER, \
+                 squaresum {0}, wsquaresum {0}",
+                real
+            )
+        } else {
+            String::new()
+        };
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (\
+             {date_time} INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             min {real}, mintime INTEGER, max {real}, maxtime INTEGER, \
+             sum {real}, count INTEGER, wsum {real}, sumtime INTEGER{wind_columns})",
+            date_time = self.q("dateTime"),
+        ))
+        .execute(self.pool())
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             name CHAR(20) NOT NULL UNIQUE PRIMARY KEY, value TEXT)",
+            tables::DAILY_SUMMARY_METADATA
+        ))
         .execute(self.pool())
         .await?;
+        Ok(())
+    }
 
-        debug!("Set metadata: {} = {}", name, value);
+    /// Get the daily summary of `obs` for the day starting at `day_start`
+    #[instrument(skip(self))]
+    pub async fn get_daily_summary(
+        &self,
+        obs: &str,
+        day_start: i64,
+    ) -> DbResult<Option<DailySummaryRow>> {
+        let table = self.q(&daily_summary_table(obs)?);
+        let row = sqlx::query_as::<_, DailySummaryRow>(&self.sql(&format!(
+            "SELECT * FROM {} WHERE {} = ?",
+            table,
+            self.q("dateTime")
+        )))
+        .bind(day_start)
+        .fetch_optional(self.pool())
+        .await?;
+        Ok(row)
+    }
+
+    /// Get the daily summaries of `obs` for the days starting at or after
+    /// `start` and before `end`, oldest first
+    #[instrument(skip(self))]
+    pub async fn get_daily_summary_range(
+        &self,
+        obs: &str,
+        start: i64,
+        end: i64,
+    ) -> DbResult<Vec<DailySummaryRow>> {
+        let table = self.q(&daily_summary_table(obs)?);
+        let date_time = self.q("dateTime");
+        let rows = sqlx::query_as::<_, DailySummaryRow>(&self.sql(&format!(
+            "SELECT * FROM {} WHERE {1} >= ? AND {1} < ? ORDER BY {1} ASC",
+            table, date_time
+        )))
+        .bind(start)
+        .bind(end)
+        .fetch_all(self.pool())
+        .await?;
+        Ok(rows)
+    }
+
+    /// Insert or replace one day of the daily summary of `obs`
+    #[instrument(skip(self, row))]
+    pub async fn put_daily_summary(&self, obs: &str, row: &DailySummaryRow) -> DbResult<()> {
+        let table = self.q(&daily_summary_table(obs)?);
+        let wind = obs == tables::WIND_SUMMARY;
+        let mut columns = DAILY_SUMMARY_COLUMNS.to_vec();
+        if wind {
+            columns.extend(WIND_SUMMARY_COLUMNS);
+        }
+        let sql = self
+            .backend()
+            .replace_sql(&table, "dateTime", &columns, &[]);
+        let mut query = sqlx::query(&sql)
+            .bind(row.date_time)
+            .bind(row.min)
+            .bind(row.mintime)
+            .bind(row.max)
+            .bind(row.maxtime)
+            .bind(row.sum)
+            .bind(row.count)
+            .bind(row.wsum)
+            .bind(row.sumtime);
+        if wind {
+            query = query
+                .bind(row.max_dir)
+                .bind(row.xsum)
+                .bind(row.ysum)
+                .bind(row.dirsumtime)
+                .bind(row.squaresum)
+                .bind(row.wsquaresum);
+        }
+        query.execute(self.pool()).await?;
+        Ok(())
+    }
+
+    /// Delete every day of the daily summary of `obs`
+    #[instrument(skip(self))]
+    pub async fn clear_daily_summary(&self, obs: &str) -> DbResult<u64> {
+        let table = self.q(&daily_summary_table(obs)?);
+        let result = sqlx::query(&format!("DELETE FROM {}", table))
+            .execute(self.pool())
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Record the timestamp of the last archive record in the daily summaries
+    #[instrument(skip(self))]
+    pub async fn set_daily_summary_last_update(&self, date_time: i64) -> DbResult<()> {
+        let sql = self.backend().replace_sql(
+            tables::DAILY_SUMMARY_METADATA,
+            "name",
+            &["name", "value"],
+            &[],
+        );
+        sqlx::query(&sql)
+            .bind("lastUpdate")
+            .bind(date_time.to_string())
+            .execute(self.pool())
+            .await?;
         Ok(())
     }
 
     /// Get count of archive records
     #[instrument(skip(self))]
     pub async fn count_archive_records(&self) -> DbResult<i64> {
-        let row = sqlx::query("SELECT COUNT(*) as count FROM archive")
-            .fetch_one(self.pool())
-            .await?;
+        let row = sqlx::query(&format!(
+            "SELECT COUNT(*) as count FROM {}",
+            tables::ARCHIVE
+        ))
+        .fetch_one(self.pool())
+        .await?;
 
         Ok(row.get("count"))
     }
@@ -144,10 +476,14 @@ impl DbClient {
     /// Delete archive records older than timestamp
     #[instrument(skip(self))]
     pub async fn delete_archive_before(&self, timestamp: i64) -> DbResult<u64> {
-        let result = sqlx::query("DELETE FROM archive WHERE dateTime < ?")
-            .bind(timestamp)
-            .execute(self.pool())
-            .await?;
+        let result = sqlx::query(&self.sql(&format!(
+            "DELETE FROM {} WHERE {} < ?",
+            tables::ARCHIVE,
+            self.q("dateTime")
+        )))
+        .bind(timestamp)
+        .execute(self.pool())
+        .await?;
 
         let deleted = result.rows_affected();
         debug!("Deleted {} archive records before {}", deleted, timestamp);
@@ -155,14 +491,222 @@ impl DbClient {
     }
 }
 
+/// Decode an `archive` row of any schema. Observation columns are REAL in
+/// WeeWX schemas; integer columns are read as integers.
+pub(crate) fn record_from_row(row: &AnyRow) -> DbResult<ArchiveRecord> {
+    let mut record = ArchiveRecord {
+        date_time: row.try_get("dateTime")?,
+        interval: row.try_get("interval")?,
+        us_units: row.try_get("usUnits")?,
+        aggregates: Default::default(),
+    };
+    for column in row.columns() {
+        let name = column.name();
+        if ArchiveColumns::KEY_COLUMNS.contains(&name) {
+            continue;
+        }
+        let i = column.ordinal();
+        let value = if let Ok(v) = row.try_get::<Option<f64>, _>(i) {
+            v.map_or(ObservationValue::Null, ObservationValue::Float)
+        } else if let Ok(v) = row.try_get::<Option<i64>, _>(i) {
+            v.map_or(ObservationValue::Null, ObservationValue::Integer)
+        } else if let Ok(v) = row.try_get::<Option<String>, _>(i) {
+            v.map_or(ObservationValue::Null, ObservationValue::String)
+        } else {
+            ObservationValue::Null
+        };
+        record.aggregates.insert(name.to_string(), value);
+    }
+    Ok(record)
+}
+
+/// Table names are interpolated into SQL, so only plain identifiers pass
+fn daily_summary_table(obs: &str) -> DbResult<String> {
+    if obs.is_empty() || !obs.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(DbError::ConfigError(format!(
+            "invalid observation name: {:?}",
+            obs
+        )));
+    }
+    Ok(tables::daily_summary(obs))
+}
+
+/// Processor state (e.g. the last rain counter reading) kept in
+/// `archive_metadata`
+#[async_trait::async_trait]
+impl weex_core::StateStore for DbClient {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.get_metadata(key).await?)
+    }
+
+    async fn save(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        Ok(self.set_metadata(key, value).await?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    // Note: Integration tests with real database are in tests/golden/
-    // These are just unit tests for query structure validation
+    // Note: Integration tests against MySQL are in tests/golden/; these run
+    // on an in-memory SQLite database
+    use super::*;
+    use weex_core::ObservationValue::Float;
+
+    async fn sqlite() -> DbClient {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL, \
+             windSpeed REAL, pm2_5 REAL, lightning_strike_count REAL)",
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+        sqlx::query("CREATE TABLE archive_metadata (name CHAR(20) PRIMARY KEY, value TEXT)")
+            .execute(db.pool())
+            .await
+            .unwrap();
+        db
+    }
+
+    fn record(date_time: i64, fields: &[(&str, f64)]) -> ArchiveRecord {
+        ArchiveRecord {
+            date_time,
+            interval: 300,
+            us_units: 16,
+            aggregates: fields
+                .iter()
+                .map(|(n, v)| (n.to_string(), Float(*v)))
+                .collect(),
+        }
+    }
 
     #[test]
-    fn test_query_syntax() {
-        // Queries are validated at runtime by sqlx
-        // This test just ensures module compiles
+    fn test_daily_summary_table_names() {
+        assert_eq!(
+            daily_summary_table("outTemp").unwrap(),
+            "archive_day_outTemp"
+        );
+        assert!(daily_summary_table("x; DROP TABLE archive").is_err());
+        assert!(daily_summary_table("").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_archive_records_on_sqlite() {
+        let db = sqlite().await;
+        let columns = db.load_archive_columns().await.unwrap();
+        assert_eq!(columns.observations().count(), 4);
+
+        let first = record(
+            300,
+            &[("outTemp", 20.5), ("pm2_5", 8.0), ("soilMoist9", 1.0)],
+        );
+        let dropped = db.insert_archive_record(&first).await.unwrap();
+        assert_eq!(dropped, vec!["soilMoist9".to_string()]);
+        db.insert_archive_record(&record(600, &[("windSpeed", 3.0)]))
+            .await
+            .unwrap();
+        assert!(db.insert_archive_record(&first).await.is_err());
+
+        // Replacing resets the columns the new record does not have
+        db.replace_archive_record(&record(300, &[("outTemp", 21.0)]))
+            .await
+            .unwrap();
+        let records = db.get_archive_records(0, 1_000).await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].aggregates.get("outTemp"), Some(&Float(21.0)));
+        assert_eq!(
+            records[0].aggregates.get("pm2_5"),
+            Some(&ObservationValue::Null)
+        );
+
+        let latest = db.get_latest_archive().await.unwrap().unwrap();
+        assert_eq!((latest.date_time, latest.wind_speed), (600, Some(3.0)));
+        assert_eq!(db.count_archive_records().await.unwrap(), 2);
+        assert_eq!(db.delete_archive_before(600).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upserts_and_batches_on_sqlite() {
+        let db = sqlite().await;
+        db.insert_archive_record(&record(300, &[("outTemp", 20.0), ("pm2_5", 8.0)]))
+            .await
+            .unwrap();
+
+        let update = record(300, &[("outTemp", 21.0)]);
+        let skipped = db
+            .upsert_archive(&update, ConflictPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(skipped.written, 0);
+        db.upsert_archive(&update, ConflictPolicy::Merge)
+            .await
+            .unwrap();
+        let merged = &db.get_archive_records(300, 300).await.unwrap()[0];
+        assert_eq!(merged.aggregates.get("outTemp"), Some(&Float(21.0)));
+        assert_eq!(merged.aggregates.get("pm2_5"), Some(&Float(8.0)));
+
+        // Records with different fields share one statement; the repeated
+        // timestamp is written from its last record
+        let batch: Vec<_> = (1..=2_000)
+            .map(|i| record(i * 300, &[("windSpeed", i as f64)]))
+            .chain([record(600, &[("outTemp", 5.0), ("soilMoist9", 1.0)])])
+            .collect();
+        let write = db
+            .insert_archive_batch(&batch, ConflictPolicy::Skip)
+            .await
+            .unwrap();
+        assert_eq!(write.written, 1_999);
+        assert_eq!(write.dropped, vec!["soilMoist9".to_string()]);
+        assert_eq!(db.count_archive_records().await.unwrap(), 2_000);
+
+        let records = db.get_archive_records(300, 600).await.unwrap();
+        assert_eq!(
+            records[0].aggregates.get("windSpeed"),
+            Some(&ObservationValue::Null)
+        );
+        assert_eq!(records[1].aggregates.get("outTemp"), Some(&Float(5.0)));
+        assert_eq!(
+            records[1].aggregates.get("windSpeed"),
+            Some(&ObservationValue::Null)
+        );
+
+        db.insert_archive_batch(&batch[..1], ConflictPolicy::Replace)
+            .await
+            .unwrap();
+        let replaced = &db.get_archive_records(300, 300).await.unwrap()[0];
+        assert_eq!(
+            replaced.aggregates.get("outTemp"),
+            Some(&ObservationValue::Null)
+        );
+        assert_eq!(replaced.aggregates.get("windSpeed"), Some(&Float(1.0)));
+    }
+
+    #[tokio::test]
+    async fn test_metadata_and_daily_summaries_on_sqlite() {
+        let db = sqlite().await;
+        db.set_metadata("unit_system", "1").await.unwrap();
+        db.set_metadata("unit_system", "16").await.unwrap();
+        assert_eq!(
+            db.get_metadata("unit_system").await.unwrap().as_deref(),
+            Some("16")
+        );
+        assert_eq!(db.get_metadata("missing").await.unwrap(), None);
+
+        db.create_daily_summary("wind").await.unwrap();
+        let row = DailySummaryRow {
+            date_time: 86_400,
+            max: Some(9.0),
+            max_dir: Some(100.0),
+            count: Some(3),
+            ..Default::default()
+        };
+        db.put_daily_summary("wind", &row).await.unwrap();
+        db.put_daily_summary("wind", &row).await.unwrap();
+        assert_eq!(
+            db.get_daily_summary("wind", 86_400).await.unwrap(),
+            Some(row)
+        );
+        db.set_daily_summary_last_update(86_700).await.unwrap();
+        assert_eq!(db.clear_daily_summary("wind").await.unwrap(), 1);
     }
 }
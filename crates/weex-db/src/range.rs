@@ -0,0 +1,239 @@
+//! Archive range reads with bounded memory
+//!
+//! [`DbClient::get_archive_range`] loads a whole range at once. The reads
+//! here take it a page at a time with keyset pagination (`dateTime` after the
+//! last one seen, `LIMIT n`), which costs the same however deep into the
+//! table a page starts, and can read just some of the columns.
+
+use crate::queries::record_from_row;
+use crate::schema::{tables, ArchiveColumns, ArchiveRow};
+use crate::{DbClient, DbError, DbResult};
+use futures_util::stream::{self, Stream, TryStreamExt};
+use sqlx::any::AnyRow;
+use sqlx::{FromRow, Row};
+use std::sync::Arc;
+use tracing::instrument;
+use weex_core::ArchiveRecord;
+
+/// Default rows fetched per query when streaming
+pub const DEFAULT_PAGE_SIZE: usize = 1_000;
+
+/// Archive records with `start <= dateTime <= end`, optionally projected to
+/// some of the columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveQuery {
+    start: i64,
+    end: i64,
+    columns: Option<Vec<String>>,
+    page_size: usize,
+}
+
+impl ArchiveQuery {
+    pub fn new(start: i64, end: i64) -> Self {
+        Self {
+            start,
+            end,
+            columns: None,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Read only these observations (by column or obs-type name) instead of
+    /// every column; `dateTime`, `usUnits` and `interval` are always read
+    pub fn columns<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.columns = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Rows fetched per query when streaming (default 1000)
+    pub fn page_size(mut self, rows: usize) -> Self {
+        self.page_size = rows.max(1);
+        self
+    }
+
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    pub fn end(&self) -> i64 {
+        self.end
+    }
+}
+
+impl DbClient {
+    /// Up to `limit` records of `query`, oldest first, after the one stamped
+    /// `after` (or from the start of the range). Pass the last record's
+    /// `date_time` as `after` for the next page; a short page is the last.
+    #[instrument(skip(self))]
+    pub async fn get_archive_page(
+        &self,
+        query: &ArchiveQuery,
+        after: Option<i64>,
+        limit: usize,
+    ) -> DbResult<Vec<ArchiveRecord>> {
+        let rows = self.fetch_archive_page(query, after, limit).await?;
+        rows.iter().map(record_from_row).collect()
+    }
+
+    /// Stream the records of `query`, oldest first, fetching a page at a
+    /// time. No connection is held between pages.
+    pub fn stream_archive(
+        &self,
+        query: ArchiveQuery,
+    ) -> impl Stream<Item = DbResult<ArchiveRecord>> + Send + '_ {
+        self.stream_pages(query, record_from_row)
+    }
+
+    /// Stream archive rows within a time range, like
+    /// [`get_archive_range`](Self::get_archive_range) without loading them
+    /// all
+    pub fn stream_archive_range(
+        &self,
+        start_time: i64,
+        end_time: i64,
+    ) -> impl Stream<Item = DbResult<ArchiveRow>> + Send + '_ {
+        self.stream_pages(ArchiveQuery::new(start_time, end_time), |row| {
+            Ok(ArchiveRow::from_row(row)?)
+        })
+    }
+
+    fn stream_pages<T: Send + 'static>(
+        &self,
+        query: ArchiveQuery,
+        decode: fn(&AnyRow) -> DbResult<T>,
+    ) -> impl Stream<Item = DbResult<T>> + Send + '_ {
+        let query = Arc::new(query);
+        // State: the key to continue after, or None once a short page ends it
+        stream::try_unfold(Some(None), move |after| {
+            let query = Arc::clone(&query);
+            async move {
+                let Some(after) = after else {
+                    return Ok::<_, DbError>(None);
+                };
+                let rows = self
+                    .fetch_archive_page(&query, after, query.page_size)
+                    .await?;
+                let next = match rows.last() {
+                    Some(last) if rows.len() == query.page_size => {
+                        Some(Some(last.try_get::<i64, _>("dateTime")?))
+                    }
+                    _ => None,
+                };
+                let page = rows.iter().map(decode).collect::<DbResult<Vec<_>>>()?;
+                Ok(Some((page, next)))
+            }
+        })
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    async fn fetch_archive_page(
+        &self,
+        query: &ArchiveQuery,
+        after: Option<i64>,
+        limit: usize,
+    ) -> DbResult<Vec<AnyRow>> {
+        let backend = self.backend();
+        let select = match &query.columns {
+            None => "*".to_string(),
+            Some(names) => {
+                let columns = self.archive_columns().await?;
+                let mut selected = ArchiveColumns::KEY_COLUMNS.to_vec();
+                for name in names {
+                    let column = columns.get(name).ok_or_else(|| {
+                        DbError::SchemaError(format!(
+                            "`{}` table has no `{}` column",
+                            tables::ARCHIVE,
+                            name
+                        ))
+                    })?;
+                    if !selected.contains(&column) {
+                        selected.push(column);
+                    }
+                }
+                let quoted: Vec<_> = selected.iter().map(|c| backend.quote(c)).collect();
+                quoted.join(", ")
+            }
+        };
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {2} >= ? AND {2} <= ? ORDER BY {2} ASC LIMIT ?",
+            select,
+            tables::ARCHIVE,
+            backend.quote("dateTime")
+        );
+        let from = after.map_or(query.start, |after| {
+            query.start.max(after.saturating_add(1))
+        });
+        let rows = sqlx::query(&backend.sql(&sql))
+            .bind(from)
+            .bind(query.end)
+            .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+            .fetch_all(self.pool())
+            .await?;
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weex_core::ObservationValue;
+
+    #[tokio::test]
+    async fn test_pages_and_streams_on_sqlite() {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL, \
+             windSpeed REAL)",
+        )
+        .execute(db.pool())
+        .await
+        .unwrap();
+        for i in 1..=25 {
+            sqlx::query("INSERT INTO archive VALUES (?, 16, 300, ?, 2.0)")
+                .bind(i * 300)
+                .bind(i as f64)
+                .execute(db.pool())
+                .await
+                .unwrap();
+        }
+
+        let query = ArchiveQuery::new(600, 6_000).columns(["outTemp"]);
+        let page = db.get_archive_page(&query, None, 10).await.unwrap();
+        assert_eq!(page.len(), 10);
+        assert_eq!((page[0].date_time, page[9].date_time), (600, 3_300));
+        assert_eq!(
+            page[0].aggregates.get("outTemp"),
+            Some(&ObservationValue::Float(2.0))
+        );
+        assert!(!page[0].aggregates.contains_key("windSpeed"));
+        let next = db.get_archive_page(&query, Some(3_300), 10).await.unwrap();
+        assert_eq!(next[0].date_time, 3_600);
+        assert!(db
+            .get_archive_page(&query.clone().columns(["soilMoist9"]), None, 10)
+            .await
+            .is_err());
+
+        // The stream runs on across pages (of 5, 5, 5 and 4 rows)
+        let records: Vec<_> = db
+            .stream_archive(query.page_size(5))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 19);
+        assert_eq!(records.last().unwrap().date_time, 6_000);
+
+        let rows: Vec<_> = db
+            .stream_archive_range(i64::MIN, i64::MAX)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(rows.len(), 25);
+        assert_eq!(rows[24].out_temp, Some(25.0));
+    }
+}
@@ -6,9 +6,13 @@
 
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::HashMap;
+use weex_core::{ArchiveRecord, ObservationValue};
 
-/// Archive table record (main weather data storage)
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+/// Archive table record (main weather data storage). Columns the table
+/// lacks read as empty.
+#[derive(Debug, Clone, Default, FromRow, Serialize, Deserialize)]
+#[sqlx(default)]
 pub struct ArchiveRow {
     /// Primary timestamp (Unix epoch, end of interval)
     #[sqlx(rename = "dateTime")]
@@ -78,6 +82,43 @@ pub struct ArchiveRow {
     pub rx_check_percent: Option<f64>,
 }
 
+impl From<&ArchiveRow> for ArchiveRecord {
+    /// The row's non-empty observations, keyed by archive column
+    fn from(row: &ArchiveRow) -> Self {
+        let columns = [
+            ("outTemp", row.out_temp),
+            ("inTemp", row.in_temp),
+            ("extraTemp1", row.extra_temp1),
+            ("outHumidity", row.out_humidity),
+            ("inHumidity", row.in_humidity),
+            ("barometer", row.barometer),
+            ("pressure", row.pressure),
+            ("altimeter", row.altimeter),
+            ("windSpeed", row.wind_speed),
+            ("windDir", row.wind_dir),
+            ("windGust", row.wind_gust),
+            ("windGustDir", row.wind_gust_dir),
+            ("rain", row.rain),
+            ("rainRate", row.rain_rate),
+            ("dewpoint", row.dewpoint),
+            ("windchill", row.windchill),
+            ("heatindex", row.heatindex),
+            ("radiation", row.radiation),
+            ("UV", row.uv),
+            ("rxCheckPercent", row.rx_check_percent),
+        ];
+        ArchiveRecord {
+            date_time: row.date_time,
+            interval: row.interval,
+            us_units: row.us_units,
+            aggregates: columns
+                .into_iter()
+                .filter_map(|(name, v)| Some((name.to_string(), ObservationValue::Float(v?))))
+                .collect(),
+        }
+    }
+}
+
 /// Metadata table for storing configuration
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
 pub struct MetadataRow {
@@ -85,29 +126,308 @@ pub struct MetadataRow {
     pub value: String,
 }
 
-/// Daily summary statistics (optional table)
-#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+/// One day of one observation in its `archive_day_<obs>` table. The wind
+/// table (`archive_day_wind`) has the extra vector columns.
+#[derive(Debug, Clone, Default, PartialEq, FromRow, Serialize, Deserialize)]
 pub struct DailySummaryRow {
+    /// Start of the day (local midnight, Unix epoch)
     #[sqlx(rename = "dateTime")]
     pub date_time: i64,
 
-    pub obs_type: String,
     pub min: Option<f64>,
+    pub mintime: Option<i64>,
     pub max: Option<f64>,
+    pub maxtime: Option<i64>,
     pub sum: Option<f64>,
-    pub count: i32,
+    pub count: Option<i64>,
+    /// Sum of value x seconds, and the seconds summed
+    pub wsum: Option<f64>,
+    pub sumtime: Option<i64>,
+
+    // Wind only
+    #[sqlx(default)]
+    pub max_dir: Option<f64>,
+    #[sqlx(default)]
+    pub xsum: Option<f64>,
+    #[sqlx(default)]
+    pub ysum: Option<f64>,
+    #[sqlx(default)]
+    pub dirsumtime: Option<i64>,
+    #[sqlx(default)]
+    pub squaresum: Option<f64>,
+    #[sqlx(default)]
+    pub wsquaresum: Option<f64>,
+}
+
+impl DailySummaryRow {
+    /// Fold one value observed at `date_time` in, weighted by `weight`
+    /// seconds
+    pub fn add_value(&mut self, value: f64, date_time: i64, weight: i64) {
+        self.add_hilo(value, date_time);
+        self.sum = Some(self.sum.unwrap_or(0.0) + value);
+        self.count = Some(self.count.unwrap_or(0) + 1);
+        self.wsum = Some(self.wsum.unwrap_or(0.0) + value * weight as f64);
+        self.sumtime = Some(self.sumtime.unwrap_or(0) + weight);
+    }
+
+    /// Update min/max; returns whether `value` is the new max
+    fn add_hilo(&mut self, value: f64, date_time: i64) -> bool {
+        if self.min.map_or(true, |min| value < min) {
+            self.min = Some(value);
+            self.mintime = Some(date_time);
+        }
+        if self.max.map_or(true, |max| value > max) {
+            self.max = Some(value);
+            self.maxtime = Some(date_time);
+            return true;
+        }
+        false
+    }
+
+    /// Fold a record's wind into a `wind` row: extremes over both speed and
+    /// gust, sums and vector sums over speed
+    pub fn add_wind(&mut self, record: &ArchiveRecord, weight: i64) {
+        let value = |name| record.aggregates.get(name).and_then(|v| v.as_f64());
+        let ts = record.date_time;
+        let wind_dir = value("windDir");
+        for (speed, dir) in [
+            (value("windSpeed"), wind_dir),
+            (value("windGust"), value("windGustDir")),
+        ] {
+            if let Some(speed) = speed {
+                if self.add_hilo(speed, ts) {
+                    self.max_dir = dir;
+                }
+            }
+        }
+
+        let Some(speed) = value("windSpeed") else {
+            return;
+        };
+        let w = weight as f64;
+        self.sum = Some(self.sum.unwrap_or(0.0) + speed);
+        self.count = Some(self.count.unwrap_or(0) + 1);
+        self.wsum = Some(self.wsum.unwrap_or(0.0) + speed * w);
+        self.sumtime = Some(self.sumtime.unwrap_or(0) + weight);
+        self.squaresum = Some(self.squaresum.unwrap_or(0.0) + speed * speed);
+        self.wsquaresum = Some(self.wsquaresum.unwrap_or(0.0) + speed * speed * w);
+        if let Some(dir) = wind_dir {
+            let rad = dir.to_radians();
+            self.xsum = Some(self.xsum.unwrap_or(0.0) + speed * rad.sin() * w);
+            self.ysum = Some(self.ysum.unwrap_or(0.0) + speed * rad.cos() * w);
+            self.dirsumtime = Some(self.dirsumtime.unwrap_or(0) + weight);
+        }
+    }
+
+    /// Combine with the row of a later stretch of time (e.g. the next day).
+    /// Ties on min or max keep the earlier time.
+    pub fn merge(&mut self, other: &DailySummaryRow) {
+        fn add<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            }
+        }
+        if let Some(min) = other.min.filter(|m| self.min.map_or(true, |min| *m < min)) {
+            self.min = Some(min);
+            self.mintime = other.mintime;
+        }
+        if let Some(max) = other.max.filter(|m| self.max.map_or(true, |max| *m > max)) {
+            self.max = Some(max);
+            self.maxtime = other.maxtime;
+            self.max_dir = other.max_dir;
+        }
+        self.sum = add(self.sum, other.sum);
+        self.count = add(self.count, other.count);
+        self.wsum = add(self.wsum, other.wsum);
+        self.sumtime = add(self.sumtime, other.sumtime);
+        self.xsum = add(self.xsum, other.xsum);
+        self.ysum = add(self.ysum, other.ysum);
+        self.dirsumtime = add(self.dirsumtime, other.dirsumtime);
+        self.squaresum = add(self.squaresum, other.squaresum);
+        self.wsquaresum = add(self.wsquaresum, other.wsquaresum);
+    }
 }
 
 /// Table names matching Python WeeWX schema
 pub mod tables {
     pub const ARCHIVE: &str = "archive";
     pub const METADATA: &str = "archive_metadata";
-    pub const DAILY_SUMMARY: &str = "archive_day_summary";
+    /// Daily summaries are kept in one table per observation
+    pub const DAILY_SUMMARY_PREFIX: &str = "archive_day_";
+    pub const DAILY_SUMMARY_METADATA: &str = "archive_day__metadata";
+    /// The combined windSpeed/windDir/windGust summary
+    pub const WIND_SUMMARY: &str = "wind";
+
+    /// Daily summary table for `obs`, e.g. `archive_day_outTemp`
+    pub fn daily_summary(obs: &str) -> String {
+        format!("{}{}", DAILY_SUMMARY_PREFIX, obs)
+    }
+}
+
+/// Columns of the `archive` table, as found in the database. Any schema
+/// works (`wview`, `wview_extended` or a custom one): observation columns are
+/// whatever the table has besides the key columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchiveColumns {
+    names: Vec<String>,
+    /// Lowercased name -> index; MySQL matches column names case-insensitively
+    index: HashMap<String, usize>,
+}
+
+impl ArchiveColumns {
+    /// Columns every archive table has, ahead of the observations
+    pub const KEY_COLUMNS: [&'static str; 3] = ["dateTime", "usUnits", "interval"];
+
+    pub fn new(names: Vec<String>) -> Self {
+        let index = names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.to_ascii_lowercase(), i))
+            .collect();
+        Self { names, index }
+    }
+
+    /// All columns, in table order
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// The table's spelling of column `name`, matched case-insensitively
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.index
+            .get(&name.to_ascii_lowercase())
+            .map(|i| self.names[*i].as_str())
+    }
+
+    /// Observation columns: everything but dateTime, usUnits and interval
+    pub fn observations(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .map(String::as_str)
+            .filter(|n| !Self::KEY_COLUMNS.iter().any(|k| k.eq_ignore_ascii_case(n)))
+    }
 }
 
 /// Expected database version (must match Python WeeWX)
 pub const EXPECTED_SCHEMA_VERSION: &str = "4.0";
 
+/// Observation columns of WeeWX's `wview_extended` archive schema, which
+/// `init` creates
+pub const WVIEW_EXTENDED_COLUMNS: &[&str] = &[
+    "altimeter",
+    "appTemp",
+    "appTemp1",
+    "barometer",
+    "batteryStatus1",
+    "batteryStatus2",
+    "batteryStatus3",
+    "batteryStatus4",
+    "batteryStatus5",
+    "batteryStatus6",
+    "batteryStatus7",
+    "batteryStatus8",
+    "cloudbase",
+    "co",
+    "co2",
+    "consBatteryVoltage",
+    "dewpoint",
+    "dewpoint1",
+    "ET",
+    "extraHumid1",
+    "extraHumid2",
+    "extraHumid3",
+    "extraHumid4",
+    "extraHumid5",
+    "extraHumid6",
+    "extraHumid7",
+    "extraHumid8",
+    "extraTemp1",
+    "extraTemp2",
+    "extraTemp3",
+    "extraTemp4",
+    "extraTemp5",
+    "extraTemp6",
+    "extraTemp7",
+    "extraTemp8",
+    "forecast",
+    "hail",
+    "hailBatteryStatus",
+    "hailRate",
+    "heatindex",
+    "heatindex1",
+    "heatingTemp",
+    "heatingVoltage",
+    "humidex",
+    "humidex1",
+    "inDewpoint",
+    "inHumidity",
+    "inTemp",
+    "inTempBatteryStatus",
+    "leafTemp1",
+    "leafTemp2",
+    "leafWet1",
+    "leafWet2",
+    "lightning_distance",
+    "lightning_disturber_count",
+    "lightning_energy",
+    "lightning_noise_count",
+    "lightning_strike_count",
+    "luminosity",
+    "maxSolarRad",
+    "nh3",
+    "no2",
+    "noise",
+    "o3",
+    "outHumidity",
+    "outTemp",
+    "outTempBatteryStatus",
+    "pb",
+    "pm10_0",
+    "pm1_0",
+    "pm2_5",
+    "pressure",
+    "radiation",
+    "rain",
+    "rainBatteryStatus",
+    "rainRate",
+    "referenceVoltage",
+    "rxCheckPercent",
+    "signal1",
+    "signal2",
+    "signal3",
+    "signal4",
+    "signal5",
+    "signal6",
+    "signal7",
+    "signal8",
+    "snow",
+    "snowBatteryStatus",
+    "snowDepth",
+    "snowMoisture",
+    "snowRate",
+    "so2",
+    "soilMoist1",
+    "soilMoist2",
+    "soilMoist3",
+    "soilMoist4",
+    "soilTemp1",
+    "soilTemp2",
+    "soilTemp3",
+    "soilTemp4",
+    "supplyVoltage",
+    "txBatteryStatus",
+    "UV",
+    "uvBatteryStatus",
+    "windBatteryStatus",
+    "windchill",
+    "windDir",
+    "windGust",
+    "windGustDir",
+    "windrun",
+    "windSpeed",
+];
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -125,5 +445,29 @@ mod tests {
     fn test_table_names() {
         assert_eq!(tables::ARCHIVE, "archive");
         assert_eq!(tables::METADATA, "archive_metadata");
+        assert_eq!(tables::daily_summary("outTemp"), "archive_day_outTemp");
+    }
+
+    #[test]
+    fn test_archive_columns() {
+        let columns = ArchiveColumns::new(
+            [
+                "dateTime",
+                "usUnits",
+                "interval",
+                "outTemp",
+                "soilMoist1",
+                "pm2_5",
+            ]
+            .map(String::from)
+            .to_vec(),
+        );
+        assert_eq!(columns.get("OUTTEMP"), Some("outTemp"));
+        assert_eq!(columns.get("pm2_5"), Some("pm2_5"));
+        assert_eq!(columns.get("pm10_0"), None);
+        assert_eq!(
+            columns.observations().collect::<Vec<_>>(),
+            ["outTemp", "soilMoist1", "pm2_5"]
+        );
     }
 }
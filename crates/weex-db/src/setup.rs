@@ -0,0 +1,229 @@
+//! Schema creation and startup validation
+//!
+//! An existing WeeWX database is used as-is: at startup its `archive` table
+//! and `archive_metadata` are checked against the configuration, and a
+//! database in another unit system is refused. An empty database can be
+//! given a WeeWX-compatible `wview_extended` schema with
+//! [`DbClient::init_schema`].
+
+use crate::schema::{tables, ArchiveColumns, EXPECTED_SCHEMA_VERSION, WVIEW_EXTENDED_COLUMNS};
+use crate::{DbClient, DbError, DbResult};
+use std::sync::Arc;
+use tracing::{info, instrument, warn};
+
+/// `archive_metadata` key of the database's unit system
+pub const UNIT_SYSTEM_KEY: &str = "unit_system";
+/// `archive_metadata` key of the schema version
+pub const VERSION_KEY: &str = "version";
+
+/// What the startup check found
+#[derive(Debug, Clone)]
+pub struct SchemaInfo {
+    pub unit_system: i32,
+    pub version: Option<String>,
+    pub columns: Arc<ArchiveColumns>,
+}
+
+impl DbClient {
+    /// Whether `table` exists
+    pub async fn table_exists(&self, table: &str) -> DbResult<bool> {
+        let row = sqlx::query(&self.backend().table_exists_sql())
+            .bind(table)
+            .fetch_optional(self.pool())
+            .await?;
+        Ok(row.is_some())
+    }
+
+    /// Check the database before writing to it: the `archive` table and its
+    /// key columns must exist, and the unit system recorded in
+    /// `archive_metadata` (or, failing that, of the latest record) must be
+    /// `unit_system`. A database without one is stamped with it, as WeeWX
+    /// does on its first record.
+    #[instrument(skip(self))]
+    pub async fn check_schema(&self, unit_system: i32) -> DbResult<SchemaInfo> {
+        for table in [tables::ARCHIVE, tables::METADATA] {
+            if !self.table_exists(table).await? {
+                return Err(DbError::SchemaError(format!(
+                    "no `{}` table; run `init` to create the WeeWX schema",
+                    table
+                )));
+            }
+        }
+        let columns = self.load_archive_columns().await?;
+        if let Some(missing) = ArchiveColumns::KEY_COLUMNS
+            .iter()
+            .find(|c| columns.get(c).is_none())
+        {
+            return Err(DbError::SchemaError(format!(
+                "`{}` table has no `{}` column",
+                tables::ARCHIVE,
+                missing
+            )));
+        }
+
+        let recorded = match self.get_metadata(UNIT_SYSTEM_KEY).await? {
+            Some(value) => Some(value.trim().parse::<i32>().map_err(|_| {
+                DbError::SchemaError(format!("invalid {} metadata: {:?}", UNIT_SYSTEM_KEY, value))
+            })?),
+            None => self.get_latest_archive().await?.map(|r| r.us_units),
+        };
+        match recorded {
+            Some(database) if database != unit_system => {
+                return Err(DbError::UnitSystemMismatch {
+                    database,
+                    configured: unit_system,
+                });
+            }
+            Some(_) => {}
+            None => {
+                self.set_metadata(UNIT_SYSTEM_KEY, &unit_system.to_string())
+                    .await?
+            }
+        }
+
+        let version = self.get_metadata(VERSION_KEY).await?;
+        if let Some(version) = version.as_deref().filter(|v| *v != EXPECTED_SCHEMA_VERSION) {
+            warn!(
+                "database schema version {} (expected {})",
+                version, EXPECTED_SCHEMA_VERSION
+            );
+        }
+        info!(
+            unit_system,
+            columns = columns.names().len(),
+            "database schema verified"
+        );
+        Ok(SchemaInfo {
+            unit_system,
+            version,
+            columns,
+        })
+    }
+
+    /// Create a WeeWX-compatible schema (`wview_extended` archive table,
+    /// `archive_metadata` and the daily summary tables) in `unit_system`.
+    /// A database that already has an `archive` table is left alone; returns
+    /// whether the schema was created.
+    #[instrument(skip(self))]
+    pub async fn init_schema(&self, unit_system: i32) -> DbResult<bool> {
+        if self.table_exists(tables::ARCHIVE).await? {
+            info!("database already has an archive table; not initializing");
+            return Ok(false);
+        }
+
+        let backend = self.backend();
+        let real = backend.real_type();
+        let mut columns = vec![
+            format!(
+                "{} INTEGER NOT NULL UNIQUE PRIMARY KEY",
+                backend.quote("dateTime")
+            ),
+            format!("{} INTEGER NOT NULL", backend.quote("usUnits")),
+            format!("{} INTEGER NOT NULL", backend.quote("interval")),
+        ];
+        columns.extend(
+            WVIEW_EXTENDED_COLUMNS
+                .iter()
+                .map(|c| format!("{} {}", backend.quote(c), real)),
+        );
+        sqlx::query(&format!(
+            "CREATE TABLE {} ({})",
+            tables::ARCHIVE,
+            columns.join(", ")
+        ))
+        .execute(self.pool())
+        .await?;
+        sqlx::query(&format!(
+            "CREATE TABLE IF NOT EXISTS {} (\
+             name CHAR(20) NOT NULL UNIQUE PRIMARY KEY, value TEXT)",
+            tables::METADATA
+        ))
+        .execute(self.pool())
+        .await?;
+        self.set_metadata(UNIT_SYSTEM_KEY, &unit_system.to_string())
+            .await?;
+        self.set_metadata(VERSION_KEY, EXPECTED_SCHEMA_VERSION)
+            .await?;
+
+        for obs in WVIEW_EXTENDED_COLUMNS.iter().chain([&tables::WIND_SUMMARY]) {
+            self.create_daily_summary(obs).await?;
+        }
+
+        self.load_archive_columns().await?;
+        info!(
+            unit_system,
+            backend = backend.name(),
+            "created WeeWX schema (wview_extended)"
+        );
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_init_and_check_schema() {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        assert!(matches!(
+            db.check_schema(16).await,
+            Err(DbError::SchemaError(_))
+        ));
+
+        assert!(db.init_schema(16).await.unwrap());
+        assert!(!db.init_schema(16).await.unwrap());
+        assert!(db.table_exists("archive_day_soilMoist1").await.unwrap());
+        assert!(db.table_exists("archive_day_wind").await.unwrap());
+
+        let info = db.check_schema(16).await.unwrap();
+        assert_eq!(info.version.as_deref(), Some(EXPECTED_SCHEMA_VERSION));
+        assert_eq!(info.columns.get("pm2_5"), Some("pm2_5"));
+        assert!(matches!(
+            db.check_schema(1).await,
+            Err(DbError::UnitSystemMismatch {
+                database: 16,
+                configured: 1
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_init_creates_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("sqlite://{}", dir.path().join("weewx.sdb").display());
+        assert!(DbClient::new(&url).await.is_err());
+
+        let db = DbClient::create(&url).await.unwrap();
+        assert!(db.init_schema(16).await.unwrap());
+        db.pool().close().await;
+
+        let db = DbClient::new(&url).await.unwrap();
+        db.check_schema(16).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_stamps_unit_system() {
+        let db = DbClient::new("sqlite::memory:").await.unwrap();
+        for sql in [
+            "CREATE TABLE archive (dateTime INTEGER NOT NULL UNIQUE PRIMARY KEY, \
+             usUnits INTEGER NOT NULL, interval INTEGER NOT NULL, outTemp REAL)",
+            "CREATE TABLE archive_metadata (name CHAR(20) PRIMARY KEY, value TEXT)",
+            "INSERT INTO archive VALUES (300, 1, 300, 68.0)",
+        ] {
+            sqlx::query(sql).execute(db.pool()).await.unwrap();
+        }
+
+        // The latest record's unit system stands in for missing metadata
+        assert!(db.check_schema(17).await.is_err());
+        sqlx::query("DELETE FROM archive")
+            .execute(db.pool())
+            .await
+            .unwrap();
+        db.check_schema(17).await.unwrap();
+        assert_eq!(
+            db.get_metadata(UNIT_SYSTEM_KEY).await.unwrap().as_deref(),
+            Some("17")
+        );
+    }
+}
@@ -25,6 +25,11 @@ impl InterceptorUdpDriver {
         }
     }
 
+    /// Address the socket is bound to, once started
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.as_ref().and_then(|s| s.local_addr().ok())
+    }
+
     fn socket_ref(&self) -> Result<&UdpSocket, IngestError> {
         self.socket
             .as_ref()
@@ -7,10 +7,12 @@
 pub mod driver;
 pub mod interceptor;
 pub mod simulator;
+pub mod source;
 
 pub use driver::*;
 pub use interceptor::*;
 pub use simulator::*;
+pub use source::*;
 
 use thiserror::Error;
 use tokio::sync::mpsc;
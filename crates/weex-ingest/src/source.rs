@@ -0,0 +1,53 @@
+//! Adapter running a station driver as a pipeline `Source`
+
+use anyhow::Result;
+use weex_core::{obs_registry, Source, WeatherPacket};
+
+use crate::StationDriver;
+
+/// Pipeline source backed by a started `StationDriver`; the driver is stopped
+/// when the pipeline closes the source. Aliased observation names are mapped
+/// to their registry names on the way in.
+pub struct DriverSource {
+    driver: Box<dyn StationDriver>,
+}
+
+impl DriverSource {
+    pub fn new(driver: Box<dyn StationDriver>) -> Self {
+        Self { driver }
+    }
+
+    pub fn name(&self) -> &str {
+        self.driver.name()
+    }
+}
+
+#[async_trait::async_trait]
+impl Source for DriverSource {
+    async fn next_packet(&mut self) -> Result<WeatherPacket> {
+        let mut packet = self.driver.get_packet().await?;
+        obs_registry().normalize(&mut packet);
+        Ok(packet)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        Ok(self.driver.stop().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SimulatorDriver;
+
+    #[tokio::test]
+    async fn reads_and_stops_driver() {
+        let mut driver = SimulatorDriver::new(0);
+        driver.start().await.unwrap();
+        let mut source = DriverSource::new(Box::new(driver));
+        assert_eq!(source.name(), "simulator");
+        let packet = source.next_packet().await.unwrap();
+        assert!(!packet.observations.is_empty());
+        source.close().await.unwrap();
+    }
+}